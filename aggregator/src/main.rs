@@ -0,0 +1,34 @@
+//! Verifies proofs of consecutive Santa segments and commits the combined fee totals of the
+//! entire range as ABI encoded `SantaAggregatePublicValues`.
+
+// These two lines are necessary for the program to properly compile.
+//
+// Under the hood, we wrap your main function with some extra code so that it behaves properly
+// inside the zkVM.
+#![no_main]
+sp1_zkvm::entrypoint!(main);
+
+use alloy_sol_types::SolValue;
+use santa_lib::{aggregation::aggregate_proven_segments, public_values::SantaPublicValues};
+use sha2::{Digest, Sha256};
+
+pub fn main() {
+    let segment_vkey: [u32; 8] = sp1_zkvm::io::read();
+    let segment_public_values: Vec<Vec<u8>> = sp1_zkvm::io::read();
+
+    let segments: Vec<SantaPublicValues> = segment_public_values
+        .iter()
+        .map(|public_values| {
+            let public_values_digest = Sha256::digest(public_values);
+            sp1_zkvm::lib::verify::verify_sp1_proof(&segment_vkey, &public_values_digest.into());
+
+            SantaPublicValues::abi_decode(public_values, true)
+                .expect("Invalid segment public values")
+        })
+        .collect();
+
+    let aggregate = aggregate_proven_segments(&segment_vkey, &segments)
+        .unwrap_or_else(|err| panic!("Invalid segments: {}", err));
+
+    sp1_zkvm::io::commit_slice(&aggregate.abi_encode());
+}
@@ -0,0 +1,296 @@
+use crate::public_values::{AssetFees, SantaAggregatePublicValues, SantaPublicValues};
+use alloy_primitives::{Address, B256, U256};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AggregationError {
+    NoSegments,
+    AngstromMismatch {
+        segment: usize,
+        expected: Address,
+        found: Address,
+    },
+    /// Segment doesn't build on the last block of the previous segment.
+    BrokenChainLink {
+        segment: usize,
+        expected: B256,
+        found: B256,
+    },
+    BlockNumberGap {
+        segment: usize,
+        expected: u64,
+        found: u64,
+    },
+    /// Previous segment ends at the highest possible block number, so no segment can follow it.
+    BlockNumberOverflow {
+        segment: usize,
+    },
+    /// Reward blocks of the segments up to `segment` don't fit into the reward block count.
+    RewardBlockCountOverflow {
+        segment: usize,
+    },
+    /// Segment other than the last proves Angstrom storage, which only describes the end of the
+    /// range at the last segment.
+    EarlyAngstromStorage {
+        segment: usize,
+    },
+}
+
+impl std::fmt::Display for AggregationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSegments => write!(f, "no segments to aggregate"),
+            Self::AngstromMismatch {
+                segment,
+                expected,
+                found,
+            } => write!(
+                f,
+                "segment #{}: proven for angstrom {} instead of {}",
+                segment, found, expected
+            ),
+            Self::BrokenChainLink {
+                segment,
+                expected,
+                found,
+            } => write!(
+                f,
+                "segment #{}: chain parent {} is not previous chain end {}",
+                segment, found, expected
+            ),
+            Self::BlockNumberGap {
+                segment,
+                expected,
+                found,
+            } => write!(
+                f,
+                "segment #{}: starts at block {} instead of {}",
+                segment, found, expected
+            ),
+            Self::BlockNumberOverflow { segment } => write!(
+                f,
+                "segment #{}: previous segment ends at the last block number",
+                segment
+            ),
+            Self::RewardBlockCountOverflow { segment } => {
+                write!(f, "segment #{}: reward block count overflows", segment)
+            }
+            Self::EarlyAngstromStorage { segment } => write!(
+                f,
+                "segment #{}: proves angstrom storage but isn't the last segment",
+                segment
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AggregationError {}
+
+/// Converts an SP1 verifying key digest as used by `verify_sp1_proof` into bytes.
+pub fn vkey_digest_to_bytes(digest: &[u32; 8]) -> B256 {
+    let mut bytes = [0u8; 32];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(digest) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    B256::from(bytes)
+}
+
+/// Combines the public values of consecutive segments into the public values of the entire range,
+/// summing fees per asset. Storage values describe the state at the end of the range, so only the
+/// last segment may prove them.
+pub fn aggregate_segments(
+    segments: &[SantaPublicValues],
+) -> Result<SantaPublicValues, AggregationError> {
+    let (first, rest) = segments.split_first().ok_or(AggregationError::NoSegments)?;
+    if let Some(segment) = segments[..segments.len() - 1]
+        .iter()
+        .position(|values| !values.angstrom_storage.is_empty())
+    {
+        return Err(AggregationError::EarlyAngstromStorage { segment });
+    }
+
+    let mut sums: BTreeMap<Address, U256> = BTreeMap::new();
+    let mut add_fees = |fees: &[AssetFees]| {
+        for fee in fees {
+            *sums.entry(fee.asset).or_default() += fee.total;
+        }
+    };
+    add_fees(&first.fees);
+
+    let mut reward_block_count = first.reward_block_count;
+    let mut last = first;
+    for (segment, values) in rest.iter().enumerate().map(|(i, v)| (i + 1, v)) {
+        if values.angstrom != first.angstrom {
+            return Err(AggregationError::AngstromMismatch {
+                segment,
+                expected: first.angstrom,
+                found: values.angstrom,
+            });
+        }
+        if values.chain_parent != last.chain_last {
+            return Err(AggregationError::BrokenChainLink {
+                segment,
+                expected: last.chain_last,
+                found: values.chain_parent,
+            });
+        }
+        let expected_number = last
+            .last_block_number
+            .checked_add(1)
+            .ok_or(AggregationError::BlockNumberOverflow { segment })?;
+        if values.first_block_number != expected_number {
+            return Err(AggregationError::BlockNumberGap {
+                segment,
+                expected: expected_number,
+                found: values.first_block_number,
+            });
+        }
+
+        add_fees(&values.fees);
+        reward_block_count = reward_block_count
+            .checked_add(values.reward_block_count)
+            .ok_or(AggregationError::RewardBlockCountOverflow { segment })?;
+        last = values;
+    }
+
+    Ok(SantaPublicValues {
+        angstrom: first.angstrom,
+        chain_parent: first.chain_parent,
+        chain_last: last.chain_last,
+        first_block_number: first.first_block_number,
+        last_block_number: last.last_block_number,
+        first_block_timestamp: first.first_block_timestamp,
+        last_block_timestamp: last.last_block_timestamp,
+        reward_block_count,
+        fees: sums
+            .into_iter()
+            .map(|(asset, total)| AssetFees { asset, total })
+            .collect(),
+        angstrom_storage: last.angstrom_storage.clone(),
+    })
+}
+
+/// Aggregates segments proven by the program with verifying key digest `segment_vkey`.
+pub fn aggregate_proven_segments(
+    segment_vkey: &[u32; 8],
+    segments: &[SantaPublicValues],
+) -> Result<SantaAggregatePublicValues, AggregationError> {
+    Ok(SantaAggregatePublicValues {
+        segment_vkey: vkey_digest_to_bytes(segment_vkey),
+        combined: aggregate_segments(segments)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::public_values::StorageSlot;
+
+    fn segment(first: u64, last: u64, fees: &[(u8, u64)]) -> SantaPublicValues {
+        SantaPublicValues {
+            angstrom: Address::repeat_byte(0x01),
+            chain_parent: B256::with_last_byte(first as u8 - 1),
+            chain_last: B256::with_last_byte(last as u8),
+            first_block_number: first,
+            last_block_number: last,
+            first_block_timestamp: first * 12,
+            last_block_timestamp: last * 12,
+            reward_block_count: fees.len() as u32,
+            fees: fees
+                .iter()
+                .map(|&(asset, total)| AssetFees {
+                    asset: Address::repeat_byte(asset),
+                    total: U256::from(total),
+                })
+                .collect(),
+            angstrom_storage: Vec::new(),
+        }
+    }
+
+    fn storage(value: u64) -> Vec<StorageSlot> {
+        vec![StorageSlot {
+            slot: B256::ZERO,
+            value: U256::from(value),
+        }]
+    }
+
+    #[test]
+    fn combines_consecutive_segments() {
+        let mut segments = [
+            segment(10, 19, &[(0xaa, 1), (0xcc, 2)]),
+            segment(20, 29, &[]),
+            segment(30, 39, &[(0xbb, 5), (0xcc, 10)]),
+        ];
+        segments[2].angstrom_storage = storage(39);
+
+        let combined = aggregate_segments(&segments).unwrap();
+        assert_eq!(combined.chain_parent, segments[0].chain_parent);
+        assert_eq!(combined.chain_last, segments[2].chain_last);
+        assert_eq!(combined.first_block_number, 10);
+        assert_eq!(combined.last_block_number, 39);
+        assert_eq!(combined.first_block_timestamp, 120);
+        assert_eq!(combined.last_block_timestamp, 468);
+        assert_eq!(combined.reward_block_count, 4);
+        assert_eq!(combined.angstrom_storage, segments[2].angstrom_storage);
+        assert_eq!(
+            combined.fees,
+            segment(0x10, 0x10, &[(0xaa, 1), (0xbb, 5), (0xcc, 12)]).fees
+        );
+    }
+
+    #[test]
+    fn rejects_unlinked_segments() {
+        assert_eq!(aggregate_segments(&[]), Err(AggregationError::NoSegments));
+
+        let mut segments = [segment(10, 19, &[]), segment(20, 29, &[])];
+        segments[1].chain_parent = B256::ZERO;
+        assert_eq!(
+            aggregate_segments(&segments),
+            Err(AggregationError::BrokenChainLink {
+                segment: 1,
+                expected: segments[0].chain_last,
+                found: B256::ZERO
+            })
+        );
+
+        let mut segments = [segment(10, 19, &[]), segment(20, 29, &[])];
+        segments[1].angstrom = Address::ZERO;
+        assert!(matches!(
+            aggregate_segments(&segments),
+            Err(AggregationError::AngstromMismatch { segment: 1, .. })
+        ));
+
+        let mut segments = [segment(10, 19, &[]), segment(20, 29, &[])];
+        segments[0].angstrom_storage = storage(19);
+        segments[1].angstrom_storage = storage(29);
+        assert_eq!(
+            aggregate_segments(&segments),
+            Err(AggregationError::EarlyAngstromStorage { segment: 0 })
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_segments() {
+        let mut segments = [segment(10, 19, &[]), segment(20, 29, &[])];
+        segments[0].last_block_number = u64::MAX;
+        assert_eq!(
+            aggregate_segments(&segments),
+            Err(AggregationError::BlockNumberOverflow { segment: 1 })
+        );
+
+        let mut segments = [segment(10, 19, &[(0xaa, 1)]), segment(20, 29, &[])];
+        segments[1].reward_block_count = u32::MAX;
+        assert_eq!(
+            aggregate_segments(&segments),
+            Err(AggregationError::RewardBlockCountOverflow { segment: 1 })
+        );
+    }
+
+    #[test]
+    fn vkey_digest_bytes_are_big_endian_words() {
+        let digest = [0x01020304, 0, 0, 0, 0, 0, 0, 0xa0b0c0d0];
+        let bytes = vkey_digest_to_bytes(&digest);
+        assert_eq!(bytes[..4], [1, 2, 3, 4]);
+        assert_eq!(bytes[28..], [0xa0, 0xb0, 0xc0, 0xd0]);
+    }
+}
@@ -0,0 +1,89 @@
+//! Helpers to determine whether a proven block hash can still be checked on-chain.
+
+/// Number of most recent block hashes available via the `BLOCKHASH` opcode.
+pub const BLOCKHASH_WINDOW: u64 = 256;
+/// Number of most recent block hashes served by the EIP-2935 history storage contract.
+pub const HISTORY_SERVE_WINDOW: u64 = 8191;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnchorStatus {
+    pub block_number: u64,
+    /// Number of upcoming blocks that can still read the hash via `BLOCKHASH`, `None` if it's
+    /// already outside the window or the block is not yet part of the chain.
+    pub blockhash_blocks_left: Option<u64>,
+    /// Number of upcoming blocks that can still read the hash from the EIP-2935 contract.
+    pub history_blocks_left: Option<u64>,
+}
+
+impl AnchorStatus {
+    /// Determines for how long the hash of `block_number` can still be read on-chain given the
+    /// current chain `head`.
+    pub fn new(block_number: u64, head: u64) -> Self {
+        Self {
+            block_number,
+            blockhash_blocks_left: blocks_left(block_number, head, BLOCKHASH_WINDOW),
+            history_blocks_left: blocks_left(block_number, head, HISTORY_SERVE_WINDOW),
+        }
+    }
+
+    pub fn anchorable(&self) -> bool {
+        self.history_blocks_left.is_some()
+    }
+}
+
+/// A block at height `h` can access the hashes of blocks `h - window..h`, so the last block able to
+/// read the hash of `block_number` is `block_number + window`, capped at the largest block number.
+fn blocks_left(block_number: u64, head: u64, window: u64) -> Option<u64> {
+    if block_number > head {
+        return None;
+    }
+    block_number
+        .saturating_add(window)
+        .checked_sub(head)
+        .filter(|&left| left > 0)
+}
+
+impl std::fmt::Display for AnchorStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "block #{}: ", self.block_number)?;
+        match self.blockhash_blocks_left {
+            Some(left) => write!(f, "BLOCKHASH for {} more blocks, ", left)?,
+            None => write!(f, "outside BLOCKHASH window, ")?,
+        }
+        match self.history_blocks_left {
+            Some(left) => write!(f, "EIP-2935 history for {} more blocks", left),
+            None => write!(f, "outside EIP-2935 history window"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_boundaries() {
+        let status = AnchorStatus::new(1000, 1000);
+        assert_eq!(status.blockhash_blocks_left, Some(256));
+        assert_eq!(status.history_blocks_left, Some(8191));
+
+        let status = AnchorStatus::new(1000, 1255);
+        assert_eq!(status.blockhash_blocks_left, Some(1));
+
+        let status = AnchorStatus::new(1000, 1256);
+        assert_eq!(status.blockhash_blocks_left, None);
+        assert_eq!(status.history_blocks_left, Some(7935));
+
+        let status = AnchorStatus::new(1000, 1000 + HISTORY_SERVE_WINDOW);
+        assert_eq!(status.history_blocks_left, None);
+        assert!(!status.anchorable());
+
+        let status = AnchorStatus::new(1001, 1000);
+        assert_eq!(status.blockhash_blocks_left, None);
+        assert!(!status.anchorable());
+
+        let status = AnchorStatus::new(u64::MAX - 1, u64::MAX - 1);
+        assert_eq!(status.blockhash_blocks_left, Some(1));
+        assert_eq!(status.history_blocks_left, Some(1));
+    }
+}
@@ -0,0 +1,434 @@
+//! Store of append-only segment files.
+//!
+//! The store is a directory of segments, each holding the data of [`SEGMENT_BLOCKS`] consecutive
+//! blocks in a file named after its first block number. A segment file is an append-only log of
+//! records, each made up of a kind byte, the little-endian `u64` block number, the little-endian
+//! `u32` payload length and the payload:
+//!
+//! - block: the RLP encoded header followed by the 32-byte hashes of the block's transactions
+//! - receipts / transactions: EIP-2718 encoded items appended to the block's list
+//! - evictions: empty, drop the block with its receipts and transactions or only the latter
+//! - confirmations: empty, mark the block as buried deep enough to not be reorged anymore
+//!
+//! Segments are only read once a block in their range is accessed and saving only appends the
+//! records added since the last save.
+
+use super::{decode_2718_list, BlockStore, MemoryStore, SmolBlock};
+use alloy_consensus::{Header, ReceiptEnvelope, TxEnvelope};
+use alloy_eips::eip2718::Encodable2718;
+use alloy_primitives::BlockNumber;
+
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Number of consecutive blocks stored in one segment file.
+pub const SEGMENT_BLOCKS: u64 = 10_000;
+
+const SEGMENT_EXTENSION: &str = "seg";
+const RECORD_HEADER_SIZE: usize = 1 + 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum RecordKind {
+    Block = 0,
+    Receipts = 1,
+    Transactions = 2,
+    EvictBlock = 3,
+    EvictReceipts = 4,
+    EvictTransactions = 5,
+    Confirm = 6,
+}
+
+impl RecordKind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Block),
+            1 => Some(Self::Receipts),
+            2 => Some(Self::Transactions),
+            3 => Some(Self::EvictBlock),
+            4 => Some(Self::EvictReceipts),
+            5 => Some(Self::EvictTransactions),
+            6 => Some(Self::Confirm),
+            _ => None,
+        }
+    }
+}
+
+/// Replays the records of the segment file at `path`. A partially written record at the end, as
+/// left behind by an interrupted save, is cut off.
+fn load_segment(path: &Path) -> MemoryStore {
+    let mut data = MemoryStore::default();
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return data,
+        Err(err) => panic!("Failed to read segment {:?}: {:?}", path, err),
+    };
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let Some((kind, bn, payload)) = read_record(&bytes[offset..]) else {
+            warn!(
+                "Truncating partial record at offset {} of segment {:?}",
+                offset, path
+            );
+            fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(offset as u64))
+                .unwrap_or_else(|err| panic!("Failed to truncate {:?}: {:?}", path, err));
+            break;
+        };
+        let corrupt = |err: &dyn std::fmt::Display| -> ! {
+            panic!(
+                "Corrupt record for block #{} at offset {} of segment {:?}: {}",
+                bn, offset, path, err
+            )
+        };
+        match RecordKind::from_byte(kind) {
+            Some(RecordKind::Block) => {
+                let block = SmolBlock::decode(payload).unwrap_or_else(|err| corrupt(&err));
+                data.append_blocks(vec![block]);
+            }
+            Some(RecordKind::Receipts) => {
+                let receipts = decode_2718_list(payload).unwrap_or_else(|err| corrupt(&err));
+                data.append_receipts(bn, receipts);
+            }
+            Some(RecordKind::Transactions) => {
+                let txs = decode_2718_list(payload).unwrap_or_else(|err| corrupt(&err));
+                data.append_transactions(bn, txs);
+            }
+            Some(RecordKind::EvictBlock) => data.evict_block(bn),
+            Some(RecordKind::EvictReceipts) => data.evict_receipts(bn),
+            Some(RecordKind::EvictTransactions) => data.evict_transactions(bn),
+            Some(RecordKind::Confirm) => data.confirm(bn),
+            None => corrupt(&format!("unknown record kind {}", kind)),
+        }
+        offset += RECORD_HEADER_SIZE + payload.len();
+    }
+
+    data
+}
+
+/// Splits off the record at the start of `bytes`, `None` if it's incomplete.
+fn read_record(bytes: &[u8]) -> Option<(u8, BlockNumber, &[u8])> {
+    let (header, rest) = bytes.split_at_checked(RECORD_HEADER_SIZE)?;
+    let bn = u64::from_le_bytes(header[1..9].try_into().unwrap());
+    let length = u32::from_le_bytes(header[9..].try_into().unwrap()) as usize;
+    Some((header[0], bn, rest.get(..length)?))
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    data: OnceCell<MemoryStore>,
+    /// Records appended since the last save.
+    pending: Vec<u8>,
+}
+
+impl Segment {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            data: OnceCell::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn data(&self) -> &MemoryStore {
+        self.data.get_or_init(|| load_segment(&self.path))
+    }
+
+    fn data_mut(&mut self) -> &mut MemoryStore {
+        self.data();
+        self.data.get_mut().unwrap()
+    }
+
+    fn push_record(
+        &mut self,
+        kind: RecordKind,
+        bn: BlockNumber,
+        encode: impl FnOnce(&mut Vec<u8>),
+    ) {
+        let start = self.pending.len();
+        self.pending.extend_from_slice(&[0; RECORD_HEADER_SIZE]);
+        encode(&mut self.pending);
+        let length = (self.pending.len() - start - RECORD_HEADER_SIZE) as u32;
+
+        let header = &mut self.pending[start..start + RECORD_HEADER_SIZE];
+        header[0] = kind as u8;
+        header[1..9].copy_from_slice(&bn.to_le_bytes());
+        header[9..].copy_from_slice(&length.to_le_bytes());
+    }
+
+    fn save(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&self.pending))
+            .unwrap_or_else(|err| panic!("Writing segment {:?} failed: {:?}", self.path, err));
+        self.pending.clear();
+    }
+}
+
+/// Contents of the single JSON file the cache used to be stored in.
+#[derive(Deserialize)]
+struct LegacyStore {
+    blocks: Vec<SmolBlock>,
+    receipts: HashMap<BlockNumber, Vec<ReceiptEnvelope>>,
+    #[serde(default)]
+    transactions: HashMap<BlockNumber, Vec<TxEnvelope>>,
+}
+
+#[derive(Debug)]
+pub struct FileStore<P: AsRef<Path>> {
+    dir: P,
+    /// Segments by their first block number.
+    segments: BTreeMap<BlockNumber, Segment>,
+}
+
+impl<P: AsRef<Path>> FileStore<P> {
+    /// Opens the store in directory `dir`. If the directory doesn't exist yet but a JSON store of the
+    /// same name does (`dir` with a `.json` extension), its contents are imported.
+    pub fn new(dir: P) -> Self {
+        let dir_ref = dir.as_ref();
+        let legacy_path = dir_ref.with_extension("json");
+        let import_legacy = !fs::exists(dir_ref).unwrap() && fs::exists(&legacy_path).unwrap();
+        fs::create_dir_all(dir_ref)
+            .unwrap_or_else(|err| panic!("Failed to create cache dir {:?}: {:?}", dir_ref, err));
+
+        let segments = fs::read_dir(dir_ref)
+            .unwrap_or_else(|err| panic!("Failed to list cache dir {:?}: {:?}", dir_ref, err))
+            .filter_map(|entry| {
+                let path = entry.expect("Failed to read dir entry").path();
+                if path.extension()? != SEGMENT_EXTENSION {
+                    return None;
+                }
+                let start = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((start, Segment::new(path)))
+            })
+            .collect();
+        let mut store = Self { dir, segments };
+
+        if import_legacy {
+            info!("Importing legacy cache {:?}", legacy_path);
+            let json = fs::read_to_string(&legacy_path)
+                .unwrap_or_else(|err| panic!("Failed to load file: {:?}", err));
+            let legacy: LegacyStore = serde_json::from_str(&json).expect("Failed to parse json");
+            store.append_blocks(legacy.blocks);
+            for (bn, receipts) in legacy.receipts {
+                store.append_receipts(bn, receipts);
+            }
+            for (bn, transactions) in legacy.transactions {
+                store.append_transactions(bn, transactions);
+            }
+            store.save();
+        }
+
+        store
+    }
+
+    fn segment_start(bn: BlockNumber) -> BlockNumber {
+        bn - bn % SEGMENT_BLOCKS
+    }
+
+    fn segment(&self, bn: BlockNumber) -> Option<&MemoryStore> {
+        self.segments
+            .get(&Self::segment_start(bn))
+            .map(Segment::data)
+    }
+
+    fn segment_mut(&mut self, bn: BlockNumber) -> &mut Segment {
+        let start = Self::segment_start(bn);
+        let dir = self.dir.as_ref();
+        self.segments.entry(start).or_insert_with(|| {
+            Segment::new(dir.join(format!("{:012}.{}", start, SEGMENT_EXTENSION)))
+        })
+    }
+
+    /// Logs an empty record of `kind` for block `bn` and applies it to the segment's data.
+    fn push_marker(
+        &mut self,
+        kind: RecordKind,
+        bn: BlockNumber,
+        apply: impl FnOnce(&mut MemoryStore, BlockNumber),
+    ) {
+        let segment = self.segment_mut(bn);
+        segment.push_record(kind, bn, |_| {});
+        apply(segment.data_mut(), bn);
+    }
+}
+
+impl<P: AsRef<Path>> BlockStore for FileStore<P> {
+    fn get_block(&self, bn: BlockNumber) -> Option<Cow<'_, SmolBlock>> {
+        self.segment(bn)?.get_block(bn)
+    }
+
+    fn get_receipts(&self, bn: BlockNumber) -> Option<Cow<'_, [ReceiptEnvelope]>> {
+        self.segment(bn)?.get_receipts(bn)
+    }
+
+    fn get_transactions(&self, bn: BlockNumber) -> Option<Cow<'_, [TxEnvelope]>> {
+        self.segment(bn)?.get_transactions(bn)
+    }
+
+    fn block_numbers(&self, from: BlockNumber) -> Vec<BlockNumber> {
+        self.segments
+            .range(Self::segment_start(from)..)
+            .flat_map(|(_, segment)| segment.data().block_numbers(from))
+            .collect()
+    }
+
+    fn is_confirmed(&self, bn: BlockNumber) -> bool {
+        self.segment(bn).is_some_and(|data| data.is_confirmed(bn))
+    }
+
+    fn append_blocks(&mut self, blocks: Vec<SmolBlock>) {
+        for block in blocks {
+            let bn = block.bn();
+            let segment = self.segment_mut(bn);
+            segment.push_record(RecordKind::Block, bn, |out| block.encode(out));
+            segment.data_mut().append_blocks(vec![block]);
+        }
+    }
+
+    fn append_receipts(&mut self, bn: BlockNumber, receipts: Vec<ReceiptEnvelope>) {
+        let segment = self.segment_mut(bn);
+        segment.push_record(RecordKind::Receipts, bn, |out| {
+            receipts.iter().for_each(|receipt| receipt.encode_2718(out))
+        });
+        segment.data_mut().append_receipts(bn, receipts);
+    }
+
+    fn append_transactions(&mut self, bn: BlockNumber, transactions: Vec<TxEnvelope>) {
+        let segment = self.segment_mut(bn);
+        segment.push_record(RecordKind::Transactions, bn, |out| {
+            transactions.iter().for_each(|tx| tx.encode_2718(out))
+        });
+        segment.data_mut().append_transactions(bn, transactions);
+    }
+
+    fn confirm(&mut self, bn: BlockNumber) {
+        self.push_marker(RecordKind::Confirm, bn, MemoryStore::confirm);
+    }
+
+    fn evict_block(&mut self, bn: BlockNumber) {
+        self.push_marker(RecordKind::EvictBlock, bn, MemoryStore::evict_block);
+    }
+
+    fn evict_receipts(&mut self, bn: BlockNumber) {
+        self.push_marker(RecordKind::EvictReceipts, bn, MemoryStore::evict_receipts);
+    }
+
+    fn evict_transactions(&mut self, bn: BlockNumber) {
+        self.push_marker(
+            RecordKind::EvictTransactions,
+            bn,
+            MemoryStore::evict_transactions,
+        );
+    }
+
+    fn get_header_receipt_pair(
+        &mut self,
+        bn: BlockNumber,
+    ) -> Option<(&mut Header, &mut Vec<ReceiptEnvelope>)> {
+        let start = Self::segment_start(bn);
+        self.segments
+            .get_mut(&start)?
+            .data_mut()
+            .get_header_receipt_pair(bn)
+    }
+
+    /// Writes the records appended since the last save to the segment files.
+    fn save(&mut self) {
+        for segment in self.segments.values_mut() {
+            segment.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{block, check_persistence, receipt, TempPath};
+    use super::*;
+
+    fn segment_lengths(dir: &Path) -> BTreeMap<PathBuf, u64> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path(), entry.metadata().unwrap().len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn persists_across_segments() {
+        let dir = TempPath::new("file-store");
+        check_persistence(|| FileStore::new(&dir.0));
+        assert_eq!(segment_lengths(&dir.0).len(), 2);
+    }
+
+    #[test]
+    fn saves_only_append_new_records() {
+        let dir = TempPath::new("append");
+        let mut store = FileStore::new(&dir.0);
+        store.append_blocks(vec![block(1), block(SEGMENT_BLOCKS)]);
+        store.save();
+        let before = segment_lengths(&dir.0);
+
+        store.save();
+        assert_eq!(segment_lengths(&dir.0), before);
+
+        store.append_receipts(1, vec![receipt(1)]);
+        store.save();
+        let after = segment_lengths(&dir.0);
+        let (first, second) = (before.keys().next().unwrap(), before.keys().last().unwrap());
+        assert!(after[first] > before[first]);
+        assert_eq!(after[second], before[second]);
+    }
+
+    #[test]
+    fn cuts_off_partial_record() {
+        let dir = TempPath::new("partial");
+        let mut store = FileStore::new(&dir.0);
+        store.append_blocks(vec![block(1), block(2)]);
+        store.save();
+
+        // Simulate a save interrupted in the middle of the second record.
+        let (path, length) = segment_lengths(&dir.0).pop_first().unwrap();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(length - 3).unwrap();
+
+        let mut store = FileStore::new(&dir.0);
+        assert!(store.get_block(1).is_some());
+        assert!(store.get_block(2).is_none());
+        store.append_blocks(vec![block(2)]);
+        store.save();
+        assert_eq!(FileStore::new(&dir.0).get_block(2).unwrap().number, 2);
+    }
+
+    #[test]
+    fn imports_legacy_json_store() {
+        let dir = TempPath::new("legacy");
+        let legacy = serde_json::json!({
+            "blocks": [block(5)],
+            "receipts": { "5": [receipt(21_000)] },
+        });
+        fs::write(dir.0.with_extension("json"), legacy.to_string()).unwrap();
+
+        let store = FileStore::new(&dir.0);
+        assert_eq!(store.get_block(5).unwrap().txs, block(5).txs);
+        assert_eq!(*store.get_receipts(5).unwrap(), [receipt(21_000)]);
+        assert!(store.get_transactions(5).is_none());
+    }
+}
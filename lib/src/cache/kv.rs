@@ -0,0 +1,252 @@
+//! Store in an embedded [redb](https://docs.rs/redb) database, keyed by block number. Unlike the
+//! segment files it doesn't keep whole segments in memory, so it suits ranges of millions of blocks.
+//!
+//! Changes are committed right away without syncing to disk, saving makes them durable.
+
+use super::{decode_2718_list, BlockStore, MemoryStore, SmolBlock};
+use alloy_consensus::{Header, ReceiptEnvelope, TxEnvelope};
+use alloy_eips::eip2718::Encodable2718;
+use alloy_primitives::BlockNumber;
+
+use redb::{Database, Durability, ReadableTable, TableDefinition, WriteTransaction};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+/// Blocks encoded like the block records of segment files.
+const BLOCKS: TableDefinition<u64, &[u8]> = TableDefinition::new("blocks");
+/// Concatenated EIP-2718 encoded receipts of a block.
+const RECEIPTS: TableDefinition<u64, &[u8]> = TableDefinition::new("receipts");
+/// Concatenated EIP-2718 encoded transactions of a block.
+const TRANSACTIONS: TableDefinition<u64, &[u8]> = TableDefinition::new("transactions");
+const CONFIRMED: TableDefinition<u64, ()> = TableDefinition::new("confirmed");
+
+/// Result of database operations, which fail with one of several redb error types.
+type DbResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+pub struct KvStore {
+    path: PathBuf,
+    db: Database,
+    /// Blocks and receipts handed out by `get_header_receipt_pair`, whose changes are not persisted.
+    /// Dropped once the block is changed in the database.
+    scratch: MemoryStore,
+}
+
+impl std::fmt::Debug for KvStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KvStore").field("path", &self.path).finish()
+    }
+}
+
+impl KvStore {
+    /// Opens the database at `path`, creating it if it doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .unwrap_or_else(|err| panic!("Failed to create cache dir {:?}: {:?}", dir, err));
+        }
+        let db = Database::create(&path)
+            .unwrap_or_else(|err| panic!("Failed to open database {:?}: {:?}", path, err));
+        let store = Self {
+            path,
+            db,
+            scratch: MemoryStore::default(),
+        };
+
+        // Create the tables so that reads don't have to deal with missing ones.
+        store.write(Durability::Immediate, |txn| {
+            txn.open_table(BLOCKS)?;
+            txn.open_table(RECEIPTS)?;
+            txn.open_table(TRANSACTIONS)?;
+            txn.open_table(CONFIRMED)?;
+            Ok(())
+        });
+        store
+    }
+
+    fn write(&self, durability: Durability, f: impl FnOnce(&WriteTransaction) -> DbResult<()>) {
+        let result = (|| -> DbResult<()> {
+            let mut txn = self.db.begin_write()?;
+            txn.set_durability(durability);
+            f(&txn)?;
+            txn.commit()?;
+            Ok(())
+        })();
+        result.unwrap_or_else(|err| panic!("Writing database {:?} failed: {:?}", self.path, err));
+    }
+
+    fn read<K: redb::Key + 'static, V: redb::Value + 'static, T>(
+        &self,
+        table: TableDefinition<K, V>,
+        f: impl FnOnce(&redb::ReadOnlyTable<K, V>) -> DbResult<T>,
+    ) -> T {
+        let result = (|| -> DbResult<T> {
+            let txn = self.db.begin_read()?;
+            f(&txn.open_table(table)?)
+        })();
+        result.unwrap_or_else(|err| panic!("Reading database {:?} failed: {:?}", self.path, err))
+    }
+
+    fn read_bytes(&self, table: TableDefinition<u64, &[u8]>, bn: BlockNumber) -> Option<Vec<u8>> {
+        self.read(table, |table| {
+            Ok(table.get(bn)?.map(|bytes| bytes.value().to_vec()))
+        })
+    }
+
+    fn corrupt(&self, bn: BlockNumber, err: &dyn std::fmt::Display) -> ! {
+        panic!(
+            "Corrupt entry for block #{} in database {:?}: {}",
+            bn, self.path, err
+        )
+    }
+
+    /// Appends the items encoded by `encode` to the list of block `bn` in `table`.
+    fn append_encoded(
+        &mut self,
+        table: TableDefinition<u64, &[u8]>,
+        bn: BlockNumber,
+        encode: impl FnOnce(&mut Vec<u8>),
+    ) {
+        self.write(Durability::None, |txn| {
+            let mut table = txn.open_table(table)?;
+            let mut bytes = table
+                .get(bn)?
+                .map(|bytes| bytes.value().to_vec())
+                .unwrap_or_default();
+            encode(&mut bytes);
+            table.insert(bn, bytes.as_slice())?;
+            Ok(())
+        });
+    }
+}
+
+impl BlockStore for KvStore {
+    fn get_block(&self, bn: BlockNumber) -> Option<Cow<'_, SmolBlock>> {
+        if let Some(block) = self.scratch.get_block(bn) {
+            return Some(block);
+        }
+        let bytes = self.read_bytes(BLOCKS, bn)?;
+        let block = SmolBlock::decode(&bytes).unwrap_or_else(|err| self.corrupt(bn, &err));
+        Some(Cow::Owned(block))
+    }
+
+    fn get_receipts(&self, bn: BlockNumber) -> Option<Cow<'_, [ReceiptEnvelope]>> {
+        if let Some(receipts) = self.scratch.get_receipts(bn) {
+            return Some(receipts);
+        }
+        let bytes = self.read_bytes(RECEIPTS, bn)?;
+        let receipts = decode_2718_list(&bytes).unwrap_or_else(|err| self.corrupt(bn, &err));
+        Some(Cow::Owned(receipts))
+    }
+
+    fn get_transactions(&self, bn: BlockNumber) -> Option<Cow<'_, [TxEnvelope]>> {
+        let bytes = self.read_bytes(TRANSACTIONS, bn)?;
+        let txs = decode_2718_list(&bytes).unwrap_or_else(|err| self.corrupt(bn, &err));
+        Some(Cow::Owned(txs))
+    }
+
+    fn block_numbers(&self, from: BlockNumber) -> Vec<BlockNumber> {
+        self.read(BLOCKS, |table| {
+            table
+                .range(from..)?
+                .map(|entry| Ok(entry?.0.value()))
+                .collect()
+        })
+    }
+
+    fn is_confirmed(&self, bn: BlockNumber) -> bool {
+        self.read(CONFIRMED, |table| Ok(table.get(bn)?.is_some()))
+    }
+
+    fn append_blocks(&mut self, blocks: Vec<SmolBlock>) {
+        for block in &blocks {
+            self.scratch.evict_block(block.bn());
+        }
+        self.write(Durability::None, |txn| {
+            let mut table = txn.open_table(BLOCKS)?;
+            let mut bytes = Vec::new();
+            for block in &blocks {
+                bytes.clear();
+                block.encode(&mut bytes);
+                table.insert(block.bn(), bytes.as_slice())?;
+            }
+            Ok(())
+        });
+    }
+
+    fn append_receipts(&mut self, bn: BlockNumber, receipts: Vec<ReceiptEnvelope>) {
+        self.scratch.evict_block(bn);
+        self.append_encoded(RECEIPTS, bn, |out| {
+            receipts.iter().for_each(|receipt| receipt.encode_2718(out))
+        });
+    }
+
+    fn append_transactions(&mut self, bn: BlockNumber, transactions: Vec<TxEnvelope>) {
+        self.append_encoded(TRANSACTIONS, bn, |out| {
+            transactions.iter().for_each(|tx| tx.encode_2718(out))
+        });
+    }
+
+    fn confirm(&mut self, bn: BlockNumber) {
+        self.write(Durability::None, |txn| {
+            txn.open_table(CONFIRMED)?.insert(bn, ())?;
+            Ok(())
+        });
+    }
+
+    fn evict_block(&mut self, bn: BlockNumber) {
+        self.scratch.evict_block(bn);
+        self.write(Durability::None, |txn| {
+            txn.open_table(BLOCKS)?.remove(bn)?;
+            txn.open_table(RECEIPTS)?.remove(bn)?;
+            txn.open_table(TRANSACTIONS)?.remove(bn)?;
+            txn.open_table(CONFIRMED)?.remove(bn)?;
+            Ok(())
+        });
+    }
+
+    fn evict_receipts(&mut self, bn: BlockNumber) {
+        self.scratch.evict_block(bn);
+        self.write(Durability::None, |txn| {
+            txn.open_table(RECEIPTS)?.remove(bn)?;
+            Ok(())
+        });
+    }
+
+    fn evict_transactions(&mut self, bn: BlockNumber) {
+        self.write(Durability::None, |txn| {
+            txn.open_table(TRANSACTIONS)?.remove(bn)?;
+            Ok(())
+        });
+    }
+
+    fn get_header_receipt_pair(
+        &mut self,
+        bn: BlockNumber,
+    ) -> Option<(&mut Header, &mut Vec<ReceiptEnvelope>)> {
+        if self.scratch.get_block(bn).is_none() {
+            let block = self.get_block(bn)?.into_owned();
+            let receipts = self.get_receipts(bn)?.into_owned();
+            self.scratch.append_blocks(vec![block]);
+            self.scratch.append_receipts(bn, receipts);
+        }
+        self.scratch.get_header_receipt_pair(bn)
+    }
+
+    /// Makes the changes committed since the last save durable.
+    fn save(&mut self) {
+        self.write(Durability::Immediate, |_| Ok(()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{check_persistence, TempPath};
+    use super::*;
+
+    #[test]
+    fn persists_changes() {
+        let path = TempPath::new("kv-store");
+        check_persistence(|| KvStore::open(&path.0));
+    }
+}
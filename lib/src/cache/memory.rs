@@ -0,0 +1,87 @@
+use super::{BlockStore, SmolBlock};
+use alloy_consensus::{Header, ReceiptEnvelope, TxEnvelope};
+use alloy_primitives::BlockNumber;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Store keeping everything in memory, saving is a no-op.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    blocks: BTreeMap<BlockNumber, SmolBlock>,
+    receipts: HashMap<BlockNumber, Vec<ReceiptEnvelope>>,
+    transactions: HashMap<BlockNumber, Vec<TxEnvelope>>,
+    /// Blocks fetched at the confirmation depth, which are no longer expected to be reorged.
+    confirmed: HashSet<BlockNumber>,
+}
+
+impl BlockStore for MemoryStore {
+    fn get_block(&self, bn: BlockNumber) -> Option<Cow<'_, SmolBlock>> {
+        self.blocks.get(&bn).map(Cow::Borrowed)
+    }
+
+    fn get_receipts(&self, bn: BlockNumber) -> Option<Cow<'_, [ReceiptEnvelope]>> {
+        self.receipts
+            .get(&bn)
+            .map(|receipts| Cow::Borrowed(&receipts[..]))
+    }
+
+    fn get_transactions(&self, bn: BlockNumber) -> Option<Cow<'_, [TxEnvelope]>> {
+        self.transactions
+            .get(&bn)
+            .map(|txs| Cow::Borrowed(&txs[..]))
+    }
+
+    fn block_numbers(&self, from: BlockNumber) -> Vec<BlockNumber> {
+        self.blocks.range(from..).map(|(&bn, _)| bn).collect()
+    }
+
+    fn is_confirmed(&self, bn: BlockNumber) -> bool {
+        self.confirmed.contains(&bn)
+    }
+
+    fn append_blocks(&mut self, blocks: Vec<SmolBlock>) {
+        self.blocks
+            .extend(blocks.into_iter().map(|block| (block.bn(), block)));
+    }
+
+    fn append_receipts(&mut self, bn: BlockNumber, receipts: Vec<ReceiptEnvelope>) {
+        self.receipts.entry(bn).or_default().extend(receipts);
+    }
+
+    fn append_transactions(&mut self, bn: BlockNumber, transactions: Vec<TxEnvelope>) {
+        self.transactions
+            .entry(bn)
+            .or_default()
+            .extend(transactions);
+    }
+
+    fn confirm(&mut self, bn: BlockNumber) {
+        self.confirmed.insert(bn);
+    }
+
+    fn evict_block(&mut self, bn: BlockNumber) {
+        self.blocks.remove(&bn);
+        self.receipts.remove(&bn);
+        self.transactions.remove(&bn);
+        self.confirmed.remove(&bn);
+    }
+
+    fn evict_receipts(&mut self, bn: BlockNumber) {
+        self.receipts.remove(&bn);
+    }
+
+    fn evict_transactions(&mut self, bn: BlockNumber) {
+        self.transactions.remove(&bn);
+    }
+
+    fn get_header_receipt_pair(
+        &mut self,
+        bn: BlockNumber,
+    ) -> Option<(&mut Header, &mut Vec<ReceiptEnvelope>)> {
+        let block = self.blocks.get_mut(&bn)?;
+        let receipts = self.receipts.get_mut(&bn)?;
+        Some((&mut block.header, receipts))
+    }
+
+    fn save(&mut self) {}
+}
@@ -0,0 +1,816 @@
+//! Cache of fetched blocks, receipts and transactions on top of a pluggable [`BlockStore`]:
+//!
+//! - [`MemoryStore`]: kept in memory only, for tests and one-off runs
+//! - [`FileStore`]: append-only segment files
+//! - `KvStore`: embedded key-value database for large ranges, behind the `kv-store` feature
+
+mod file;
+#[cfg(feature = "kv-store")]
+mod kv;
+mod memory;
+
+pub use file::{FileStore, SEGMENT_BLOCKS};
+#[cfg(feature = "kv-store")]
+pub use kv::KvStore;
+pub use memory::MemoryStore;
+
+use alloy_consensus::proofs::{calculate_receipt_root, calculate_transaction_root};
+use alloy_consensus::{Header, ReceiptEnvelope, TxEnvelope};
+use alloy_eips::eip2718::Decodable2718;
+use alloy_primitives::{BlockNumber, B256};
+use alloy_rlp::{Decodable, Encodable};
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::Path;
+use tracing::info;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct SmolBlock {
+    pub header: Header,
+    pub txs: Vec<B256>,
+}
+
+impl SmolBlock {
+    pub fn new(header: Header, txs: Vec<B256>) -> Self {
+        Self { header, txs }
+    }
+
+    fn bn(&self) -> u64 {
+        self.header.number
+    }
+
+    /// Encodes the header as RLP followed by the 32-byte hashes of the block's transactions.
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        for tx in &self.txs {
+            out.extend_from_slice(tx.as_slice());
+        }
+    }
+
+    fn decode(mut payload: &[u8]) -> alloy_rlp::Result<Self> {
+        let header = Header::decode(&mut payload)?;
+        let hashes = payload.chunks_exact(32);
+        if !hashes.remainder().is_empty() {
+            return Err(alloy_rlp::Error::Custom("trailing bytes after tx hashes"));
+        }
+        let txs = hashes.map(B256::from_slice).collect();
+        Ok(Self { header, txs })
+    }
+}
+
+impl core::ops::Deref for SmolBlock {
+    type Target = Header;
+
+    fn deref(&self) -> &Self::Target {
+        &self.header
+    }
+}
+
+fn decode_2718_list<T: Decodable2718>(
+    mut payload: &[u8],
+) -> alloy_eips::eip2718::Eip2718Result<Vec<T>> {
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        items.push(T::decode_2718(&mut payload)?);
+    }
+    Ok(items)
+}
+
+/// Storage backend of the [`Cache`]. Stores hand out borrowed data where they hold it in memory and
+/// owned data where it has to be decoded first.
+pub trait BlockStore {
+    fn get_block(&self, bn: BlockNumber) -> Option<Cow<'_, SmolBlock>>;
+
+    fn get_receipts(&self, bn: BlockNumber) -> Option<Cow<'_, [ReceiptEnvelope]>>;
+
+    fn get_transactions(&self, bn: BlockNumber) -> Option<Cow<'_, [TxEnvelope]>>;
+
+    /// Numbers of the stored blocks from `from` on, in ascending order.
+    fn block_numbers(&self, from: BlockNumber) -> Vec<BlockNumber>;
+
+    fn is_confirmed(&self, bn: BlockNumber) -> bool;
+
+    /// Stores `blocks`, replacing stored blocks of the same number.
+    fn append_blocks(&mut self, blocks: Vec<SmolBlock>);
+
+    /// Appends `receipts` to the receipts stored for block `bn`.
+    fn append_receipts(&mut self, bn: BlockNumber, receipts: Vec<ReceiptEnvelope>);
+
+    /// Appends `transactions` to the transactions stored for block `bn`.
+    fn append_transactions(&mut self, bn: BlockNumber, transactions: Vec<TxEnvelope>);
+
+    fn confirm(&mut self, bn: BlockNumber);
+
+    /// Drops block `bn` together with its receipts, transactions and confirmation.
+    fn evict_block(&mut self, bn: BlockNumber);
+
+    fn evict_receipts(&mut self, bn: BlockNumber);
+
+    fn evict_transactions(&mut self, bn: BlockNumber);
+
+    /// Gives mutable access to a block's header and receipts, changes are not persisted.
+    fn get_header_receipt_pair(
+        &mut self,
+        bn: BlockNumber,
+    ) -> Option<(&mut Header, &mut Vec<ReceiptEnvelope>)>;
+
+    /// Persists the changes made since the last save.
+    fn save(&mut self);
+}
+
+impl<S: BlockStore + ?Sized> BlockStore for Box<S> {
+    fn get_block(&self, bn: BlockNumber) -> Option<Cow<'_, SmolBlock>> {
+        (**self).get_block(bn)
+    }
+
+    fn get_receipts(&self, bn: BlockNumber) -> Option<Cow<'_, [ReceiptEnvelope]>> {
+        (**self).get_receipts(bn)
+    }
+
+    fn get_transactions(&self, bn: BlockNumber) -> Option<Cow<'_, [TxEnvelope]>> {
+        (**self).get_transactions(bn)
+    }
+
+    fn block_numbers(&self, from: BlockNumber) -> Vec<BlockNumber> {
+        (**self).block_numbers(from)
+    }
+
+    fn is_confirmed(&self, bn: BlockNumber) -> bool {
+        (**self).is_confirmed(bn)
+    }
+
+    fn append_blocks(&mut self, blocks: Vec<SmolBlock>) {
+        (**self).append_blocks(blocks)
+    }
+
+    fn append_receipts(&mut self, bn: BlockNumber, receipts: Vec<ReceiptEnvelope>) {
+        (**self).append_receipts(bn, receipts)
+    }
+
+    fn append_transactions(&mut self, bn: BlockNumber, transactions: Vec<TxEnvelope>) {
+        (**self).append_transactions(bn, transactions)
+    }
+
+    fn confirm(&mut self, bn: BlockNumber) {
+        (**self).confirm(bn)
+    }
+
+    fn evict_block(&mut self, bn: BlockNumber) {
+        (**self).evict_block(bn)
+    }
+
+    fn evict_receipts(&mut self, bn: BlockNumber) {
+        (**self).evict_receipts(bn)
+    }
+
+    fn evict_transactions(&mut self, bn: BlockNumber) {
+        (**self).evict_transactions(bn)
+    }
+
+    fn get_header_receipt_pair(
+        &mut self,
+        bn: BlockNumber,
+    ) -> Option<(&mut Header, &mut Vec<ReceiptEnvelope>)> {
+        (**self).get_header_receipt_pair(bn)
+    }
+
+    fn save(&mut self) {
+        (**self).save()
+    }
+}
+
+/// Backend to open a cache with, such as from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreKind {
+    Memory,
+    File,
+    #[cfg(feature = "kv-store")]
+    Kv,
+}
+
+impl StoreKind {
+    /// Opens the store at `path`, a directory for [`FileStore`] and the same path with a `.redb`
+    /// extension for `KvStore`.
+    pub fn open(self, path: &Path) -> Box<dyn BlockStore + Send> {
+        match self {
+            Self::Memory => Box::new(MemoryStore::default()),
+            Self::File => Box::new(FileStore::new(path.to_path_buf())),
+            #[cfg(feature = "kv-store")]
+            Self::Kv => Box::new(KvStore::open(path.with_extension("redb"))),
+        }
+    }
+}
+
+impl std::str::FromStr for StoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "file" => Ok(Self::File),
+            #[cfg(feature = "kv-store")]
+            "kv" => Ok(Self::Kv),
+            _ => Err(format!("unknown store {:?}", s)),
+        }
+    }
+}
+
+/// Inconsistency found by [`Cache::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheIssue {
+    /// Header of block `number` doesn't hash to the parent hash of the next block.
+    BrokenParentLink {
+        number: BlockNumber,
+        hash: B256,
+        next_parent_hash: B256,
+    },
+    /// Number of receipts differs from the number of transactions, such as after an interrupted
+    /// fetch.
+    ReceiptCountMismatch {
+        number: BlockNumber,
+        receipts: usize,
+        txs: usize,
+    },
+    ReceiptsRootMismatch {
+        number: BlockNumber,
+        computed: B256,
+        expected: B256,
+    },
+    TransactionCountMismatch {
+        number: BlockNumber,
+        transactions: usize,
+        txs: usize,
+    },
+    TransactionsRootMismatch {
+        number: BlockNumber,
+        computed: B256,
+        expected: B256,
+    },
+}
+
+impl std::fmt::Display for CacheIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BrokenParentLink {
+                number,
+                hash,
+                next_parent_hash,
+            } => write!(
+                f,
+                "block #{}: hash {} is not the parent hash {} of the next block",
+                number, hash, next_parent_hash
+            ),
+            Self::ReceiptCountMismatch {
+                number,
+                receipts,
+                txs,
+            } => write!(
+                f,
+                "block #{}: {} receipts for {} transactions",
+                number, receipts, txs
+            ),
+            Self::ReceiptsRootMismatch {
+                number,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "block #{}: receipts root {} of receipts does not match {}",
+                number, computed, expected
+            ),
+            Self::TransactionCountMismatch {
+                number,
+                transactions,
+                txs,
+            } => write!(
+                f,
+                "block #{}: {} transactions for {} transaction hashes",
+                number, transactions, txs
+            ),
+            Self::TransactionsRootMismatch {
+                number,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "block #{}: transactions root {} of transactions does not match {}",
+                number, computed, expected
+            ),
+        }
+    }
+}
+
+/// Reorg that [`Cache::append_chain`] refuses to roll back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReorgError {
+    /// Reorg at block `fork` orphans the confirmed block `confirmed`, blocks are confirmed too
+    /// close to the chain head.
+    ConfirmedBlockOrphaned {
+        fork: BlockNumber,
+        confirmed: BlockNumber,
+    },
+}
+
+impl std::fmt::Display for ReorgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ConfirmedBlockOrphaned { fork, confirmed } => write!(
+                f,
+                "reorg at block #{} orphans confirmed block #{}, confirmation depth is too shallow",
+                fork, confirmed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReorgError {}
+
+#[derive(Debug)]
+pub struct Cache<S: BlockStore> {
+    store: S,
+}
+
+impl<S: BlockStore> Cache<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Persists the data appended since the last save.
+    pub fn save(&mut self) {
+        use std::time::Instant;
+
+        let start = Instant::now();
+        self.store.save();
+        let elapsed = start.elapsed();
+
+        info!("elapsed: {:?}", elapsed);
+    }
+
+    pub fn append_blocks(&mut self, blocks: impl IntoIterator<Item = SmolBlock>) {
+        self.store.append_blocks(blocks.into_iter().collect());
+    }
+
+    pub fn append_receipt(&mut self, bn: BlockNumber, receipt: ReceiptEnvelope) {
+        self.store.append_receipts(bn, vec![receipt]);
+    }
+
+    pub fn append_receipts(&mut self, bn: BlockNumber, receipts: Vec<ReceiptEnvelope>) {
+        self.store.append_receipts(bn, receipts);
+    }
+
+    pub fn append_transaction(&mut self, bn: BlockNumber, transaction: TxEnvelope) {
+        self.store.append_transactions(bn, vec![transaction]);
+    }
+
+    pub fn append_transactions(&mut self, bn: BlockNumber, transactions: Vec<TxEnvelope>) {
+        self.store.append_transactions(bn, transactions);
+    }
+
+    pub fn get_block(&self, bn: BlockNumber) -> Option<Cow<'_, SmolBlock>> {
+        self.store.get_block(bn)
+    }
+
+    pub fn get_receipts(&self, bn: BlockNumber) -> Option<Cow<'_, [ReceiptEnvelope]>> {
+        self.store.get_receipts(bn)
+    }
+
+    pub fn get_transactions(&self, bn: BlockNumber) -> Option<Cow<'_, [TxEnvelope]>> {
+        self.store.get_transactions(bn)
+    }
+
+    /// Drops block `bn` together with its receipts and transactions.
+    pub fn evict_block(&mut self, bn: BlockNumber) {
+        self.store.evict_block(bn);
+    }
+
+    pub fn evict_receipts(&mut self, bn: BlockNumber) {
+        self.store.evict_receipts(bn);
+    }
+
+    pub fn evict_transactions(&mut self, bn: BlockNumber) {
+        self.store.evict_transactions(bn);
+    }
+
+    /// Marks block `bn` as confirmed, it's fetched again to check for reorgs until then.
+    pub fn confirm(&mut self, bn: BlockNumber) {
+        if !self.store.is_confirmed(bn) {
+            self.store.confirm(bn);
+        }
+    }
+
+    pub fn is_confirmed(&self, bn: BlockNumber) -> bool {
+        self.store.is_confirmed(bn)
+    }
+
+    /// Whether the cached predecessor of `block` isn't its parent, having been orphaned by a reorg.
+    pub fn has_orphaned_parent(&self, block: &SmolBlock) -> bool {
+        block.bn() > 0
+            && self
+                .get_block(block.bn() - 1)
+                .is_some_and(|parent| parent.hash_slow() != block.parent_hash)
+    }
+
+    /// Appends `chain`, consecutive blocks of the canonical chain in ascending order whose first
+    /// block builds on its cached predecessor. If a cached block differs from the chain's block of
+    /// the same number, it and the consecutive cached blocks above it are orphaned and rolled back
+    /// together with their receipts and transactions. Returns the numbers of the orphaned blocks,
+    /// or an error without changing the cache if a confirmed block would be rolled back.
+    ///
+    /// Panics if `chain` doesn't link up.
+    pub fn append_chain(&mut self, chain: Vec<SmolBlock>) -> Result<Vec<BlockNumber>, ReorgError> {
+        let Some(first) = chain.first() else {
+            return Ok(Vec::new());
+        };
+        assert!(
+            !self.has_orphaned_parent(first),
+            "Chain starting at block #{} doesn't build on the cached blocks",
+            first.bn()
+        );
+        for (parent, child) in chain.iter().zip(&chain[1..]) {
+            assert!(
+                child.bn() == parent.bn() + 1 && child.parent_hash == parent.hash_slow(),
+                "Block #{} doesn't build on its predecessor",
+                child.bn()
+            );
+        }
+
+        let hashes: Vec<_> = chain.iter().map(|block| block.hash_slow()).collect();
+        let fork = chain.iter().zip(&hashes).find_map(|(block, hash)| {
+            let cached = self.get_block(block.bn())?;
+            (cached.hash_slow() != *hash).then_some(block.bn())
+        });
+        let orphaned = match fork {
+            Some(fork) => self.rollback(fork)?,
+            None => Vec::new(),
+        };
+
+        let new_blocks = chain
+            .into_iter()
+            .filter(|block| self.get_block(block.bn()).is_none())
+            .collect::<Vec<_>>();
+        self.append_blocks(new_blocks);
+        Ok(orphaned)
+    }
+
+    /// Evicts the consecutive cached blocks from `fork` on, up to the first block that isn't
+    /// cached, returning their numbers.
+    fn rollback(&mut self, fork: BlockNumber) -> Result<Vec<BlockNumber>, ReorgError> {
+        let orphaned: Vec<_> = self
+            .store
+            .block_numbers(fork)
+            .into_iter()
+            .zip(fork..)
+            .take_while(|(bn, expected)| bn == expected)
+            .map(|(bn, _)| bn)
+            .collect();
+        if let Some(&confirmed) = orphaned.iter().find(|&&bn| self.is_confirmed(bn)) {
+            return Err(ReorgError::ConfirmedBlockOrphaned { fork, confirmed });
+        }
+        for &bn in &orphaned {
+            self.evict_block(bn);
+        }
+        Ok(orphaned)
+    }
+
+    /// Checks that every cached header links to the next cached block and that the cached receipts
+    /// and transactions of every block are complete and match its header.
+    pub fn verify(&self) -> Vec<CacheIssue> {
+        let mut issues = Vec::new();
+        for number in self.store.block_numbers(0) {
+            let block = self.get_block(number).unwrap();
+            if let Some(next) = self.get_block(number + 1) {
+                let hash = block.hash_slow();
+                if hash != next.parent_hash {
+                    issues.push(CacheIssue::BrokenParentLink {
+                        number,
+                        hash,
+                        next_parent_hash: next.parent_hash,
+                    });
+                }
+            }
+
+            if let Some(receipts) = self.get_receipts(number) {
+                if receipts.len() != block.txs.len() {
+                    issues.push(CacheIssue::ReceiptCountMismatch {
+                        number,
+                        receipts: receipts.len(),
+                        txs: block.txs.len(),
+                    });
+                } else {
+                    let computed = calculate_receipt_root(&receipts);
+                    if computed != block.receipts_root {
+                        issues.push(CacheIssue::ReceiptsRootMismatch {
+                            number,
+                            computed,
+                            expected: block.receipts_root,
+                        });
+                    }
+                }
+            }
+
+            if let Some(transactions) = self.get_transactions(number) {
+                if transactions.len() != block.txs.len() {
+                    issues.push(CacheIssue::TransactionCountMismatch {
+                        number,
+                        transactions: transactions.len(),
+                        txs: block.txs.len(),
+                    });
+                } else {
+                    let computed = calculate_transaction_root(&transactions);
+                    if computed != block.transactions_root {
+                        issues.push(CacheIssue::TransactionsRootMismatch {
+                            number,
+                            computed,
+                            expected: block.transactions_root,
+                        });
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    /// Evicts the entries affected by `issue` so that they get fetched again. As it's unknown which
+    /// of the two headers of a broken parent link is wrong, both blocks are evicted.
+    pub fn evict(&mut self, issue: &CacheIssue) {
+        match *issue {
+            CacheIssue::BrokenParentLink { number, .. } => {
+                self.evict_block(number);
+                self.evict_block(number + 1);
+            }
+            CacheIssue::ReceiptCountMismatch { number, .. }
+            | CacheIssue::ReceiptsRootMismatch { number, .. } => self.evict_receipts(number),
+            CacheIssue::TransactionCountMismatch { number, .. }
+            | CacheIssue::TransactionsRootMismatch { number, .. } => {
+                self.evict_transactions(number)
+            }
+        }
+    }
+
+    /// Gives mutable access to a block's header and receipts, changes are not persisted.
+    pub fn get_header_receipt_pair(
+        &mut self,
+        bn: BlockNumber,
+    ) -> Option<(&mut Header, &mut Vec<ReceiptEnvelope>)> {
+        self.store.get_header_receipt_pair(bn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_consensus::{Receipt, SignableTransaction, TxEip1559};
+    use alloy_primitives::{Log, PrimitiveSignature as Signature};
+    use std::path::PathBuf;
+
+    /// Fresh path for the store of a test, removed again when dropped.
+    pub(super) struct TempPath(pub(super) PathBuf);
+
+    impl TempPath {
+        pub(super) fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("santa-cache-{}-{}", name, std::process::id()));
+            let temp = Self(path);
+            temp.remove();
+            temp
+        }
+
+        fn remove(&self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(self.0.with_extension("json"));
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    pub(super) fn block(number: u64) -> SmolBlock {
+        SmolBlock::new(
+            Header {
+                number,
+                ..Default::default()
+            },
+            vec![B256::with_last_byte(number as u8); 2],
+        )
+    }
+
+    pub(super) fn receipt(cumulative_gas_used: u64) -> ReceiptEnvelope {
+        ReceiptEnvelope::Eip1559(
+            Receipt {
+                status: true.into(),
+                cumulative_gas_used,
+                logs: vec![Log::empty()],
+            }
+            .with_bloom(),
+        )
+    }
+
+    pub(super) fn transaction(nonce: u64) -> TxEnvelope {
+        let tx = TxEip1559 {
+            nonce,
+            ..Default::default()
+        };
+        TxEnvelope::Eip1559(tx.into_signed(Signature::test_signature()))
+    }
+
+    /// Checks that everything written to the store returned by `open` survives reopening it, with
+    /// the exception of changes through `get_header_receipt_pair`.
+    pub(super) fn check_persistence<S: BlockStore>(open: impl Fn() -> S) {
+        let far = 3 * SEGMENT_BLOCKS + 5;
+        let mut store = open();
+        store.append_blocks(vec![block(7), block(far), block(8), block(9)]);
+        store.append_receipts(7, vec![receipt(1), receipt(2)]);
+        store.append_receipts(7, vec![receipt(3)]);
+        store.append_receipts(8, vec![receipt(4)]);
+        store.append_transactions(far, vec![transaction(4)]);
+        store.append_transactions(9, vec![transaction(5)]);
+        store.confirm(7);
+        store.confirm(9);
+        store.evict_block(9);
+        store.evict_receipts(8);
+        let (header, receipts) = store.get_header_receipt_pair(7).unwrap();
+        header.number = 1;
+        receipts.clear();
+        store.save();
+        drop(store);
+
+        let mut store = open();
+        assert_eq!(store.block_numbers(0), [7, 8, far]);
+        assert_eq!(store.block_numbers(8), [8, far]);
+        assert_eq!(store.get_block(7).unwrap().number, 7);
+        assert_eq!(store.get_block(far).unwrap().txs, block(far).txs);
+        assert!(store.get_block(9).is_none());
+        assert_eq!(
+            *store.get_receipts(7).unwrap(),
+            [receipt(1), receipt(2), receipt(3)]
+        );
+        assert!(store.get_receipts(8).is_none());
+        assert!(store.get_receipts(far).is_none());
+        assert_eq!(*store.get_transactions(far).unwrap(), [transaction(4)]);
+        assert!(store.get_transactions(9).is_none());
+        assert!(store.is_confirmed(7));
+        assert!(!store.is_confirmed(9));
+
+        // Appending to a block stays possible after a reopen.
+        store.append_receipts(7, vec![receipt(5)]);
+        store.save();
+        drop(store);
+        assert_eq!(open().get_receipts(7).unwrap().len(), 4);
+    }
+
+    /// Caches a chain of 3 blocks with one receipt and transaction each.
+    fn consistent_cache() -> Cache<MemoryStore> {
+        let mut cache = Cache::new(MemoryStore::default());
+        let mut parent_hash = B256::ZERO;
+        for number in 0..3 {
+            let receipts = vec![receipt(21_000)];
+            let transactions = vec![transaction(number)];
+            let block = SmolBlock::new(
+                Header {
+                    number,
+                    parent_hash,
+                    receipts_root: calculate_receipt_root(&receipts),
+                    transactions_root: calculate_transaction_root(&transactions),
+                    ..Default::default()
+                },
+                vec![*transactions[0].tx_hash()],
+            );
+            parent_hash = block.hash_slow();
+            cache.append_blocks([block]);
+            cache.append_receipts(number, receipts);
+            cache.append_transactions(number, transactions);
+        }
+        cache
+    }
+
+    #[test]
+    fn memory_store_round_trips() {
+        let mut store = MemoryStore::default();
+        store.append_blocks(vec![block(7)]);
+        store.append_receipts(7, vec![receipt(1)]);
+        store.append_receipts(7, vec![receipt(2)]);
+        assert_eq!(*store.get_receipts(7).unwrap(), [receipt(1), receipt(2)]);
+        assert!(matches!(store.get_block(7), Some(Cow::Borrowed(_))));
+        assert!(store.get_transactions(7).is_none());
+    }
+
+    #[test]
+    fn verifies_and_evicts_inconsistent_entries() {
+        let mut cache = consistent_cache();
+        assert_eq!(cache.verify(), []);
+
+        cache.append_receipt(0, receipt(42_000));
+        cache.evict_transactions(2);
+        cache.append_transaction(2, transaction(7));
+        let mut forked = cache.get_block(1).unwrap().into_owned();
+        forked.header.extra_data = vec![1].into();
+        cache.append_blocks([forked.clone()]);
+
+        let issues = cache.verify();
+        let block_2 = cache.get_block(2).unwrap().into_owned();
+        assert_eq!(
+            issues,
+            [
+                CacheIssue::ReceiptCountMismatch {
+                    number: 0,
+                    receipts: 2,
+                    txs: 1
+                },
+                CacheIssue::BrokenParentLink {
+                    number: 1,
+                    hash: forked.hash_slow(),
+                    next_parent_hash: block_2.parent_hash,
+                },
+                CacheIssue::TransactionsRootMismatch {
+                    number: 2,
+                    computed: calculate_transaction_root(&[transaction(7)]),
+                    expected: block_2.transactions_root,
+                },
+            ]
+        );
+
+        for issue in &issues {
+            cache.evict(issue);
+        }
+        assert_eq!(cache.verify(), []);
+        assert!(cache.get_receipts(0).is_none());
+        assert!(cache.get_block(1).is_none());
+        assert!(cache.get_block(2).is_none());
+        assert!(cache.get_block(0).is_some());
+    }
+
+    #[test]
+    fn rolls_back_orphaned_blocks() {
+        let mut cache = consistent_cache();
+        cache.confirm(0);
+        let far = SEGMENT_BLOCKS + 1;
+        cache.append_blocks([block(far)]);
+
+        // Canonical chain forking off after block 0 and extending past the cached blocks.
+        let mut parent_hash = cache.get_block(0).unwrap().hash_slow();
+        let chain: Vec<_> = (1..4)
+            .map(|number| {
+                let block = SmolBlock::new(
+                    Header {
+                        number,
+                        parent_hash,
+                        extra_data: vec![1].into(),
+                        ..Default::default()
+                    },
+                    Vec::new(),
+                );
+                parent_hash = block.hash_slow();
+                block
+            })
+            .collect();
+
+        assert!(!cache.has_orphaned_parent(&chain[0]));
+        assert!(cache.has_orphaned_parent(&chain[2]));
+        assert_eq!(cache.append_chain(chain.clone()), Ok(vec![1, 2]));
+
+        assert_eq!(
+            cache.get_block(2).unwrap().hash_slow(),
+            chain[1].hash_slow()
+        );
+        assert_eq!(
+            cache.get_block(3).unwrap().hash_slow(),
+            chain[2].hash_slow()
+        );
+        assert!(cache.get_receipts(1).is_none());
+        // Blocks past a gap aren't known to build on the orphaned ones.
+        assert!(cache.get_block(far).is_some());
+        assert!(cache.get_receipts(0).is_some());
+        assert!(cache.is_confirmed(0));
+        assert!(!cache.is_confirmed(1));
+    }
+
+    #[test]
+    fn refuses_to_roll_back_confirmed_blocks() {
+        let mut cache = consistent_cache();
+        cache.confirm(2);
+        let mut fork = cache.get_block(1).unwrap().into_owned();
+        fork.header.extra_data = vec![1].into();
+        assert_eq!(
+            cache.append_chain(vec![fork]),
+            Err(ReorgError::ConfirmedBlockOrphaned {
+                fork: 1,
+                confirmed: 2
+            })
+        );
+        assert!(cache.get_block(1).is_some());
+        assert!(cache.get_block(2).is_some());
+    }
+}
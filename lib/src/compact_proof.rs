@@ -0,0 +1,507 @@
+//! Structured view of the compact receipt proofs checked by
+//! [`receipt_trie_root_from_proof`](crate::receipt_trie::receipt_trie_root_from_proof).
+//!
+//! A proof lists the nodes on the path from the proven leaf up to the root, leaving out everything
+//! the verifier recomputes itself:
+//!
+//! ```text
+//! proof     := version leaf step*
+//! version   := 0x01
+//! leaf      := path
+//! step      := extension | branch
+//! extension := 0x00 path
+//! branch    := (0x20 | index) branch_map:u16 hash*
+//!            | (0x30 | index) branch_map:u16 payload_length:u32 (length:u32 child)*
+//! path      := nibble_count:u8 [odd_nibble:u8] packed_nibbles
+//! ```
+//!
+//! Integers are big-endian. A path with an odd number of nibbles stores the first one in a byte of
+//! its own, the remaining ones are packed two per byte.
+//!
+//! `index` is the nibble under which the branch holds the node proven so far, `branch_map` has a
+//! bit set for every non-empty child including `index`. The siblings follow in ascending order.
+//! If all children of a branch are hashes they're listed as 32 bytes each, otherwise the
+//! length-prefixed layout is used: 32-byte children are hashes while shorter ones are nodes
+//! inlined in the branch, which have to be complete RLP lists of at least one item. There
+//! `payload_length` is the length of the branch's RLP payload.
+
+use crate::receipt_trie::*;
+use crate::Reader;
+
+use alloy_primitives::{Bytes, B256};
+use alloy_rlp::Decodable;
+use alloy_trie::{
+    nodes::{BranchNode, ExtensionNode, LeafNode, RlpNode, TrieNode},
+    proof::ProofNodes,
+    Nibbles, TrieMask,
+};
+
+/// A node on the path between the proven leaf and the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofStep {
+    Extension {
+        path: Nibbles,
+    },
+    /// Branch holding the node proven so far at `index`. `siblings[index]` is empty, the other
+    /// siblings are empty, 32-byte hashes or inline nodes.
+    Branch {
+        index: u8,
+        siblings: Box<[Bytes; 16]>,
+        layout: BranchLayout,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchLayout {
+    /// Every child is a hash.
+    Hashes,
+    /// Children are length-prefixed, allowing for inline nodes.
+    LengthPrefixed { payload_length: u32 },
+}
+
+/// Decoded compact proof, `steps` going from the leaf up to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactProof {
+    pub leaf_path: Nibbles,
+    pub steps: Vec<ProofStep>,
+}
+
+impl CompactProof {
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProofError> {
+        let mut proof = Reader::from(bytes);
+        read_version(&mut proof)?;
+        let leaf_path = read_path(&mut proof)?;
+
+        let mut steps = Vec::new();
+        while !proof.is_empty() {
+            let step = match read_step_kind(&mut proof)? {
+                StepKind::Extension => ProofStep::Extension {
+                    path: read_path(&mut proof)?,
+                },
+                StepKind::Branch {
+                    index,
+                    weird_branches,
+                } => read_branch(&mut proof, index, weird_branches)?,
+            };
+            steps.push(step);
+        }
+
+        Ok(Self { leaf_path, steps })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut proof = vec![COMPACT_PROOF_VERSION];
+        write_path(&mut proof, &self.leaf_path);
+
+        for step in &self.steps {
+            match step {
+                ProofStep::Extension { path } => {
+                    proof.push(EXTENSION_NODE_FLAG);
+                    write_path(&mut proof, path);
+                }
+                ProofStep::Branch {
+                    index,
+                    siblings,
+                    layout,
+                } => {
+                    let siblings = siblings
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, sibling)| *i != usize::from(*index) && !sibling.is_empty());
+                    let branch_map = siblings
+                        .clone()
+                        .fold(1u16 << index, |map, (i, _)| map | (1 << i));
+
+                    match layout {
+                        BranchLayout::Hashes => {
+                            proof.push(BRANCH_NODE_FLAG | index);
+                            proof.extend_from_slice(&branch_map.to_be_bytes());
+                            siblings.for_each(|(_, sibling)| proof.extend_from_slice(sibling));
+                        }
+                        BranchLayout::LengthPrefixed { payload_length } => {
+                            proof.push(BRANCH_NODE_FLAG | WEIRD_BRANCHES_FLAG | index);
+                            proof.extend_from_slice(&branch_map.to_be_bytes());
+                            proof.extend_from_slice(&payload_length.to_be_bytes());
+                            for (_, sibling) in siblings {
+                                proof.extend_from_slice(&(sibling.len() as u32).to_be_bytes());
+                                proof.extend_from_slice(sibling);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        proof
+    }
+
+    /// Builds the proof for `key` from the nodes retained by a [`alloy_trie::HashBuilder`].
+    pub fn from_proof_nodes(nodes: &ProofNodes, key: &Nibbles) -> alloy_rlp::Result<Self> {
+        let mut leaf_path = None;
+        let mut steps = Vec::new();
+
+        for (prefix, encoded) in nodes.matching_nodes_sorted(key).iter().rev() {
+            match (TrieNode::decode(&mut &encoded[..])?, &leaf_path) {
+                (TrieNode::Leaf(leaf), None) => {
+                    if prefix.join(&leaf.key) != *key {
+                        return Err(alloy_rlp::Error::Custom("leaf doesn't match key"));
+                    }
+                    leaf_path = Some(leaf.key);
+                }
+                (TrieNode::Extension(extension), Some(_)) => {
+                    steps.push(ProofStep::Extension {
+                        path: extension.key,
+                    });
+                }
+                (TrieNode::Branch(branch), Some(_)) => {
+                    let index = *key
+                        .get(prefix.len())
+                        .ok_or(alloy_rlp::Error::Custom("branch at key"))?;
+                    steps.push(branch_step(&branch, index));
+                }
+                _ => return Err(alloy_rlp::Error::Custom("unexpected node on proof path")),
+            }
+        }
+
+        Ok(Self {
+            leaf_path: leaf_path.ok_or(alloy_rlp::Error::Custom("missing leaf"))?,
+            steps,
+        })
+    }
+
+    /// Rebuilds the proven nodes keyed by their path, given the `value` stored in the leaf.
+    ///
+    /// # Panics
+    ///
+    /// If a sibling is longer than a hash.
+    pub fn to_proof_nodes(&self, value: &[u8]) -> ProofNodes {
+        // Paths are only known top-down while nodes are hashed bottom-up.
+        let mut prefixes = Vec::with_capacity(self.steps.len());
+        let mut prefix = Nibbles::default();
+        for step in self.steps.iter().rev() {
+            prefixes.push(prefix.clone());
+            match step {
+                ProofStep::Extension { path } => prefix = prefix.join(path),
+                ProofStep::Branch { index, .. } => prefix.push(*index),
+            }
+        }
+
+        let mut nodes = ProofNodes::default();
+        let mut encoded = Vec::new();
+        let mut child = LeafNode::new(self.leaf_path.clone(), value.to_vec())
+            .as_ref()
+            .rlp(&mut encoded);
+        nodes.insert(prefix, Bytes::copy_from_slice(&encoded));
+
+        for (step, prefix) in self.steps.iter().zip(prefixes.into_iter().rev()) {
+            encoded.clear();
+            child = match step {
+                ProofStep::Extension { path } => ExtensionNode::new(path.clone(), child)
+                    .as_ref()
+                    .rlp(&mut encoded),
+                ProofStep::Branch {
+                    index, siblings, ..
+                } => {
+                    let mut stack = Vec::new();
+                    let mut state_mask = TrieMask::default();
+                    for (i, sibling) in (0u8..).zip(siblings.iter()) {
+                        if i == *index {
+                            stack.push(child.clone());
+                        } else if !sibling.is_empty() {
+                            stack.push(sibling_node(sibling));
+                        } else {
+                            continue;
+                        }
+                        state_mask.set_bit(i);
+                    }
+                    BranchNode::new(stack, state_mask)
+                        .as_ref()
+                        .rlp(&mut encoded)
+                }
+            };
+            nodes.insert(prefix, Bytes::copy_from_slice(&encoded));
+        }
+
+        nodes
+    }
+}
+
+fn read_path(proof: &mut Reader) -> Result<Nibbles, ProofError> {
+    let nibble_count = proof.try_read_byte()?;
+    let mut nibbles = Vec::with_capacity(usize::from(nibble_count));
+    if nibble_count % 2 == 1 {
+        nibbles.push(proof.try_read_byte()? & NIBBLE_MASK);
+    }
+    for byte in proof.try_read_next(usize::from(nibble_count / 2))? {
+        nibbles.extend_from_slice(&[byte >> 4, byte & NIBBLE_MASK]);
+    }
+    Ok(Nibbles::from_vec_unchecked(nibbles))
+}
+
+fn write_path(proof: &mut Vec<u8>, path: &Nibbles) {
+    proof.push(path.len() as u8);
+    let (odd, packed) = path.split_at(path.len() % 2);
+    proof.extend_from_slice(odd);
+    proof.extend(packed.chunks_exact(2).map(|pair| pair[0] << 4 | pair[1]));
+}
+
+fn read_branch(
+    proof: &mut Reader,
+    index: u8,
+    weird_branches: bool,
+) -> Result<ProofStep, ProofError> {
+    let branch_map = read_branch_map(proof, index)?;
+    let layout = if weird_branches {
+        BranchLayout::LengthPrefixed {
+            payload_length: read_u32_length(proof)? as u32,
+        }
+    } else {
+        BranchLayout::Hashes
+    };
+
+    let mut siblings: Box<[Bytes; 16]> = Default::default();
+    for (i, sibling) in (0u8..).zip(siblings.iter_mut()) {
+        if i == index || branch_map & (1 << i) == 0 {
+            continue;
+        }
+        let child = if weird_branches {
+            read_sibling(proof)?
+        } else {
+            proof.try_read_next(32)?
+        };
+        *sibling = Bytes::copy_from_slice(child);
+    }
+
+    Ok(ProofStep::Branch {
+        index,
+        siblings,
+        layout,
+    })
+}
+
+/// Converts a decoded branch into a step proving its child at `index`.
+fn branch_step(branch: &BranchNode, index: u8) -> ProofStep {
+    let mut siblings: Box<[Bytes; 16]> = Default::default();
+    let mut payload_length = 1;
+    let mut inline_children = false;
+
+    let mut children = branch.stack.iter();
+    for (i, sibling) in (0u8..).zip(siblings.iter_mut()) {
+        if !branch.state_mask.is_bit_set(i) {
+            payload_length += 1;
+            continue;
+        }
+        let child = children.next().expect("one child per set bit");
+        payload_length += child.len();
+        let child = match child.as_hash() {
+            Some(hash) => Bytes::copy_from_slice(hash.as_slice()),
+            None => {
+                inline_children = true;
+                Bytes::copy_from_slice(child)
+            }
+        };
+        if i != index {
+            *sibling = child;
+        }
+    }
+
+    let layout = if inline_children {
+        BranchLayout::LengthPrefixed {
+            payload_length: payload_length as u32,
+        }
+    } else {
+        BranchLayout::Hashes
+    };
+    ProofStep::Branch {
+        index,
+        siblings,
+        layout,
+    }
+}
+
+fn sibling_node(sibling: &[u8]) -> RlpNode {
+    match sibling.len() {
+        32 => RlpNode::word_rlp(&B256::from_slice(sibling)),
+        length => {
+            assert!(length < 32, "Sibling longer than a hash");
+            RlpNode::from_raw(sibling).expect("inline node")
+        }
+    }
+}
+
+struct DisplayPath<'a>(&'a Nibbles);
+
+impl std::fmt::Display for DisplayPath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for nibble in self.0.iter() {
+            write!(f, "{:x}", nibble)?;
+        }
+        write!(f, "] ({} nibbles)", self.0.len())
+    }
+}
+
+impl std::fmt::Display for CompactProof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "compact proof v{}", COMPACT_PROOF_VERSION)?;
+        write!(f, "  leaf {}", DisplayPath(&self.leaf_path))?;
+        for step in &self.steps {
+            match step {
+                ProofStep::Extension { path } => {
+                    write!(f, "\n  extension {}", DisplayPath(path))?;
+                }
+                ProofStep::Branch {
+                    index,
+                    siblings,
+                    layout,
+                } => {
+                    write!(f, "\n  branch at {:x}", index)?;
+                    if let BranchLayout::LengthPrefixed { payload_length } = layout {
+                        write!(f, ", payload length {}", payload_length)?;
+                    }
+                    for (i, sibling) in siblings.iter().enumerate() {
+                        match sibling.len() {
+                            0 => {}
+                            32 => write!(f, "\n    {:x}: hash {}", i, sibling)?,
+                            _ => write!(f, "\n    {:x}: inline {}", i, sibling)?,
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receipt_trie::tests::{blobs, Blob};
+    use crate::Keccak256;
+    use alloy_rlp::EMPTY_STRING_CODE;
+
+    fn assert_conversions_round_trip(items: &[Blob]) {
+        for (index, item) in (0u32..).zip(items) {
+            let bytes = get_proof_for_receipt(items, index);
+            let proof = CompactProof::decode(&bytes).unwrap();
+            assert_eq!(proof.encode(), bytes);
+
+            let key = Nibbles::unpack(alloy_rlp::encode(index));
+            let nodes = get_trie_proof_nodes(items, index);
+            assert_eq!(
+                CompactProof::from_proof_nodes(&nodes, &key),
+                Ok(proof.clone())
+            );
+            assert_eq!(
+                proof.to_proof_nodes(&item.0),
+                nodes.matching_nodes(&key).into_iter().collect()
+            );
+        }
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        assert_conversions_round_trip(&blobs([1]));
+        assert_conversions_round_trip(&blobs((0..130).map(|i| 40 + i % 50)));
+        assert_conversions_round_trip(&blobs((0..130).map(|i| (i * 7) % 45 + 1)));
+    }
+
+    #[test]
+    fn malformed_proofs_error() {
+        let items = blobs((0..20).map(|i| (i * 7) % 45 + 1));
+        let assert_errors = |proof: &[u8], item: &[u8], expected: ProofError| {
+            assert_eq!(CompactProof::decode(proof), Err(expected));
+            assert_eq!(
+                receipt_trie_root_from_proof(&mut Keccak256::default(), proof, item),
+                Err(expected)
+            );
+        };
+
+        let proof = get_proof_for_receipt(&items, 3);
+        let mut unversioned = proof.clone();
+        unversioned[0] = 0x02;
+        assert_errors(
+            &unversioned,
+            &items[3].0,
+            ProofError::UnsupportedVersion { version: 2 },
+        );
+
+        let mut builder = ProofBuilder::with_leaf_rest_path_compact([0x20]);
+        builder.push(0x40);
+        assert_errors(
+            &builder.build(),
+            &[0x01],
+            ProofError::InvalidNodeType {
+                position: 2,
+                node_type: 0x40,
+            },
+        );
+
+        let mut builder = ProofBuilder::with_leaf_rest_path_compact([0x20]);
+        builder.push(BRANCH_NODE_FLAG | 2);
+        builder.extend_from_slice(&0b1u16.to_be_bytes());
+        assert_errors(
+            &builder.build(),
+            &[0x01],
+            ProofError::MissingProvenChild { position: 3 },
+        );
+
+        let mut builder = ProofBuilder::with_leaf_rest_path_compact([0x20]);
+        builder.push(BRANCH_NODE_FLAG | WEIRD_BRANCHES_FLAG);
+        builder.extend_from_slice(&0b11u16.to_be_bytes());
+        builder.extend_from_slice(&70u32.to_be_bytes());
+        builder.extend_from_slice(&33u32.to_be_bytes());
+        builder.extend_from_slice(&[0; 33]);
+        assert_errors(
+            &builder.build(),
+            &[0x01],
+            ProofError::InvalidChildLength {
+                position: 9,
+                length: 33,
+            },
+        );
+
+        for inline_child in [
+            &[][..],
+            &[EMPTY_STRING_CODE, EMPTY_STRING_CODE],
+            &[0xc2, 0x20],
+        ] {
+            let mut builder = ProofBuilder::with_leaf_rest_path_compact([0x20]);
+            builder.push(BRANCH_NODE_FLAG | WEIRD_BRANCHES_FLAG);
+            builder.extend_from_slice(&0b11u16.to_be_bytes());
+            builder.extend_from_slice(&70u32.to_be_bytes());
+            builder.extend_from_slice(&(inline_child.len() as u32).to_be_bytes());
+            builder.extend_from_slice(inline_child);
+            assert_errors(
+                &builder.build(),
+                &[0x01],
+                ProofError::InvalidInlineChild { position: 9 },
+            );
+        }
+    }
+
+    #[test]
+    fn pretty_prints_steps() {
+        let mut builder = ProofBuilder::with_leaf_rest_path_compact([0x31, 0xab]);
+        let mut children = [&[][..]; 16];
+        children[4] = &[0xab; 32];
+        children[12] = &[0xab; 32];
+        builder.add_branch(12, children);
+        children[3] = &[0xc2, 0x20, 0x01];
+        builder.add_branch(4, children);
+        builder.add_extension([0x00, 0x81]);
+
+        let proof = CompactProof::decode(&builder.build()).unwrap();
+        assert_eq!(
+            proof.to_string(),
+            "compact proof v1
+  leaf [1ab] (3 nibbles)
+  branch at c
+    4: hash 0xabababababababababababababababababababababababababababababababab
+  branch at 4, payload length 83
+    3: inline 0xc22001
+    c: hash 0xabababababababababababababababababababababababababababababababab
+  extension [81] (2 nibbles)"
+        );
+    }
+}
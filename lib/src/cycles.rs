@@ -0,0 +1,218 @@
+//! Cycle tracking of the phases of payload validation. With the `cycle-tracker` feature enabled the
+//! guest reports the start and end of every phase to the SP1 executor, which sums up the cycles
+//! spent in each phase in its execution report.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Phase of payload validation tracked separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    HeaderHashing,
+    ReceiptEncoding,
+    ReceiptProof,
+    TransactionEncoding,
+    /// Transaction proof verification and hashing of the transaction.
+    TransactionProof,
+    FeeSummaryHashing,
+    FeeAggregation,
+    StateProof,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 8] = [
+        Self::HeaderHashing,
+        Self::ReceiptEncoding,
+        Self::ReceiptProof,
+        Self::TransactionEncoding,
+        Self::TransactionProof,
+        Self::FeeSummaryHashing,
+        Self::FeeAggregation,
+        Self::StateProof,
+    ];
+
+    /// Name the phase is reported under.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::HeaderHashing => "header_hashing",
+            Self::ReceiptEncoding => "receipt_encoding",
+            Self::ReceiptProof => "receipt_proof",
+            Self::TransactionEncoding => "transaction_encoding",
+            Self::TransactionProof => "transaction_proof",
+            Self::FeeSummaryHashing => "fee_summary_hashing",
+            Self::FeeAggregation => "fee_aggregation",
+            Self::StateProof => "state_proof",
+        }
+    }
+
+    /// Whether the phase runs once per reward block.
+    const fn per_reward_block(self) -> bool {
+        !matches!(
+            self,
+            Self::HeaderHashing | Self::FeeAggregation | Self::StateProof
+        )
+    }
+}
+
+/// Counts the cycles until it's dropped towards its phase. Only reports in the zkVM, the native
+/// validation of the script shares the library.
+#[must_use]
+#[cfg_attr(
+    not(all(feature = "cycle-tracker", target_os = "zkvm")),
+    allow(dead_code)
+)]
+pub(crate) struct Span(Phase);
+
+pub(crate) fn span(phase: Phase) -> Span {
+    #[cfg(all(feature = "cycle-tracker", target_os = "zkvm"))]
+    println!("cycle-tracker-report-start: {}", phase.name());
+    Span(phase)
+}
+
+#[cfg(all(feature = "cycle-tracker", target_os = "zkvm"))]
+impl Drop for Span {
+    fn drop(&mut self) {
+        println!("cycle-tracker-report-end: {}", self.0.name());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PhaseCycles {
+    pub phase: &'static str,
+    pub cycles: u64,
+}
+
+/// Breakdown of the cycles of an execution by phase and by unit of work.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CycleReport {
+    pub total: u64,
+    /// Cycles of every phase in the order of [`Phase::ALL`], zero for phases that weren't tracked.
+    pub phases: Vec<PhaseCycles>,
+    /// Cycles not spent in any of the tracked phases, such as reading the payload.
+    pub untracked: u64,
+    pub headers: u64,
+    pub reward_blocks: u64,
+    pub fee_entries: u64,
+    /// Header hashing cycles per header.
+    pub cycles_per_header: u64,
+    /// Cycles of the phases run for every reward block, per reward block.
+    pub cycles_per_reward_block: u64,
+    /// Fee summary hashing and aggregation cycles per fee entry.
+    pub cycles_per_fee_entry: u64,
+}
+
+impl CycleReport {
+    /// Builds the report from the executor's `cycle_tracker` totals by phase name.
+    pub fn new(
+        cycle_tracker: impl IntoIterator<Item = (impl AsRef<str>, u64)>,
+        total: u64,
+        headers: u64,
+        reward_blocks: u64,
+        fee_entries: u64,
+    ) -> Self {
+        let cycle_tracker: HashMap<_, _> = cycle_tracker
+            .into_iter()
+            .map(|(phase, cycles)| (phase.as_ref().to_string(), cycles))
+            .collect();
+        let cycles = |phase: Phase| cycle_tracker.get(phase.name()).copied().unwrap_or(0);
+        let phases: Vec<_> = Phase::ALL
+            .into_iter()
+            .map(|phase| PhaseCycles {
+                phase: phase.name(),
+                cycles: cycles(phase),
+            })
+            .collect();
+        let tracked: u64 = phases.iter().map(|phase| phase.cycles).sum();
+        let reward_block_cycles: u64 = Phase::ALL
+            .into_iter()
+            .filter(|phase| phase.per_reward_block())
+            .map(cycles)
+            .sum();
+        let fee_entry_cycles = cycles(Phase::FeeSummaryHashing) + cycles(Phase::FeeAggregation);
+
+        Self {
+            total,
+            phases,
+            untracked: total.saturating_sub(tracked),
+            headers,
+            reward_blocks,
+            fee_entries,
+            cycles_per_header: cycles(Phase::HeaderHashing) / headers.max(1),
+            cycles_per_reward_block: reward_block_cycles / reward_blocks.max(1),
+            cycles_per_fee_entry: fee_entry_cycles / fee_entries.max(1),
+        }
+    }
+}
+
+impl std::fmt::Display for CycleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let share = |cycles: u64| cycles as f64 * 100.0 / self.total.max(1) as f64;
+        writeln!(f, "{:<22} {:>14} {:>7}", "phase", "cycles", "share")?;
+        for PhaseCycles { phase, cycles } in &self.phases {
+            writeln!(f, "{:<22} {:>14} {:>6.2}%", phase, cycles, share(*cycles))?;
+        }
+        writeln!(
+            f,
+            "{:<22} {:>14} {:>6.2}%",
+            "untracked",
+            self.untracked,
+            share(self.untracked)
+        )?;
+        writeln!(f, "{:<22} {:>14}", "total", self.total)?;
+        writeln!(
+            f,
+            "per header:       {:>10} ({} headers)",
+            self.cycles_per_header, self.headers
+        )?;
+        writeln!(
+            f,
+            "per reward block: {:>10} ({} reward blocks)",
+            self.cycles_per_reward_block, self.reward_blocks
+        )?;
+        write!(
+            f,
+            "per fee entry:    {:>10} ({} fee entries)",
+            self.cycles_per_fee_entry, self.fee_entries
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaks_down_cycles() {
+        let cycle_tracker = [
+            ("header_hashing", 4_000),
+            ("receipt_encoding", 300),
+            ("receipt_proof", 1_200),
+            ("transaction_proof", 500),
+            ("fee_summary_hashing", 200),
+            ("fee_aggregation", 400),
+            ("unrelated", 1_000_000),
+        ];
+
+        let report = CycleReport::new(cycle_tracker, 10_000, 4, 2, 6);
+        assert_eq!(report.phases.len(), Phase::ALL.len());
+        assert_eq!(
+            report.phases[3],
+            PhaseCycles {
+                phase: "transaction_encoding",
+                cycles: 0
+            }
+        );
+        assert_eq!(report.untracked, 3_400);
+        assert_eq!(report.cycles_per_header, 1_000);
+        assert_eq!(report.cycles_per_reward_block, 1_100);
+        assert_eq!(report.cycles_per_fee_entry, 100);
+
+        let table = report.to_string();
+        assert!(table.contains("receipt_proof                    1200  12.00%"));
+        assert!(table.contains("per fee entry:           100 (6 fee entries)"));
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["phases"][0]["phase"], "header_hashing");
+        assert_eq!(json["cycles_per_reward_block"], 1_100);
+    }
+}
@@ -0,0 +1,701 @@
+//! Reading of [Era1](https://github.com/ethereum/go-ethereum/blob/master/internal/era/era.go)
+//! archive files of pre-merge blocks into the [`Cache`].
+//!
+//! An Era1 file is a sequence of e2store entries, each made up of a little-endian `u16` type, a
+//! little-endian `u32` data length, two reserved zero bytes and the data:
+//!
+//! ```text
+//! Version | (CompressedHeader | CompressedBody | CompressedReceipts | TotalDifficulty)* |
+//!     Accumulator | BlockIndex
+//! ```
+//!
+//! Headers, bodies and receipts are RLP encoded and compressed with the snappy framing format. The
+//! accumulator is the SSZ root of the list of `(block hash, total difficulty)` records of the file's
+//! blocks, committing to all of its headers. As the file carries its own accumulator, the headers are
+//! checked against a trusted root instead, such as one of the published list of canonical Era1
+//! accumulator roots.
+
+use crate::cache::{BlockStore, Cache, SmolBlock};
+use alloy_consensus::proofs::{calculate_receipt_root, calculate_transaction_root};
+use alloy_consensus::{Header, ReceiptEnvelope, TxEnvelope};
+use alloy_eips::eip2718::{Decodable2718, Eip2718Error};
+use alloy_primitives::{BlockNumber, B256, U256};
+use alloy_rlp::Decodable;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::ops::RangeInclusive;
+
+const VERSION: u16 = 0x3265;
+const COMPRESSED_HEADER: u16 = 0x03;
+const COMPRESSED_BODY: u16 = 0x04;
+const COMPRESSED_RECEIPTS: u16 = 0x05;
+const TOTAL_DIFFICULTY: u16 = 0x06;
+const ACCUMULATOR: u16 = 0x07;
+const BLOCK_INDEX: u16 = 0x3266;
+
+const ENTRY_HEADER_SIZE: usize = 8;
+
+/// Maximum number of blocks in an Era1 file, the limit of the accumulator's SSZ list.
+pub const MAX_ERA1_BLOCKS: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Era1Error {
+    /// The file ends in the middle of an entry or before the block index.
+    Truncated {
+        offset: usize,
+    },
+    UnexpectedEntry {
+        offset: usize,
+        expected: u16,
+        found: u16,
+    },
+    Decompression {
+        offset: usize,
+        error: String,
+    },
+    Malformed {
+        offset: usize,
+        error: alloy_rlp::Error,
+    },
+    TooManyBlocks {
+        blocks: usize,
+    },
+    NonConsecutiveBlock {
+        number: BlockNumber,
+        expected: BlockNumber,
+    },
+    /// Length of the block index doesn't match its count of offsets.
+    BlockIndexLength {
+        length: usize,
+        count: u64,
+    },
+    /// Start or count of the block index don't match the blocks in the file.
+    BlockIndexMismatch {
+        start: BlockNumber,
+        count: u64,
+    },
+    TransactionsRootMismatch {
+        number: BlockNumber,
+        computed: B256,
+        expected: B256,
+    },
+    ReceiptsRootMismatch {
+        number: BlockNumber,
+        computed: B256,
+        expected: B256,
+    },
+    /// The file's accumulator isn't the trusted one.
+    UntrustedAccumulator {
+        accumulator: B256,
+        trusted: B256,
+    },
+    AccumulatorMismatch {
+        computed: B256,
+        expected: B256,
+    },
+}
+
+impl std::fmt::Display for Era1Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated { offset } => write!(f, "file truncated at offset {}", offset),
+            Self::UnexpectedEntry {
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected entry of type {:#06x} at offset {}, found {:#06x}",
+                expected, offset, found
+            ),
+            Self::Decompression { offset, error } => write!(
+                f,
+                "failed to decompress entry at offset {}: {}",
+                offset, error
+            ),
+            Self::Malformed { offset, error } => {
+                write!(f, "malformed entry at offset {}: {}", offset, error)
+            }
+            Self::TooManyBlocks { blocks } => write!(
+                f,
+                "{} blocks exceed the limit of {}",
+                blocks, MAX_ERA1_BLOCKS
+            ),
+            Self::NonConsecutiveBlock { number, expected } => {
+                write!(f, "expected block #{}, found #{}", expected, number)
+            }
+            Self::BlockIndexLength { length, count } => write!(
+                f,
+                "block index of {} bytes doesn't hold {} offsets",
+                length, count
+            ),
+            Self::BlockIndexMismatch { start, count } => write!(
+                f,
+                "block index of {} blocks from #{} doesn't match the file's blocks",
+                count, start
+            ),
+            Self::TransactionsRootMismatch {
+                number,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "block #{}: transactions root {} of body does not match {}",
+                number, computed, expected
+            ),
+            Self::ReceiptsRootMismatch {
+                number,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "block #{}: receipts root {} of receipts does not match {}",
+                number, computed, expected
+            ),
+            Self::UntrustedAccumulator {
+                accumulator,
+                trusted,
+            } => write!(
+                f,
+                "accumulator root {} of file is not the trusted root {}",
+                accumulator, trusted
+            ),
+            Self::AccumulatorMismatch { computed, expected } => write!(
+                f,
+                "accumulator root {} of headers does not match {}",
+                computed, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Era1Error {}
+
+/// Block of an Era1 file with its body and receipts.
+#[derive(Debug, Clone)]
+pub struct Era1Block {
+    pub block: SmolBlock,
+    pub transactions: Vec<TxEnvelope>,
+    pub receipts: Vec<ReceiptEnvelope>,
+    pub total_difficulty: U256,
+}
+
+/// Reads the e2store entries of a file.
+struct Entries<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Entries<'a> {
+    fn peek_type(&self) -> Option<u16> {
+        let header = self.bytes.get(self.offset..self.offset + 2)?;
+        Some(u16::from_le_bytes(header.try_into().unwrap()))
+    }
+
+    /// Reads the next entry, which has to be of type `expected`. Returns its offset and data.
+    fn next(&mut self, expected: u16) -> Result<(usize, &'a [u8]), Era1Error> {
+        let offset = self.offset;
+        let truncated = Era1Error::Truncated { offset };
+        let header = self
+            .bytes
+            .get(offset..offset + ENTRY_HEADER_SIZE)
+            .ok_or(truncated.clone())?;
+        let found = u16::from_le_bytes(header[..2].try_into().unwrap());
+        if found != expected {
+            return Err(Era1Error::UnexpectedEntry {
+                offset,
+                expected,
+                found,
+            });
+        }
+        let length = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+        let start = offset + ENTRY_HEADER_SIZE;
+        let data = self.bytes.get(start..start + length).ok_or(truncated)?;
+        self.offset = start + length;
+        Ok((offset, data))
+    }
+
+    fn next_decompressed(&mut self, expected: u16) -> Result<(usize, Vec<u8>), Era1Error> {
+        let (offset, data) = self.next(expected)?;
+        let mut decompressed = Vec::new();
+        snap::read::FrameDecoder::new(data)
+            .read_to_end(&mut decompressed)
+            .map_err(|err| Era1Error::Decompression {
+                offset,
+                error: err.to_string(),
+            })?;
+        Ok((offset, decompressed))
+    }
+}
+
+fn malformed(offset: usize) -> impl Fn(alloy_rlp::Error) -> Era1Error {
+    move |error| Era1Error::Malformed { offset, error }
+}
+
+/// Decodes the payload of an RLP list of items in the network encoding, where typed items are
+/// wrapped in an RLP string.
+fn decode_network_items<T: Decodable2718>(mut payload: &[u8]) -> alloy_rlp::Result<Vec<T>> {
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        items.push(T::network_decode(&mut payload).map_err(|err| match err {
+            Eip2718Error::RlpError(err) => err,
+            Eip2718Error::UnexpectedType(_) => alloy_rlp::Error::Custom("unexpected type"),
+            _ => alloy_rlp::Error::Custom("invalid eip-2718 item"),
+        })?);
+    }
+    Ok(items)
+}
+
+fn decode_receipts(mut buf: &[u8]) -> alloy_rlp::Result<Vec<ReceiptEnvelope>> {
+    let receipts = alloy_rlp::Header::decode_bytes(&mut buf, true)?;
+    if !buf.is_empty() {
+        return Err(alloy_rlp::Error::UnexpectedLength);
+    }
+    decode_network_items(receipts)
+}
+
+/// Decodes the transactions of a body `[transactions, ommers]`.
+fn decode_body_transactions(mut buf: &[u8]) -> alloy_rlp::Result<Vec<TxEnvelope>> {
+    let mut body = alloy_rlp::Header::decode_bytes(&mut buf, true)?;
+    let transactions = alloy_rlp::Header::decode_bytes(&mut body, true)?;
+    decode_network_items(transactions)
+}
+
+fn sha256_pair(left: &[u8], right: &[u8]) -> B256 {
+    B256::from_slice(
+        &Sha256::new()
+            .chain_update(left)
+            .chain_update(right)
+            .finalize(),
+    )
+}
+
+/// SSZ root of the `List[HeaderRecord, 8192]` of `(block hash, total difficulty)` records.
+pub fn accumulator_root(records: &[(B256, U256)]) -> B256 {
+    assert!(records.len() <= MAX_ERA1_BLOCKS, "Too many header records");
+    let mut layer: Vec<B256> = records
+        .iter()
+        .map(|(hash, td)| sha256_pair(hash.as_slice(), &td.to_le_bytes::<32>()))
+        .collect();
+
+    // Merkleize the records padded with zero chunks to the list limit.
+    let mut zero = B256::ZERO;
+    for _ in 0..MAX_ERA1_BLOCKS.trailing_zeros() {
+        if layer.len() % 2 == 1 {
+            layer.push(zero);
+        }
+        layer = layer
+            .chunks_exact(2)
+            .map(|pair| sha256_pair(pair[0].as_slice(), pair[1].as_slice()))
+            .collect();
+        zero = sha256_pair(zero.as_slice(), zero.as_slice());
+    }
+    let root = layer.first().copied().unwrap_or(zero);
+
+    sha256_pair(
+        root.as_slice(),
+        &U256::from(records.len()).to_le_bytes::<32>(),
+    )
+}
+
+/// Reads the blocks of an Era1 file, checking that the bodies and receipts match the headers and
+/// that the headers match the trusted accumulator root `accumulator`.
+pub fn read_era1(bytes: &[u8], accumulator: B256) -> Result<Vec<Era1Block>, Era1Error> {
+    let mut entries = Entries { bytes, offset: 0 };
+    entries.next(VERSION)?;
+
+    let mut blocks: Vec<Era1Block> = Vec::new();
+    while entries.peek_type() == Some(COMPRESSED_HEADER) {
+        if blocks.len() == MAX_ERA1_BLOCKS {
+            return Err(Era1Error::TooManyBlocks {
+                blocks: blocks.len() + 1,
+            });
+        }
+
+        let (offset, header) = entries.next_decompressed(COMPRESSED_HEADER)?;
+        let header = Header::decode(&mut header.as_slice()).map_err(malformed(offset))?;
+        if let Some(previous) = blocks.last() {
+            let expected = previous.block.number + 1;
+            if header.number != expected {
+                return Err(Era1Error::NonConsecutiveBlock {
+                    number: header.number,
+                    expected,
+                });
+            }
+        }
+
+        let (offset, body) = entries.next_decompressed(COMPRESSED_BODY)?;
+        let transactions = decode_body_transactions(&body).map_err(malformed(offset))?;
+        let computed = calculate_transaction_root(&transactions);
+        if computed != header.transactions_root {
+            return Err(Era1Error::TransactionsRootMismatch {
+                number: header.number,
+                computed,
+                expected: header.transactions_root,
+            });
+        }
+
+        let (offset, receipts) = entries.next_decompressed(COMPRESSED_RECEIPTS)?;
+        let receipts = decode_receipts(&receipts).map_err(malformed(offset))?;
+        let computed = calculate_receipt_root(&receipts);
+        if computed != header.receipts_root {
+            return Err(Era1Error::ReceiptsRootMismatch {
+                number: header.number,
+                computed,
+                expected: header.receipts_root,
+            });
+        }
+
+        let (offset, total_difficulty) = entries.next(TOTAL_DIFFICULTY)?;
+        let total_difficulty: [u8; 32] =
+            total_difficulty
+                .try_into()
+                .map_err(|_| Era1Error::Malformed {
+                    offset,
+                    error: alloy_rlp::Error::UnexpectedLength,
+                })?;
+
+        let txs = transactions.iter().map(|tx| *tx.tx_hash()).collect();
+        blocks.push(Era1Block {
+            block: SmolBlock::new(header, txs),
+            transactions,
+            receipts,
+            total_difficulty: U256::from_le_bytes(total_difficulty),
+        });
+    }
+
+    let (offset, file_accumulator) = entries.next(ACCUMULATOR)?;
+    let file_accumulator = B256::try_from(file_accumulator).map_err(|_| Era1Error::Malformed {
+        offset,
+        error: alloy_rlp::Error::UnexpectedLength,
+    })?;
+    if file_accumulator != accumulator {
+        return Err(Era1Error::UntrustedAccumulator {
+            accumulator: file_accumulator,
+            trusted: accumulator,
+        });
+    }
+
+    // The block index is the start, an offset per block and the count, all `u64`.
+    let (offset, index) = entries.next(BLOCK_INDEX)?;
+    if index.len() < 16 {
+        return Err(Era1Error::Truncated { offset });
+    }
+    let count = u64::from_le_bytes(index[index.len() - 8..].try_into().unwrap());
+    let expected_length = count
+        .checked_mul(8)
+        .and_then(|offsets| offsets.checked_add(16));
+    if expected_length != Some(index.len() as u64) {
+        return Err(Era1Error::BlockIndexLength {
+            length: index.len(),
+            count,
+        });
+    }
+    let start = u64::from_le_bytes(index[..8].try_into().unwrap());
+    let first = blocks.first().map(|block| block.block.number);
+    if count != blocks.len() as u64 || first.is_some_and(|first| first != start) {
+        return Err(Era1Error::BlockIndexMismatch { start, count });
+    }
+
+    let records: Vec<_> = blocks
+        .iter()
+        .map(|block| (block.block.hash_slow(), block.total_difficulty))
+        .collect();
+    let computed = accumulator_root(&records);
+    if computed != accumulator {
+        return Err(Era1Error::AccumulatorMismatch {
+            computed,
+            expected: accumulator,
+        });
+    }
+
+    Ok(blocks)
+}
+
+impl<S: BlockStore> Cache<S> {
+    /// Imports the blocks of the Era1 file `bytes` with their transactions and receipts as
+    /// confirmed blocks, replacing any cached data of the same blocks. Nothing is imported if the
+    /// file doesn't check out against the trusted accumulator root `accumulator`. Returns the range
+    /// of imported blocks.
+    pub fn import_era1(
+        &mut self,
+        bytes: &[u8],
+        accumulator: B256,
+    ) -> Result<Option<RangeInclusive<BlockNumber>>, Era1Error> {
+        let blocks = read_era1(bytes, accumulator)?;
+        let range = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => Some(first.block.number..=last.block.number),
+            _ => None,
+        };
+
+        for era1_block in blocks {
+            let bn = era1_block.block.number;
+            let cached = self.get_block(bn).is_some()
+                || self.get_receipts(bn).is_some()
+                || self.get_transactions(bn).is_some();
+            if cached {
+                self.evict_block(bn);
+            }
+            self.append_blocks([era1_block.block]);
+            self.append_transactions(bn, era1_block.transactions);
+            self.append_receipts(bn, era1_block.receipts);
+            self.confirm(bn);
+        }
+        Ok(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryStore;
+    use alloy_consensus::{Receipt, SignableTransaction, TxEip1559, TxLegacy};
+    use alloy_eips::eip2718::Encodable2718;
+    use alloy_primitives::{Log, PrimitiveSignature as Signature};
+    use std::io::Write;
+
+    fn entry(out: &mut Vec<u8>, ty: u16, data: &[u8]) {
+        out.extend_from_slice(&ty.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(data);
+    }
+
+    fn compressed(data: &[u8]) -> Vec<u8> {
+        let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+        encoder.write_all(data).unwrap();
+        encoder.into_inner().unwrap()
+    }
+
+    fn network_list<T: Encodable2718>(items: &[T]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        items
+            .iter()
+            .for_each(|item| item.network_encode(&mut payload));
+        let mut out = Vec::new();
+        alloy_rlp::Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut out);
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    /// Encodes `blocks` as an Era1 file with the given accumulator root.
+    fn era1_file(blocks: &[Era1Block], accumulator: B256) -> Vec<u8> {
+        let mut out = Vec::new();
+        entry(&mut out, VERSION, &[]);
+        for block in blocks {
+            entry(
+                &mut out,
+                COMPRESSED_HEADER,
+                &compressed(&alloy_rlp::encode(&block.block.header)),
+            );
+            let mut body = network_list(&block.transactions);
+            body.push(alloy_rlp::EMPTY_LIST_CODE);
+            let mut body_list = Vec::new();
+            alloy_rlp::Header {
+                list: true,
+                payload_length: body.len(),
+            }
+            .encode(&mut body_list);
+            body_list.extend_from_slice(&body);
+            entry(&mut out, COMPRESSED_BODY, &compressed(&body_list));
+            entry(
+                &mut out,
+                COMPRESSED_RECEIPTS,
+                &compressed(&network_list(&block.receipts)),
+            );
+            entry(
+                &mut out,
+                TOTAL_DIFFICULTY,
+                &block.total_difficulty.to_le_bytes::<32>(),
+            );
+        }
+        entry(&mut out, ACCUMULATOR, accumulator.as_slice());
+
+        let start = blocks.first().map_or(0, |block| block.block.number);
+        let mut index = start.to_le_bytes().to_vec();
+        index.extend(blocks.iter().flat_map(|_| 0u64.to_le_bytes()));
+        index.extend_from_slice(&(blocks.len() as u64).to_le_bytes());
+        entry(&mut out, BLOCK_INDEX, &index);
+        out
+    }
+
+    fn accumulator(blocks: &[Era1Block]) -> B256 {
+        let records: Vec<_> = blocks
+            .iter()
+            .map(|block| (block.block.hash_slow(), block.total_difficulty))
+            .collect();
+        accumulator_root(&records)
+    }
+
+    /// Three consecutive blocks with legacy and EIP-1559 transactions and receipts.
+    fn era1_blocks() -> Vec<Era1Block> {
+        let mut parent_hash = B256::repeat_byte(0x11);
+        (1000..1003)
+            .map(|number| {
+                let transactions = vec![
+                    TxEnvelope::Legacy(
+                        TxLegacy {
+                            nonce: number,
+                            ..Default::default()
+                        }
+                        .into_signed(Signature::test_signature()),
+                    ),
+                    TxEnvelope::Eip1559(
+                        TxEip1559 {
+                            nonce: number,
+                            ..Default::default()
+                        }
+                        .into_signed(Signature::test_signature()),
+                    ),
+                ];
+                let receipt = |cumulative_gas_used| {
+                    Receipt {
+                        status: true.into(),
+                        cumulative_gas_used,
+                        logs: vec![Log::empty()],
+                    }
+                    .with_bloom()
+                };
+                let receipts = vec![
+                    ReceiptEnvelope::Legacy(receipt(21_000)),
+                    ReceiptEnvelope::Eip1559(receipt(42_000)),
+                ];
+                let header = Header {
+                    number,
+                    parent_hash,
+                    transactions_root: calculate_transaction_root(&transactions),
+                    receipts_root: calculate_receipt_root(&receipts),
+                    ..Default::default()
+                };
+                parent_hash = header.hash_slow();
+                let txs = transactions.iter().map(|tx| *tx.tx_hash()).collect();
+                Era1Block {
+                    block: SmolBlock::new(header, txs),
+                    transactions,
+                    receipts,
+                    total_difficulty: U256::from(number * 17),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn imports_blocks_checked_against_accumulator() {
+        let blocks = era1_blocks();
+        let accumulator_root = accumulator(&blocks);
+        let file = era1_file(&blocks, accumulator_root);
+
+        let mut cache = Cache::new(MemoryStore::default());
+        assert_eq!(
+            cache.import_era1(&file, accumulator_root),
+            Ok(Some(1000..=1002))
+        );
+        for block in &blocks {
+            let number = block.block.number;
+            assert_eq!(
+                cache.get_block(number).unwrap().hash_slow(),
+                block.block.hash_slow()
+            );
+            assert_eq!(cache.get_block(number).unwrap().txs, block.block.txs);
+            assert_eq!(*cache.get_receipts(number).unwrap(), block.receipts);
+            assert_eq!(*cache.get_transactions(number).unwrap(), block.transactions);
+            assert!(cache.is_confirmed(number));
+        }
+        assert_eq!(cache.verify(), []);
+
+        // Importing again replaces instead of appending to the cached lists.
+        cache.import_era1(&file, accumulator_root).unwrap();
+        assert_eq!(cache.get_receipts(1001).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_inconsistent_files() {
+        let blocks = era1_blocks();
+        let accumulator_root = accumulator(&blocks);
+        let mut cache = Cache::new(MemoryStore::default());
+
+        let mut tampered = blocks.clone();
+        tampered[1].total_difficulty += U256::from(1);
+        assert_eq!(
+            cache.import_era1(&era1_file(&tampered, accumulator_root), accumulator_root),
+            Err(Era1Error::AccumulatorMismatch {
+                computed: accumulator(&tampered),
+                expected: accumulator_root,
+            })
+        );
+
+        let mut tampered = blocks.clone();
+        tampered[2].receipts.swap(0, 1);
+        assert!(matches!(
+            cache.import_era1(&era1_file(&tampered, accumulator_root), accumulator_root),
+            Err(Era1Error::ReceiptsRootMismatch { number: 1002, .. })
+        ));
+
+        let mut tampered = blocks.clone();
+        tampered[0].transactions.pop();
+        assert!(matches!(
+            cache.import_era1(&era1_file(&tampered, accumulator_root), accumulator_root),
+            Err(Era1Error::TransactionsRootMismatch { number: 1000, .. })
+        ));
+
+        let file = era1_file(&blocks, accumulator_root);
+        assert!(matches!(
+            cache.import_era1(&file[..file.len() - 3], accumulator_root),
+            Err(Era1Error::Truncated { .. })
+        ));
+
+        // A file consistent with its own accumulator is still rejected if that isn't trusted.
+        let mut tampered = blocks.clone();
+        tampered[1].total_difficulty += U256::from(1);
+        let tampered_root = accumulator(&tampered);
+        assert_eq!(
+            cache.import_era1(&era1_file(&tampered, tampered_root), accumulator_root),
+            Err(Era1Error::UntrustedAccumulator {
+                accumulator: tampered_root,
+                trusted: accumulator_root,
+            })
+        );
+
+        // The count closes the file, after the offsets of the three blocks.
+        for count in [4, u64::MAX] {
+            let mut file = file.clone();
+            let end = file.len();
+            file[end - 8..].copy_from_slice(&count.to_le_bytes());
+            assert_eq!(
+                cache.import_era1(&file, accumulator_root),
+                Err(Era1Error::BlockIndexLength { length: 40, count })
+            );
+        }
+        assert!(cache.get_block(1000).is_none());
+    }
+
+    #[test]
+    fn accumulator_root_matches_full_merkleization() {
+        let records: Vec<_> = (0u8..3)
+            .map(|i| (B256::repeat_byte(i), U256::from(i) << 100usize))
+            .collect();
+
+        let mut layer: Vec<B256> = (0..MAX_ERA1_BLOCKS)
+            .map(|i| match records.get(i) {
+                Some((hash, td)) => sha256_pair(hash.as_slice(), &td.to_le_bytes::<32>()),
+                None => B256::ZERO,
+            })
+            .collect();
+        while layer.len() > 1 {
+            layer = layer
+                .chunks(2)
+                .map(|pair| sha256_pair(pair[0].as_slice(), pair[1].as_slice()))
+                .collect();
+        }
+        let mut length = [0; 32];
+        length[0] = 3;
+        assert_eq!(
+            accumulator_root(&records),
+            sha256_pair(layer[0].as_slice(), &length)
+        );
+    }
+}
@@ -1,78 +1,162 @@
+use crate::lazy_header::{HeaderField, RLPListInspector, ReceiptsRoot, RequestsHash};
 use crate::rlp::*;
-use crate::Reader;
+use crate::{Reader, ReaderError};
 use alloy_primitives::{keccak256, B256};
+use std::marker::PhantomData;
 use std::ops::Deref;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderLensError {
+    Truncated(ReaderError),
+    /// Header is not encoded as a long RLP list.
+    InvalidListHead {
+        head: u8,
+    },
+    /// Field at `position` (relative to the start of the header) is not encoded as expected.
+    InvalidFieldHead {
+        position: usize,
+        expected: u8,
+        found: u8,
+    },
+    /// Integer field at `position` is too long or not canonically encoded.
+    InvalidIntegerField {
+        position: usize,
+    },
+    /// Byte string field at `position` is not canonically encoded.
+    InvalidStringField {
+        position: usize,
+    },
+    /// Header list continues past its last known field.
+    TrailingBytes {
+        position: usize,
+    },
+}
+
+impl From<ReaderError> for HeaderLensError {
+    fn from(err: ReaderError) -> Self {
+        Self::Truncated(err)
+    }
+}
+
+impl std::fmt::Display for HeaderLensError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated(err) => write!(f, "truncated header: {}", err),
+            Self::InvalidListHead { head } => {
+                write!(f, "Invalid head byte {:x} for encoded header", head)
+            }
+            Self::InvalidFieldHead {
+                position,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected string header byte {:x} not {:x} at position {}",
+                expected, found, position
+            ),
+            Self::InvalidIntegerField { position } => {
+                write!(f, "Invalid integer encoding at position {}", position)
+            }
+            Self::InvalidStringField { position } => {
+                write!(f, "Invalid string encoding at position {}", position)
+            }
+            Self::TrailingBytes { position } => {
+                write!(f, "Unexpected trailing bytes at position {}", position)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeaderLensError {}
+
 /// Tracks an already RLP encoded, partially validated header. Only validates that the encoding is
-/// valid up to the `receipts_root` field.
+/// valid up to the field `V`, fields up to it can be accessed via the [`crate::lazy_header`]
+/// inspector traits.
 #[derive(Debug, Clone)]
-pub struct EncodedHeaderLens<'bytes> {
+pub struct EncodedHeaderLens<'bytes, V = ReceiptsRoot> {
     encoded: &'bytes [u8],
     payload_offset: usize,
+    validated: PhantomData<V>,
 }
 
-impl<'bytes> EncodedHeaderLens<'bytes> {
+impl<'bytes, V: HeaderField> EncodedHeaderLens<'bytes, V> {
     pub fn hash(&self) -> B256 {
         keccak256(self)
     }
 
-    pub fn read_from(reader: &mut Reader<'bytes>) -> Result<Self, String> {
-        let head = reader[0];
+    pub fn read_from(reader: &mut Reader<'bytes>) -> Result<Self, HeaderLensError> {
+        let mut header_reader = reader.clone();
+
+        let head = header_reader.try_read_byte()?;
         let length_bytes = if head > RLP_LIST_OFFSET + RLP_MAX_PACKED_LEN {
             usize::from(head - RLP_LIST_OFFSET - RLP_MAX_PACKED_LEN)
         } else {
-            return Err(format!("Invalid head byte {:x} for encoded header", head));
+            return Err(HeaderLensError::InvalidListHead { head });
         };
+        if length_bytes > std::mem::size_of::<usize>() {
+            return Err(HeaderLensError::InvalidListHead { head });
+        }
         let mut length: usize = 0;
-        for i in 0..length_bytes {
-            length = (256 * length) + usize::from(reader[i + 1]);
+        for _ in 0..length_bytes {
+            length = (length << 8) | usize::from(header_reader.try_read_byte()?);
         }
 
         let payload_offset = 1 + length_bytes;
-        let encoded = reader.read_next(payload_offset + length);
-
-        let mut payload_reader = Reader::from(&encoded[payload_offset..]);
+        let encoded = reader.try_read_next(payload_offset.saturating_add(length))?;
 
-        Self::validate_small_fixed_field::<32>(&mut payload_reader)?; // parent_hash
-        Self::validate_small_fixed_field::<32>(&mut payload_reader)?; // ommers_hash
-        Self::validate_small_fixed_field::<20>(&mut payload_reader)?; // beneficiary
-        Self::validate_small_fixed_field::<32>(&mut payload_reader)?; // state_root
-        Self::validate_small_fixed_field::<32>(&mut payload_reader)?; // transactions_root
-        Self::validate_small_fixed_field::<32>(&mut payload_reader)?; // receipts_root
+        let mut payload_reader = Reader::from(encoded);
+        payload_reader.try_read_next(payload_offset)?;
+        V::validate_through(&mut payload_reader)?;
 
         Ok(Self {
             encoded,
             payload_offset,
+            validated: PhantomData,
         })
     }
 
-    pub fn parent_hash(&self) -> &[u8; 32] {
-        self.encoded[self.payload_offset + 1..][..32]
-            .try_into()
-            .unwrap()
-    }
+    /// Validates the fields up to `W`, allowing them to be inspected.
+    pub fn validate_through<W: HeaderField>(
+        self,
+    ) -> Result<EncodedHeaderLens<'bytes, W>, HeaderLensError> {
+        let mut payload_reader = Reader::from(self.encoded);
+        payload_reader.try_read_next(self.payload_offset)?;
+        W::validate_through(&mut payload_reader)?;
 
-    pub fn receipts_root(&self) -> &[u8; 32] {
-        self.encoded[self.payload_offset + 33 + 33 + 21 + 33 + 33 + 1..][..32]
-            .try_into()
-            .unwrap()
+        Ok(EncodedHeaderLens {
+            encoded: self.encoded,
+            payload_offset: self.payload_offset,
+            validated: PhantomData,
+        })
     }
 
-    fn validate_small_fixed_field<const N: u8>(payload_reader: &mut Reader) -> Result<(), String> {
-        let expected_byte = RLP_STR_OFFSET + N;
-        let byte = payload_reader[0];
-        if byte != expected_byte {
-            return Err(format!(
-                "Expected string header byte {:x} not {:x}",
-                expected_byte, byte
-            ));
+    /// Validates every field of the header and that no bytes follow the last one.
+    pub fn validate_fields(&self) -> Result<(), HeaderLensError> {
+        let mut payload_reader = Reader::from(self.encoded);
+        payload_reader.try_read_next(self.payload_offset)?;
+        RequestsHash::validate_through(&mut payload_reader)?;
+        if !payload_reader.is_empty() {
+            return Err(HeaderLensError::TrailingBytes {
+                position: payload_reader.position(),
+            });
         }
-        payload_reader.read_next((N + 1).into());
         Ok(())
     }
 }
 
-impl<'a> Deref for EncodedHeaderLens<'a> {
+impl<'bytes, V: HeaderField> RLPListInspector for EncodedHeaderLens<'bytes, V> {
+    type Validated = V;
+
+    fn payload_offset(&self) -> usize {
+        self.payload_offset
+    }
+
+    fn encoded(&self) -> &[u8] {
+        self.encoded
+    }
+}
+
+impl<'a, V> Deref for EncodedHeaderLens<'a, V> {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
@@ -80,7 +164,7 @@ impl<'a> Deref for EncodedHeaderLens<'a> {
     }
 }
 
-impl<'a> AsRef<[u8]> for EncodedHeaderLens<'a> {
+impl<'a, V> AsRef<[u8]> for EncodedHeaderLens<'a, V> {
     fn as_ref(&self) -> &[u8] {
         &self.encoded
     }
@@ -89,8 +173,10 @@ impl<'a> AsRef<[u8]> for EncodedHeaderLens<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lazy_header::*;
     use alloy_consensus::Header;
-    use alloy_rlp::Encodable;
+    use alloy_primitives::{b256, hex, Address, Bloom, Bytes, B64, U256};
+    use alloy_rlp::{Decodable, Encodable};
 
     #[test]
     fn simple_header_equivalence() {
@@ -104,12 +190,258 @@ mod tests {
         let mut reader = Reader::from(encoded.as_slice());
         assert_eq!(reader.len(), encoded.len());
 
-        let header_lens = EncodedHeaderLens::read_from(&mut reader).unwrap();
+        let header_lens: EncodedHeaderLens = EncodedHeaderLens::read_from(&mut reader).unwrap();
         assert_eq!(reader.len(), 0);
 
         assert_eq!(header_lens.len(), encoded.len());
         assert_eq!(header_lens.hash(), header.hash_slow());
-        assert_eq!(header_lens.parent_hash(), header.parent_hash);
-        assert_eq!(header_lens.receipts_root(), header.receipts_root);
+        assert_eq!(header_lens.parent_hash(), &header.parent_hash);
+        assert_eq!(header_lens.receipts_root(), &header.receipts_root);
+    }
+
+    #[test]
+    fn block_number_and_timestamp_equivalence() {
+        for (number, difficulty, timestamp) in [
+            (0, U256::ZERO, 0),
+            (1, U256::from(0x7f), 0x7f),
+            (0x80, U256::from(0x80), 0x80),
+            (
+                21_000_000,
+                U256::from(58_750_003_716_598_352_816_469u128),
+                1_730_000_000,
+            ),
+            (u64::MAX, U256::MAX, u64::MAX),
+        ] {
+            let header = Header {
+                number,
+                difficulty,
+                gas_limit: 30_000_000,
+                gas_used: 12_345_678,
+                timestamp,
+                ..Default::default()
+            };
+            let mut encoded = Vec::<u8>::new();
+            header.encode(&mut encoded);
+
+            let header_lens: EncodedHeaderLens<Timestamp> =
+                EncodedHeaderLens::read_from(&mut Reader::from(&encoded[..])).unwrap();
+            assert_eq!(header_lens.number(), number);
+            assert_eq!(header_lens.timestamp(), timestamp);
+        }
+    }
+
+    fn assert_lens_matches(header: &Header) {
+        let mut encoded = Vec::<u8>::new();
+        header.encode(&mut encoded);
+        let decoded = Header::decode(&mut encoded.as_slice()).unwrap();
+
+        let lens: EncodedHeaderLens<RequestsHash> =
+            EncodedHeaderLens::read_from(&mut Reader::from(encoded.as_slice())).unwrap();
+        assert_eq!(lens.validate_fields(), Ok(()));
+        assert_eq!(lens.hash(), decoded.hash_slow());
+        assert_eq!(lens.parent_hash(), &decoded.parent_hash);
+        assert_eq!(lens.ommers_hash(), &decoded.ommers_hash);
+        assert_eq!(lens.beneficiary(), &decoded.beneficiary);
+        assert_eq!(lens.state_root(), &decoded.state_root);
+        assert_eq!(lens.transactions_root(), &decoded.transactions_root);
+        assert_eq!(lens.receipts_root(), &decoded.receipts_root);
+        assert_eq!(lens.logs_bloom(), &decoded.logs_bloom);
+        assert_eq!(lens.difficulty(), decoded.difficulty);
+        assert_eq!(lens.number(), decoded.number);
+        assert_eq!(lens.gas_limit(), decoded.gas_limit);
+        assert_eq!(lens.gas_used(), decoded.gas_used);
+        assert_eq!(lens.timestamp(), decoded.timestamp);
+        assert_eq!(lens.extra_data(), &decoded.extra_data[..]);
+        assert_eq!(lens.mix_hash(), &decoded.mix_hash);
+        assert_eq!(lens.nonce(), &decoded.nonce);
+        assert_eq!(lens.base_fee_per_gas(), decoded.base_fee_per_gas);
+        assert_eq!(lens.withdrawals_root(), decoded.withdrawals_root.as_ref());
+        assert_eq!(lens.blob_gas_used(), decoded.blob_gas_used);
+        assert_eq!(lens.excess_blob_gas(), decoded.excess_blob_gas);
+        assert_eq!(
+            lens.parent_beacon_block_root(),
+            decoded.parent_beacon_block_root.as_ref()
+        );
+        assert_eq!(lens.requests_hash(), decoded.requests_hash.as_ref());
+    }
+
+    /// Synthetic headers with the fields of each fork's header layout and arbitrary values, not
+    /// real mainnet headers.
+    #[test]
+    fn all_fields_equivalence_across_synthetic_fork_headers() {
+        let synthetic_paris = Header {
+            parent_hash: B256::repeat_byte(0x01),
+            ommers_hash: B256::repeat_byte(0x02),
+            beneficiary: Address::repeat_byte(0x03),
+            state_root: B256::repeat_byte(0x04),
+            transactions_root: B256::repeat_byte(0x05),
+            receipts_root: B256::repeat_byte(0x06),
+            logs_bloom: Bloom::repeat_byte(0x07),
+            difficulty: U256::ZERO,
+            number: 15_537_394,
+            gas_limit: 30_000_000,
+            gas_used: 0x7f,
+            timestamp: 1_663_224_179,
+            extra_data: Bytes::from_static(b"santa"),
+            mix_hash: B256::repeat_byte(0x08),
+            nonce: B64::ZERO,
+            base_fee_per_gas: Some(7),
+            ..Default::default()
+        };
+        let synthetic_london = Header {
+            difficulty: U256::from(11_055_787_484_078_698u64),
+            nonce: B64::repeat_byte(0x09),
+            extra_data: Bytes::new(),
+            ..synthetic_paris.clone()
+        };
+        let synthetic_shanghai = Header {
+            withdrawals_root: Some(B256::repeat_byte(0x0a)),
+            extra_data: Bytes::from_static(&[0x01]),
+            ..synthetic_paris.clone()
+        };
+        let synthetic_cancun = Header {
+            blob_gas_used: Some(0),
+            excess_blob_gas: Some(393_216),
+            parent_beacon_block_root: Some(B256::repeat_byte(0x0b)),
+            extra_data: Bytes::from(vec![0xee; 32]),
+            ..synthetic_shanghai.clone()
+        };
+        let synthetic_prague = Header {
+            requests_hash: Some(B256::repeat_byte(0x0c)),
+            // Not valid on mainnet but requires the long string encoding.
+            extra_data: Bytes::from(vec![0xdd; 97]),
+            ..synthetic_cancun.clone()
+        };
+        let synthetic_frontier = Header {
+            base_fee_per_gas: None,
+            ..synthetic_london.clone()
+        };
+
+        for header in [
+            synthetic_frontier,
+            synthetic_london,
+            synthetic_paris,
+            synthetic_shanghai,
+            synthetic_cancun,
+            synthetic_prague,
+        ] {
+            assert_lens_matches(&header);
+        }
+    }
+
+    /// Mainnet headers as RLP with their block hashes. Only blocks whose RLP could be checked
+    /// against the hash are listed; London, Paris, Shanghai and Prague headers still need adding.
+    const MAINNET_HEADERS: [(&str, B256, &[u8]); 3] = [
+        (
+            "frontier genesis",
+            b256!("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"),
+            &hex!("f90214a00000000000000000000000000000000000000000000000000000000000000000a01dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347940000000000000000000000000000000000000000a0d7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421b9010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000850400000000808213888080a011bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82faa00000000000000000000000000000000000000000000000000000000000000000880000000000000042"),
+        ),
+        (
+            "muir glacier #11117104",
+            b256!("b25d0e54ca0104e3ebfb5a1dcdf9528140854d609886a300946fd6750dcb19f4"),
+            &hex!("f90217a09400ec9ef59689c157ac89eeed906f15ddd768f94e1575e0e27d37c241439a5da01dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d4934794829bd824b016326a401d083b33d092293333a830a0546e330050c66d02923e7f1f3e925efaf64e4384eeecf2288f40088714a77a84a0d5eb3ad6d7c7a4798cc5fb14a6820073f44a941107c5d79dac60bd16325631fea0b21c41cbb3439c5af25304e1405524c885e733b16203221900cb7f4b387b62f0b901001f304e641097eafae088627298685d20202004a4a59e4d8900914724e2402b028c9d596660581f361240816e82d00fa14250c9ca89840887a381efa600288283d170010ab0b2a0694c81842c2482457e0eb77c2c02554614007f42aaf3b4dc15d006a83522c86a240c06d241013258d90540c3008888d576a02c10120808520a2221110f4805200302624d22092b2c0e94e849b1e1aa80bc4cc3206f00b249d0a603ee4310216850e47c8997a20aa81fe95040a49ca5a420464600e008351d161dc00d620970b6a801535c218d0b4116099292000c08001943a225d6485528828110645b8244625a182c1a88a41087e6d039b000a180d04300d0680700a15794870c40faff9c737d83a9a23083be5a6683be0fcc845f93b749967070796520e4b883e5bda9e7a59ee4bb99e9b1bc0103a0d5e2b7b71fbe4ddfe552fb2377bf7cddb16bbb7e185806036cee86994c6e97fc884722f2acd35abe0f"),
+        ),
+        (
+            "cancun #19449567",
+            b256!("85cdcbe36217fd57bf2c33731d8460657a7ce512401f49c9f6392c82a7ccf7ac"),
+            &hex!("f90255a090926e0298d418181bd20c23b332451e35fd7d696b5dcdc5a3a0a6b715f4c717a01dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d493479495222290dd7278aa3ddd389cc1e1d165cc4bafe5a0707875120a7103621fb4131df59904cda39de948dfda9084a1e3da44594d5404a0889a1c26dc42ba829dab552b779620feac231cde8a6c79af022bdc605c23a780a0d43aa19ecb03571d1b86d89d9bb980139d32f2f2ba59646cd5c1de9e80c68c90b90100c36919406572730518285284f2293101104140c0d42c4a786c892467868a8806f40159d29988002870403902413a1d04321320308da2e845438429e0012a00b419d8ccc8584a1c28f82a415d04eab8a5ae75c00d07761acf233414c08b6d9b571c06156086c70ea5186e9b989b0c2d55c0213c936805cd2ab331589c90194d070c00867549b1e1be14cb24500b0386cd901197c1ef5a00da453234fa48f3003dcaa894e3111c22b80e17f7d4388385a10720cda1140c0400f9e084ca34fc4870fb16b472340a2a6a63115a82522f506c06c2675080508834828c63defd06bc2331b4aa708906a06a560457b114248041e40179ebc05c6846c1e922125982f42780840128c6df8401c9c38083b0033c8465f5f4c38f6265617665726275696c642e6f7267a04c068e902990f21f92a2456fc75c59bec8be03b7f13682b6ebd27da56269beb5880000000000000000850886b221ada0360c33f20eeed5efbc7d08be46e58f8440af5db503e40908ef3d1eb314856ef78080a02843cb9f7d001bd58816a915e685ed96a555c9aeec1217736bd83a96ebd409cc"),
+        ),
+    ];
+
+    #[test]
+    fn all_fields_equivalence_on_mainnet_headers() {
+        for (fork, hash, encoded) in MAINNET_HEADERS {
+            let header = Header::decode(&mut &encoded[..]).unwrap();
+            assert_eq!(header.hash_slow(), hash, "{fork}");
+
+            let lens: EncodedHeaderLens<RequestsHash> =
+                EncodedHeaderLens::read_from(&mut Reader::from(encoded)).unwrap();
+            assert_eq!(lens.hash(), hash, "{fork}");
+            assert_lens_matches(&header);
+        }
+    }
+
+    #[test]
+    fn malformed_tail_fields_error() {
+        let header = Header {
+            base_fee_per_gas: Some(1),
+            extra_data: Bytes::from_static(&[0x01]),
+            ..Default::default()
+        };
+        let mut encoded = Vec::<u8>::new();
+        header.encode(&mut encoded);
+        let extra_data_position = encoded.len() - 1 - 9 - 33 - 1;
+        assert_eq!(encoded[extra_data_position], 0x01);
+
+        // Single byte below 0x80 must not be wrapped in a string header.
+        let mut non_canonical = encoded.clone();
+        non_canonical[extra_data_position] = RLP_STR_OFFSET + 1;
+        non_canonical.insert(extra_data_position + 1, 0x01);
+        non_canonical[2] += 1;
+        let lens: EncodedHeaderLens<Timestamp> =
+            EncodedHeaderLens::read_from(&mut Reader::from(&non_canonical[..])).unwrap();
+        assert_eq!(lens.timestamp(), 0);
+        let expected = HeaderLensError::InvalidStringField {
+            position: extra_data_position,
+        };
+        assert_eq!(lens.validate_fields(), Err(expected.clone()));
+        assert_eq!(
+            lens.validate_through::<BaseFeePerGas>().unwrap_err(),
+            expected
+        );
+
+        let mut trailing = encoded.clone();
+        trailing.push(0x80);
+        trailing[2] += 1;
+        let lens: EncodedHeaderLens<BaseFeePerGas> =
+            EncodedHeaderLens::read_from(&mut Reader::from(&trailing[..])).unwrap();
+        assert_eq!(lens.base_fee_per_gas(), Some(1));
+        let expected = HeaderLensError::InvalidFieldHead {
+            position: encoded.len(),
+            expected: RLP_STR_OFFSET + 32,
+            found: 0x80,
+        };
+        assert_eq!(lens.validate_fields(), Err(expected.clone()));
+        assert_eq!(
+            lens.validate_through::<WithdrawalsRoot>().unwrap_err(),
+            expected
+        );
+    }
+
+    #[test]
+    fn malformed_headers_error() {
+        let mut encoded = Vec::<u8>::new();
+        Header::default().encode(&mut encoded);
+
+        let truncated = &encoded[..encoded.len() - 1];
+        assert!(matches!(
+            EncodedHeaderLens::<ReceiptsRoot>::read_from(&mut Reader::from(truncated)),
+            Err(HeaderLensError::Truncated(_))
+        ));
+
+        assert!(matches!(
+            EncodedHeaderLens::<ReceiptsRoot>::read_from(&mut Reader::from(&encoded[..2])),
+            Err(HeaderLensError::Truncated(_))
+        ));
+
+        assert_eq!(
+            EncodedHeaderLens::<ReceiptsRoot>::read_from(&mut Reader::from(&[0xc1, 0x80][..]))
+                .unwrap_err(),
+            HeaderLensError::InvalidListHead { head: 0xc1 }
+        );
+
+        // Shorten the beneficiary field header.
+        let beneficiary_offset = 3 + 33 + 33;
+        encoded[beneficiary_offset] = RLP_STR_OFFSET + 19;
+        assert_eq!(
+            EncodedHeaderLens::<ReceiptsRoot>::read_from(&mut Reader::from(encoded.as_slice()))
+                .unwrap_err(),
+            HeaderLensError::InvalidFieldHead {
+                position: beneficiary_offset,
+                expected: RLP_STR_OFFSET + 20,
+                found: RLP_STR_OFFSET + 19
+            }
+        );
     }
 }
@@ -1,4 +1,3 @@
-use std::ops::{Deref, DerefMut};
 use tiny_keccak::keccakf;
 
 type Word = u32;
@@ -6,38 +5,54 @@ type Word = u32;
 const STATE_BYTES: usize = 200;
 const WORD_BYTES: usize = (Word::BITS as usize) / 8;
 const WORDS: usize = STATE_BYTES / WORD_BYTES;
+const LANES: usize = STATE_BYTES / 8;
 
 const DELIM: u8 = 0x01;
 
 const BLOCK_SIZE: usize = 136;
+const BLOCK_WORDS: usize = BLOCK_SIZE / WORD_BYTES;
 
+/// Keccak state as the little-endian bytes of its lanes, aligned so that it can be viewed as words
+/// or as lanes without copying.
 #[derive(Debug, Clone)]
-struct Keccak256State([Word; WORDS]);
+#[repr(C, align(8))]
+struct Keccak256State([u8; STATE_BYTES]);
 
-impl Deref for Keccak256State {
-    type Target = [Word; WORDS];
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl Default for Keccak256State {
+    fn default() -> Self {
+        Self([0; STATE_BYTES])
     }
 }
 
-impl DerefMut for Keccak256State {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl Keccak256State {
+    /// Native-endian words of the state. Words are only ever XORed with or set to words read the
+    /// same way from the input, which is endian independent.
+    #[inline]
+    fn words(&mut self) -> &mut [Word; WORDS] {
+        // Safety: The state is aligned to 8 bytes, exactly `WORDS` words long and all bit patterns
+        // are valid words.
+        unsafe { &mut *(self.0.as_mut_ptr() as *mut [Word; WORDS]) }
     }
-}
 
-impl Default for Keccak256State {
-    fn default() -> Self {
-        Self([0; WORDS])
+    #[inline]
+    fn lanes(&mut self) -> &mut [u64; LANES] {
+        // Safety: The state is aligned to 8 bytes, exactly `LANES` lanes long and all bit patterns
+        // are valid lanes.
+        unsafe { &mut *(self.0.as_mut_ptr() as *mut [u64; LANES]) }
     }
-}
 
-impl AsMut<[u8; STATE_BYTES]> for Keccak256State {
     #[inline]
-    fn as_mut(&mut self) -> &mut [u8; STATE_BYTES] {
-        unsafe { core::mem::transmute(self) }
+    fn permute(&mut self) {
+        let lanes = self.lanes();
+        // Lanes are stored little-endian, converting them compiles to nothing on little-endian
+        // targets such as the zkVM.
+        for lane in lanes.iter_mut() {
+            *lane = u64::from_le(*lane);
+        }
+        keccakf(lanes);
+        for lane in lanes.iter_mut() {
+            *lane = lane.to_le();
+        }
     }
 }
 
@@ -60,11 +75,6 @@ impl Default for Keccak256 {
 
 /// Keccak256 struct optimized for repeated hashing & outputting 32-byte hashes.
 impl Keccak256 {
-    #[inline]
-    fn permute(&mut self) {
-        keccakf(unsafe { core::mem::transmute(&mut self.state) })
-    }
-
     #[inline]
     fn absorb<T: std::ops::BitXorAssign<T> + Copy>(first: bool, dst: &mut T, src: &T) {
         if first {
@@ -74,26 +84,51 @@ impl Keccak256 {
         }
     }
 
-    /// Safety: Assumes caller has checked that `block` is aligned with width of `Word`
     #[inline]
-    unsafe fn absorb_aligned(&mut self, first: bool, block: &[u8]) {
-        let block: &[Word] =
-            std::slice::from_raw_parts(block.as_ptr() as *const Word, BLOCK_SIZE / WORD_BYTES);
-
-        for i in 0..BLOCK_SIZE / WORD_BYTES {
-            Self::absorb(first, &mut self.state[i], &block[i]);
+    fn absorb_aligned(&mut self, first: bool, block: &[Word; BLOCK_WORDS]) {
+        for (s, b) in self.state.words().iter_mut().zip(block) {
+            Self::absorb(first, s, b);
         }
 
-        self.permute();
+        self.state.permute();
     }
 
     #[inline]
     fn absorb_block(&mut self, first: bool, block: &[u8; BLOCK_SIZE]) {
-        for (s, b) in self.state.iter_mut().zip(block.chunks_exact(WORD_BYTES)) {
-            Self::absorb(first, s, &Word::from_le_bytes(b.try_into().unwrap()));
+        for (s, b) in self
+            .state
+            .words()
+            .iter_mut()
+            .zip(block.chunks_exact(WORD_BYTES))
+        {
+            Self::absorb(first, s, &Word::from_ne_bytes(b.try_into().unwrap()));
+        }
+
+        self.state.permute();
+    }
+
+    /// Absorbs the full blocks at the start of `input`, reading them as words directly if they're
+    /// aligned. Returns the remaining input.
+    #[inline]
+    fn absorb_blocks<'i>(&mut self, input: &'i [u8]) -> &'i [u8] {
+        let full_blocks = input.len() / BLOCK_SIZE * BLOCK_SIZE;
+        let (blocks, rest) = input.split_at(full_blocks);
+
+        // Safety: All bit patterns are valid words.
+        let (unaligned, words, _) = unsafe { blocks.align_to::<Word>() };
+        if unaligned.is_empty() {
+            for block in words.chunks_exact(BLOCK_WORDS) {
+                self.absorb_aligned(self.first_block, block.try_into().unwrap());
+                self.first_block = false;
+            }
+        } else {
+            for block in blocks.chunks_exact(BLOCK_SIZE) {
+                self.absorb_block(self.first_block, block.try_into().unwrap());
+                self.first_block = false;
+            }
         }
 
-        self.permute();
+        rest
     }
 
     pub fn update(&mut self, input: impl AsRef<[u8]>) {
@@ -102,8 +137,7 @@ impl Keccak256 {
 
         // If input not long enough to fill block partially absorb.
         if input.len() < rem {
-            let state_bytes = self.state.as_mut();
-            for (s, inp) in state_bytes.iter_mut().zip(input) {
+            for (s, inp) in self.state.0[self.offset..].iter_mut().zip(input) {
                 Self::absorb(self.first_block, s, inp);
             }
             self.offset += input.len();
@@ -115,65 +149,29 @@ impl Keccak256 {
             let (left, right) = input.split_at(rem);
             input = right;
 
-            let state_bytes = self.state.as_mut();
-            for (s, inp) in state_bytes[self.offset..].iter_mut().zip(left) {
+            for (s, inp) in self.state.0[self.offset..].iter_mut().zip(left) {
                 Self::absorb(self.first_block, s, inp);
             }
 
-            self.permute();
-            self.first_block = false;
-        }
-
-        let align_offset = input.as_ptr().align_offset(WORD_BYTES);
-
-        if self.first_block && input.len() >= BLOCK_SIZE {
-            let (block, right) = input.split_at(BLOCK_SIZE);
-            input = right;
-
-            if align_offset == 0 {
-                unsafe { self.absorb_aligned(true, block) };
-            } else {
-                self.absorb_block(true, block.try_into().unwrap());
-            }
-
+            self.state.permute();
             self.first_block = false;
         }
 
-        // Absorb remaining full blocks.
-        if align_offset == 0 {
-            while input.len() >= BLOCK_SIZE {
-                let (block, right) = input.split_at(BLOCK_SIZE);
-                input = right;
-
-                // Safety: If `input` was aligned initially and we only split in aligned
-                // increments we know the resulting slice is still aligned.
-                unsafe { self.absorb_aligned(false, block) };
-            }
-        } else {
-            while input.len() >= BLOCK_SIZE {
-                let (block, right) = input.split_at(BLOCK_SIZE);
-                input = right;
-
-                self.absorb_block(false, block.try_into().unwrap());
-            }
-        }
+        input = self.absorb_blocks(input);
 
         // Absorb remainder of input that doesn't quite fit into a block.
-        let state_bytes = self.state.as_mut();
-        for i in 0..input.len() {
-            state_bytes[i] ^= input[i];
+        for (s, inp) in self.state.0.iter_mut().zip(input) {
+            Self::absorb(self.first_block, s, inp);
         }
         self.offset = input.len();
     }
 
     fn pad(&mut self) {
-        let state_bytes = self.state.as_mut();
+        let state_bytes = &mut self.state.0;
         if self.first_block {
             // If we didn't complete the first block by the time we're padding we need to zero out
             // the bytes that are potentially still dirty from the last hash.
-            for i in self.offset..BLOCK_SIZE {
-                state_bytes[i] = 0;
-            }
+            state_bytes[self.offset..BLOCK_SIZE].fill(0);
         }
 
         state_bytes[self.offset] ^= DELIM;
@@ -183,20 +181,15 @@ impl Keccak256 {
     pub fn finalize_and_reset(&mut self, output: &mut [u8; 32]) {
         self.pad();
 
-        self.permute();
+        self.state.permute();
 
-        let words_out: &mut [Word; 32 / WORD_BYTES] = unsafe { core::mem::transmute(output) };
-        for (a, b) in words_out.iter_mut().zip(*self.state) {
-            *a = b;
-        }
+        output.copy_from_slice(&self.state.0[..32]);
 
         self.reset();
     }
 
     fn reset(&mut self) {
-        for i in BLOCK_SIZE / WORD_BYTES..WORDS {
-            self.state[i] = 0;
-        }
+        self.state.0[BLOCK_SIZE..].fill(0);
         self.first_block = true;
         self.offset = 0;
     }
@@ -246,4 +239,65 @@ mod tests {
 
         assert_eq!(&hash, keccak256(preimage), "potato");
     }
+
+    #[test]
+    fn test_incremental_updates() {
+        let mut keccak = Keccak256::default();
+        let mut hash = [0u8; 32];
+
+        let preimage = "incremental updates, each smaller than a block. ".repeat(9);
+        for chunk in preimage.as_bytes().chunks(7) {
+            keccak.update(chunk);
+        }
+        keccak.finalize_and_reset(&mut hash);
+
+        assert_eq!(&hash, keccak256(&preimage), "small chunks");
+
+        keccak.update(&preimage.as_bytes()[..3]);
+        keccak.update(&preimage.as_bytes()[3..]);
+        keccak.finalize_and_reset(&mut hash);
+
+        assert_eq!(&hash, keccak256(&preimage), "short prefix");
+    }
+
+    /// Xorshift generator, keeping the test deterministic and fast enough for Miri.
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_random_splits_and_alignments() {
+        let rounds = if cfg!(miri) { 2 } else { 50 };
+        let mut random = 0x5eed_u64;
+        let mut keccak = Keccak256::default();
+        let mut hash = [0u8; 32];
+
+        // Backing buffer to place the preimage at every offset from its (unknown) alignment.
+        let mut backing = vec![0u8; 3 * BLOCK_SIZE + 8];
+        for len in [0, 1, 4, 135, 136, 137, 271, 272, 273, 3 * BLOCK_SIZE] {
+            for shift in 0..8 {
+                let preimage = &mut backing[shift..shift + len];
+                preimage
+                    .iter_mut()
+                    .for_each(|byte| *byte = next_random(&mut random) as u8);
+                let expected = keccak256(&*preimage);
+
+                for _ in 0..rounds {
+                    let mut rest = &*preimage;
+                    while !rest.is_empty() {
+                        let split = next_random(&mut random) as usize % (rest.len() + 1);
+                        let (chunk, right) = rest.split_at(split);
+                        keccak.update(chunk);
+                        rest = right;
+                    }
+                    keccak.finalize_and_reset(&mut hash);
+
+                    assert_eq!(hash, expected, "length {} shifted by {}", len, shift);
+                }
+            }
+        }
+    }
 }
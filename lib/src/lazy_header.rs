@@ -1,62 +1,314 @@
+//! Zero-copy inspection of RLP encoded headers. Every header field has a marker type implementing
+//! [`HeaderField`] and an inspector trait exposing its value. An inspector is only implemented for
+//! lenses that validated the header up to and including its field on creation, so that code can
+//! depend on exactly the fields it inspects and no more of the header than that gets validated.
+use crate::header_lens::HeaderLensError;
 use crate::rlp::*;
-use alloy_primitives::{Address, B256};
-use typenum::{IsLess, True, Unsigned, U20, U32, U56};
-
-type HeaderValidationError = String;
-
-fn validate_small_fixed_field<N: Unsigned + IsLess<U56, Output = True>>(
-    payload: &mut &[u8],
-) -> Result<(), HeaderValidationError> {
-    if payload.len() < N::USIZE + 1 {
-        return Err(format!(
-            "Remaining payload too short to be encoding RLP string of length {}",
-            N::USIZE
-        ));
+use crate::Reader;
+use alloy_primitives::{Address, Bloom, B256, B64, U256};
+use typenum::{
+    IsGreaterOrEqual, IsLess, True, Unsigned, U0, U1, U10, U11, U12, U13, U14, U15, U16, U17, U18,
+    U19, U2, U20, U3, U32, U4, U5, U56, U6, U7, U8, U9,
+};
+
+/// Encoded length of the fields up to and including the `receipts_root`.
+const RECEIPTS_ROOT_END: usize = 33 + 33 + 21 + 33 + 33 + 33;
+/// Header of the 256-byte `logs_bloom` string.
+const LOGS_BLOOM_HEAD: [u8; 3] = [RLP_STR_OFFSET + RLP_MAX_PACKED_LEN + 2, 0x01, 0x00];
+
+/// A field of the header, identified by its position in the encoding.
+pub trait HeaderField {
+    /// Index of the field in the header list, starting at 0 for the `parent_hash`.
+    type Index: Unsigned;
+    type Value<'r>;
+
+    /// Reads and validates the field, `payload` being positioned at its RLP header.
+    fn read<'r>(payload: &mut Reader<'r>) -> Result<Self::Value<'r>, HeaderLensError>;
+
+    /// Validates all fields up to and including this one, `payload` being positioned at the
+    /// `parent_hash`.
+    fn validate_through(payload: &mut Reader) -> Result<(), HeaderLensError>;
+}
+
+/// A field following the `receipts_root`. These don't have a fixed offset and have to be located by
+/// skipping the fields before them.
+pub trait TailField: HeaderField {
+    /// Advances `tail`, positioned right after the `receipts_root`, to the start of this field.
+    fn seek(tail: &mut Reader) -> Result<(), HeaderLensError>;
+
+    /// Advances `tail`, positioned right after the `receipts_root`, past this field.
+    fn skip_through(tail: &mut Reader) -> Result<(), HeaderLensError> {
+        Self::seek(tail)?;
+        Self::read(tail)?;
+        Ok(())
     }
-    let byte = payload[0];
-    let expected_byte = RLP_STR_OFFSET + N::U8;
-    if byte != expected_byte {
-        return Err(format!(
-            "Expected string header byte {:x} not {:x}",
-            expected_byte, byte
-        ));
+}
+
+fn validate_small_fixed_field<'r, N: Unsigned + IsLess<U56, Output = True>>(
+    payload: &mut Reader<'r>,
+) -> Result<&'r [u8], HeaderLensError> {
+    let expected = RLP_STR_OFFSET + N::U8;
+    let position = payload.position();
+    let found = payload.try_read_byte()?;
+    if found != expected {
+        return Err(HeaderLensError::InvalidFieldHead {
+            position,
+            expected,
+            found,
+        });
     }
-    *payload = &payload[N::USIZE + 1..];
-    Ok(())
+    Ok(payload.try_read_next(N::USIZE)?)
 }
 
-pub trait RLPListInspector: std::ops::Deref<Target = [u8]> {
-    fn payload_offset(&self) -> usize;
+fn read_fixed<'r, N, T>(payload: &mut Reader<'r>) -> Result<&'r T, HeaderLensError>
+where
+    N: Unsigned + IsLess<U56, Output = True>,
+    &'r T: TryFrom<&'r [u8]>,
+    T: 'r,
+{
+    validate_small_fixed_field::<N>(payload)
+        .map(|bytes| bytes.try_into().ok().expect("field has length N"))
+}
 
-    fn encoded(&self) -> &[u8];
+fn read_logs_bloom<'r>(payload: &mut Reader<'r>) -> Result<&'r Bloom, HeaderLensError> {
+    let position = payload.position();
+    let head = payload.try_read_array::<3>()?;
+    if head != &LOGS_BLOOM_HEAD {
+        return Err(HeaderLensError::InvalidFieldHead {
+            position,
+            expected: LOGS_BLOOM_HEAD[0],
+            found: head[0],
+        });
+    }
+    Ok(payload.try_read_array::<256>()?.into())
+}
+
+/// Reads a canonically encoded RLP string of any length.
+fn read_bytes<'r>(payload: &mut Reader<'r>) -> Result<&'r [u8], HeaderLensError> {
+    let position = payload.position();
+    let invalid = HeaderLensError::InvalidStringField { position };
+    let head = payload.peek()?;
+    if head < RLP_STR_OFFSET {
+        return Ok(payload.try_read_next(1)?);
+    }
+    payload.try_read_byte()?;
+    if head <= RLP_STR_OFFSET + RLP_MAX_PACKED_LEN {
+        let bytes = payload.try_read_next(usize::from(head - RLP_STR_OFFSET))?;
+        return match bytes {
+            [b] if *b < RLP_STR_OFFSET => Err(invalid),
+            _ => Ok(bytes),
+        };
+    }
+    if head >= RLP_LIST_OFFSET {
+        return Err(invalid);
+    }
+
+    let length_bytes =
+        payload.try_read_next(usize::from(head - RLP_STR_OFFSET - RLP_MAX_PACKED_LEN))?;
+    if length_bytes[0] == 0 || length_bytes.len() > std::mem::size_of::<usize>() {
+        return Err(invalid);
+    }
+    let length = length_bytes
+        .iter()
+        .fold(0usize, |length, &b| (length << 8) | usize::from(b));
+    if length <= usize::from(RLP_MAX_PACKED_LEN) {
+        return Err(invalid);
+    }
+    Ok(payload.try_read_next(length)?)
 }
 
-pub trait ParentHashInspector: RLPListInspector {
-    fn validate_field(payload: &mut &[u8]) -> Result<(), HeaderValidationError> {
-        validate_small_fixed_field::<U32>(payload)
+/// Reads a canonically encoded RLP integer of at most `MAX_BYTES`, returning its big endian bytes
+/// without leading zeros.
+fn read_uint<'r, const MAX_BYTES: usize>(
+    payload: &mut Reader<'r>,
+) -> Result<&'r [u8], HeaderLensError> {
+    let position = payload.position();
+    let invalid = HeaderLensError::InvalidIntegerField { position };
+    match payload.peek()? {
+        0 => Err(invalid),
+        head if head < RLP_STR_OFFSET => Ok(payload.try_read_next(1)?),
+        head if usize::from(head - RLP_STR_OFFSET) <= MAX_BYTES => {
+            payload.try_read_byte()?;
+            let bytes = payload.try_read_next(usize::from(head - RLP_STR_OFFSET))?;
+            match bytes {
+                [0, ..] => Err(invalid),
+                [b] if *b < RLP_STR_OFFSET => Err(invalid),
+                _ => Ok(bytes),
+            }
+        }
+        _ => Err(invalid),
     }
+}
 
-    fn parent_hash(&self) -> &B256 {
-        self[self.payload_offset() + 1..][..32].try_into().unwrap()
+fn read_u256(payload: &mut Reader) -> Result<U256, HeaderLensError> {
+    read_uint::<32>(payload).map(U256::from_be_slice)
+}
+
+fn read_u64(payload: &mut Reader) -> Result<u64, HeaderLensError> {
+    let value = read_uint::<8>(payload)?;
+
+    let mut bytes = [0u8; 8];
+    bytes[8 - value.len()..].copy_from_slice(value);
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Reads a field introduced by a later fork, `None` if the header list ends before it.
+fn read_optional<'r, T>(
+    payload: &mut Reader<'r>,
+    read: impl FnOnce(&mut Reader<'r>) -> Result<T, HeaderLensError>,
+) -> Result<Option<T>, HeaderLensError> {
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    read(payload).map(Some)
+}
+
+macro_rules! header_field {
+    (
+        $(#[$doc:meta])*
+        $field:ident<$lt:lifetime> $(: $previous:ident)?,
+        $index:ty,
+        $value:ty,
+        $read:expr
+    ) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct $field;
+
+        impl HeaderField for $field {
+            type Index = $index;
+            type Value<$lt> = $value;
+
+            fn read<$lt>(payload: &mut Reader<$lt>) -> Result<Self::Value<$lt>, HeaderLensError> {
+                $read(payload)
+            }
+
+            fn validate_through(payload: &mut Reader) -> Result<(), HeaderLensError> {
+                $(<$previous as HeaderField>::validate_through(payload)?;)?
+                Self::read(payload)?;
+                Ok(())
+            }
+        }
+    };
+}
+
+macro_rules! tail_field {
+    (
+        $(#[$doc:meta])*
+        $field:ident<$lt:lifetime>: $previous:ident,
+        $index:ty,
+        $value:ty,
+        $read:expr
+    ) => {
+        header_field!($(#[$doc])* $field<$lt>: $previous, $index, $value, $read);
+
+        impl TailField for $field {
+            fn seek(tail: &mut Reader) -> Result<(), HeaderLensError> {
+                <$previous as TailField>::skip_through(tail)
+            }
+        }
+    };
+}
+
+header_field!(ParentHash<'r>, U0, &'r B256, read_fixed::<U32, B256>);
+header_field!(OmmersHash<'r>: ParentHash, U1, &'r B256, read_fixed::<U32, B256>);
+header_field!(Beneficiary<'r>: OmmersHash, U2, &'r Address, read_fixed::<U20, Address>);
+header_field!(StateRoot<'r>: Beneficiary, U3, &'r B256, read_fixed::<U32, B256>);
+header_field!(TransactionsRoot<'r>: StateRoot, U4, &'r B256, read_fixed::<U32, B256>);
+header_field!(ReceiptsRoot<'r>: TransactionsRoot, U5, &'r B256, read_fixed::<U32, B256>);
+
+impl TailField for ReceiptsRoot {
+    fn seek(_tail: &mut Reader) -> Result<(), HeaderLensError> {
+        Ok(())
+    }
+
+    /// The tail starts right after the `receipts_root`, there's nothing to skip.
+    fn skip_through(_tail: &mut Reader) -> Result<(), HeaderLensError> {
+        Ok(())
+    }
+}
+
+tail_field!(LogsBloom<'r>: ReceiptsRoot, U6, &'r Bloom, read_logs_bloom);
+tail_field!(Difficulty<'r>: LogsBloom, U7, U256, read_u256);
+tail_field!(Number<'r>: Difficulty, U8, u64, read_u64);
+tail_field!(GasLimit<'r>: Number, U9, u64, read_u64);
+tail_field!(GasUsed<'r>: GasLimit, U10, u64, read_u64);
+tail_field!(Timestamp<'r>: GasUsed, U11, u64, read_u64);
+tail_field!(ExtraData<'r>: Timestamp, U12, &'r [u8], read_bytes);
+tail_field!(MixHash<'r>: ExtraData, U13, &'r B256, read_fixed::<U32, B256>);
+tail_field!(Nonce<'r>: MixHash, U14, &'r B64, read_fixed::<U8, B64>);
+tail_field!(
+    /// Present since London.
+    BaseFeePerGas<'r>: Nonce,
+    U15,
+    Option<u64>,
+    |payload| read_optional(payload, read_u64)
+);
+tail_field!(
+    /// Present since Shanghai.
+    WithdrawalsRoot<'r>: BaseFeePerGas,
+    U16,
+    Option<&'r B256>,
+    |payload| read_optional(payload, read_fixed::<U32, B256>)
+);
+tail_field!(
+    /// Present since Cancun.
+    BlobGasUsed<'r>: WithdrawalsRoot,
+    U17,
+    Option<u64>,
+    |payload| read_optional(payload, read_u64)
+);
+tail_field!(
+    /// Present since Cancun.
+    ExcessBlobGas<'r>: BlobGasUsed,
+    U18,
+    Option<u64>,
+    |payload| read_optional(payload, read_u64)
+);
+tail_field!(
+    /// Present since Cancun.
+    ParentBeaconBlockRoot<'r>: ExcessBlobGas,
+    U19,
+    Option<&'r B256>,
+    |payload| read_optional(payload, read_fixed::<U32, B256>)
+);
+tail_field!(
+    /// Present since Prague.
+    RequestsHash<'r>: ParentBeaconBlockRoot,
+    U20,
+    Option<&'r B256>,
+    |payload| read_optional(payload, read_fixed::<U32, B256>)
+);
+
+/// Index of the last field validated by an inspector.
+type ValidatedIndex<I> = <<I as RLPListInspector>::Validated as HeaderField>::Index;
+
+pub trait RLPListInspector: std::ops::Deref<Target = [u8]> {
+    /// Last field validated on creation, all fields up to and including it can be inspected.
+    type Validated: HeaderField;
+
+    fn payload_offset(&self) -> usize;
+
+    fn encoded(&self) -> &[u8];
+
+    /// Reads one of the validated fields following the `receipts_root`.
+    fn tail_field<F: TailField>(&self) -> F::Value<'_> {
+        let mut tail = Reader::from(&self.encoded()[self.payload_offset() + RECEIPTS_ROOT_END..]);
+        F::seek(&mut tail)
+            .and_then(|()| F::read(&mut tail))
+            .expect("field validated on creation")
     }
 }
 
 macro_rules! fixed_field_inspector {
     (
-        $trait_name:ident :
-        $parent_trait:ident,
+        $trait_name:ident $(: $parent_trait:ident)?,
+        $field:ident,
         $field_name:ident,
         $field_type:ty,
-        $field_size:ty,
         $offset:expr
     ) => {
-        pub trait $trait_name: $parent_trait {
-            fn validate_field(payload: &mut &[u8]) -> Result<(), HeaderValidationError> {
-                // If parent is not RLPListInspector, call its validation
-                <Self as $parent_trait>::validate_field(payload)?;
-                validate_small_fixed_field::<$field_size>(payload)
-            }
-
+        pub trait $trait_name: RLPListInspector $(+ $parent_trait)? {
             fn $field_name(&self) -> &$field_type {
                 self[self.payload_offset() + $offset + 1..][..::std::mem::size_of::<$field_type>()]
                     .try_into()
@@ -64,46 +316,109 @@ macro_rules! fixed_field_inspector {
             }
         }
 
-        impl<I: $parent_trait> $trait_name for I {}
+        impl<I> $trait_name for I
+        where
+            I: RLPListInspector $(+ $parent_trait)?,
+            ValidatedIndex<I>: IsGreaterOrEqual<<$field as HeaderField>::Index, Output = True>,
+        {
+        }
+    };
+}
+
+macro_rules! tail_field_inspector {
+    (
+        $trait_name:ident: $parent_trait:ident,
+        $field:ident,
+        $field_name:ident
+    ) => {
+        pub trait $trait_name: $parent_trait {
+            fn $field_name(&self) -> <$field as HeaderField>::Value<'_> {
+                self.tail_field::<$field>()
+            }
+        }
+
+        impl<I> $trait_name for I
+        where
+            I: $parent_trait,
+            ValidatedIndex<I>: IsGreaterOrEqual<<$field as HeaderField>::Index, Output = True>,
+        {
+        }
     };
 }
 
+fixed_field_inspector!(ParentHashInspector, ParentHash, parent_hash, B256, 0);
 fixed_field_inspector!(
     OmmersHashInspector: ParentHashInspector,
+    OmmersHash,
     ommers_hash,
     B256,
-    U32,
     33
 );
-
 fixed_field_inspector!(
     BeneficiaryInspector: OmmersHashInspector,
+    Beneficiary,
     beneficiary,
     Address,
-    U20,
     33 + 33
 );
-
 fixed_field_inspector!(
     StateRootInspector: BeneficiaryInspector,
+    StateRoot,
     state_root,
     B256,
-    U32,
     33 + 33 + 21
 );
-
 fixed_field_inspector!(
-    TransactionRootInspector: StateRootInspector,
-    transaction_root,
+    TransactionsRootInspector: StateRootInspector,
+    TransactionsRoot,
+    transactions_root,
     B256,
-    U32,
     33 + 33 + 21 + 33
 );
-
 fixed_field_inspector!(
-    ReceiptsRootInspector: TransactionRootInspector,
+    ReceiptsRootInspector: TransactionsRootInspector,
+    ReceiptsRoot,
     receipts_root,
     B256,
-    U32,
     33 + 33 + 21 + 33 + 33
 );
+
+tail_field_inspector!(LogsBloomInspector: ReceiptsRootInspector, LogsBloom, logs_bloom);
+tail_field_inspector!(DifficultyInspector: LogsBloomInspector, Difficulty, difficulty);
+tail_field_inspector!(NumberInspector: DifficultyInspector, Number, number);
+tail_field_inspector!(GasLimitInspector: NumberInspector, GasLimit, gas_limit);
+tail_field_inspector!(GasUsedInspector: GasLimitInspector, GasUsed, gas_used);
+tail_field_inspector!(TimestampInspector: GasUsedInspector, Timestamp, timestamp);
+tail_field_inspector!(ExtraDataInspector: TimestampInspector, ExtraData, extra_data);
+tail_field_inspector!(MixHashInspector: ExtraDataInspector, MixHash, mix_hash);
+tail_field_inspector!(NonceInspector: MixHashInspector, Nonce, nonce);
+tail_field_inspector!(
+    BaseFeePerGasInspector: NonceInspector,
+    BaseFeePerGas,
+    base_fee_per_gas
+);
+tail_field_inspector!(
+    WithdrawalsRootInspector: BaseFeePerGasInspector,
+    WithdrawalsRoot,
+    withdrawals_root
+);
+tail_field_inspector!(
+    BlobGasUsedInspector: WithdrawalsRootInspector,
+    BlobGasUsed,
+    blob_gas_used
+);
+tail_field_inspector!(
+    ExcessBlobGasInspector: BlobGasUsedInspector,
+    ExcessBlobGas,
+    excess_blob_gas
+);
+tail_field_inspector!(
+    ParentBeaconBlockRootInspector: ExcessBlobGasInspector,
+    ParentBeaconBlockRoot,
+    parent_beacon_block_root
+);
+tail_field_inspector!(
+    RequestsHashInspector: ParentBeaconBlockRootInspector,
+    RequestsHash,
+    requests_hash
+);
@@ -1,17 +1,26 @@
+pub mod compact_proof;
 pub mod header_lens;
 pub mod reader;
 pub mod receipt_trie;
-pub use reader::Reader;
+pub use reader::{Reader, ReaderError};
 pub mod rlp;
+pub mod state_proof;
 
 mod bytes_wrapper_macro;
 
-mod cache;
+pub mod cache;
+pub mod cycles;
+#[cfg(feature = "era1")]
+pub mod era1;
 pub mod fee_summary;
 mod keccak;
 mod trie_path;
 
+pub mod aggregation;
+pub mod anchor;
 pub mod payload;
+pub mod public_values;
+pub mod verifier;
 pub use cache::{Cache, SmolBlock};
 
 pub use keccak::Keccak256;
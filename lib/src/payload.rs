@@ -1,7 +1,8 @@
 use crate::fee_summary::FeeEntry;
 use crate::receipt_trie::get_proof_for_receipt;
-use alloy_consensus::{Header, ReceiptEnvelope};
-use alloy_primitives::{Address, B256};
+use crate::state_proof::StateProof;
+use alloy_consensus::{Header, ReceiptEnvelope, Transaction, TxEnvelope};
+use alloy_primitives::{Address, Log, B256};
 use alloy_rlp::Encodable;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -9,25 +10,129 @@ use std::collections::BTreeMap;
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RewardBlock {
     pub block_index: u32,
+    /// Index of the transaction and its receipt within the block.
+    pub tx_index: u32,
     pub proof: Vec<u8>,
     pub receipt: ReceiptEnvelope,
+    /// Proof of `transaction` against the header's transactions root.
+    pub transaction_proof: Vec<u8>,
+    pub transaction: TxEnvelope,
     pub log_index: u32,
     pub fee_entries: u32,
 }
 
+/// Describes the event Angstrom emits to commit to a block's fee summary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RewardEvent {
+    /// Event signature hash, expected as the log's first topic.
+    pub signature: B256,
+    /// Byte offset of the fee summary hash within the log data.
+    pub hash_offset: u32,
+    /// Exact length of the log data.
+    pub data_len: u32,
+}
+
+impl RewardEvent {
+    /// Returns the fee summary hash committed to by `log` if it's an instance of the event,
+    /// ignoring the emitting address.
+    pub fn reward_hash<'l>(&self, log: &'l Log) -> Option<&'l [u8; 32]> {
+        if log.topics().first() != Some(&self.signature) {
+            return None;
+        }
+        let data = &log.data.data;
+        if data.len() != self.data_len as usize {
+            return None;
+        }
+        data.get(self.hash_offset as usize..)?
+            .get(..32)?
+            .try_into()
+            .ok()
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Payload {
     pub angstrom: Address,
+    pub reward_event: RewardEvent,
     pub headers: Vec<u8>,
     pub reward_blocks: Vec<RewardBlock>,
     pub fee_entries: Vec<u8>,
+    /// Proof of Angstrom's account and storage slots against the state root of the last header.
+    pub angstrom_state: Option<StateProof>,
+}
+
+/// Transactions of a block together with their receipts.
+pub type BlockBody = (Vec<TxEnvelope>, Vec<ReceiptEnvelope>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadError {
+    /// The body of the block at `block_index` doesn't have one receipt per transaction.
+    ReceiptCountMismatch {
+        block_index: u32,
+        transactions: usize,
+        receipts: usize,
+    },
+    /// The block at `block_index` comes with its body but has no provable reward log.
+    NoRewardLogs { block_index: u32 },
+    /// The fee summary oracle has no entries for the logged `reward_hash`.
+    MissingFeeSummary { block_index: u32, reward_hash: B256 },
+    /// The fee summary of `reward_hash` has more entries than fit the `u32` count.
+    TooManyFeeEntries {
+        block_index: u32,
+        reward_hash: B256,
+        fee_entries: usize,
+    },
+}
+
+impl std::fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReceiptCountMismatch {
+                block_index,
+                transactions,
+                receipts,
+            } => write!(
+                f,
+                "block {}: expected one receipt per transaction, got {} transactions and {} receipts",
+                block_index, transactions, receipts
+            ),
+            Self::NoRewardLogs { block_index } => write!(
+                f,
+                "block {}: no reward log in a successful call to Angstrom",
+                block_index
+            ),
+            Self::MissingFeeSummary {
+                block_index,
+                reward_hash,
+            } => write!(
+                f,
+                "block {}: no fee summary for reward hash {}",
+                block_index, reward_hash
+            ),
+            Self::TooManyFeeEntries {
+                block_index,
+                reward_hash,
+                fee_entries,
+            } => write!(
+                f,
+                "block {}: fee summary {} has too many entries ({})",
+                block_index, reward_hash, fee_entries
+            ),
+        }
+    }
 }
 
+impl std::error::Error for PayloadError {}
+
+/// Builds the payload for `blocks`, the ones with reward logs coming with their body. Only reward
+/// logs of successful transactions calling Angstrom directly are proven, as the verifier rejects
+/// others.
 pub fn build_payload<T>(
-    blocks: Vec<(Header, Option<Vec<ReceiptEnvelope>>)>,
+    blocks: Vec<(Header, Option<BlockBody>)>,
     angstrom: Address,
+    reward_event: RewardEvent,
     fee_summary_oracle: &BTreeMap<B256, T>,
-) -> Payload
+) -> Result<Payload, PayloadError>
 where
     T: AsRef<[FeeEntry]>,
 {
@@ -35,45 +140,84 @@ where
     let mut reward_blocks = Vec::new();
     let mut fee_entries = Vec::new();
 
-    for ((header, receipts), block_index) in blocks.into_iter().zip(0..) {
+    for ((header, body), block_index) in blocks.into_iter().zip(0..) {
         header.encode(&mut headers);
-        if let Some(receipts) = receipts {
-            let (receipt, receipt_index, reward_hash, log_index) = receipts
+        if let Some((transactions, receipts)) = body {
+            if transactions.len() != receipts.len() {
+                return Err(PayloadError::ReceiptCountMismatch {
+                    block_index,
+                    transactions: transactions.len(),
+                    receipts: receipts.len(),
+                });
+            }
+            let reward_logs = receipts
                 .iter()
+                .zip(&transactions)
                 .zip(0..)
-                .find_map(|(receipt, receipt_index)| {
-                    receipt.logs().iter().zip(0..).find_map(|(log, log_index)| {
-                        if log.address != angstrom {
-                            return None;
-                        }
-                        let reward_hash = B256::try_from(&log.data.data[0..32]).unwrap();
-                        Some((receipt, receipt_index, reward_hash, log_index))
-                    })
+                .filter(|((receipt, transaction), _)| {
+                    receipt.is_success() && TxEnvelope::to(transaction) == Some(angstrom)
+                })
+                .flat_map(|((receipt, _), receipt_index)| {
+                    receipt
+                        .logs()
+                        .iter()
+                        .zip(0..)
+                        .filter_map(move |(log, log_index)| {
+                            if log.address != angstrom {
+                                return None;
+                            }
+                            let reward_hash = B256::from(reward_event.reward_hash(log)?);
+                            Some((receipt, receipt_index, reward_hash, log_index))
+                        })
                 })
-                .expect("Receipt list without reward log");
+                .collect::<Vec<_>>();
+            if reward_logs.is_empty() {
+                return Err(PayloadError::NoRewardLogs { block_index });
+            }
 
-            let block_fee_entries = fee_summary_oracle
-                .get(&reward_hash)
-                .expect("Missing fee summary oracle entry");
+            for (receipt, receipt_index, reward_hash, log_index) in reward_logs {
+                let block_fee_entries = fee_summary_oracle
+                    .get(&reward_hash)
+                    .ok_or(PayloadError::MissingFeeSummary {
+                        block_index,
+                        reward_hash,
+                    })?
+                    .as_ref();
+                let fee_entry_count = block_fee_entries.len().try_into().map_err(|_| {
+                    PayloadError::TooManyFeeEntries {
+                        block_index,
+                        reward_hash,
+                        fee_entries: block_fee_entries.len(),
+                    }
+                })?;
 
-            for entry in block_fee_entries.as_ref().iter() {
-                fee_entries.extend_from_slice(entry.as_slice());
-            }
+                for entry in block_fee_entries {
+                    fee_entries.extend_from_slice(entry.as_slice());
+                }
 
-            reward_blocks.push(RewardBlock {
-                block_index,
-                proof: get_proof_for_receipt(receipts.as_slice(), receipt_index),
-                receipt: receipt.clone(),
-                log_index: log_index.try_into().unwrap(),
-                fee_entries: block_fee_entries.as_ref().len().try_into().unwrap(),
-            })
+                reward_blocks.push(RewardBlock {
+                    block_index,
+                    tx_index: receipt_index,
+                    proof: get_proof_for_receipt(receipts.as_slice(), receipt_index),
+                    receipt: receipt.clone(),
+                    transaction_proof: get_proof_for_receipt(
+                        transactions.as_slice(),
+                        receipt_index,
+                    ),
+                    transaction: transactions[receipt_index as usize].clone(),
+                    log_index,
+                    fee_entries: fee_entry_count,
+                })
+            }
         }
     }
 
-    Payload {
+    Ok(Payload {
         angstrom,
+        reward_event,
         headers,
         reward_blocks,
         fee_entries,
-    }
+        angstrom_state: None,
+    })
 }
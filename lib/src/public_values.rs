@@ -0,0 +1,119 @@
+use crate::verifier::SantaOutput;
+use alloy_primitives::Address;
+use alloy_sol_types::sol;
+
+sol! {
+    /// Total fees collected for a single asset.
+    #[derive(Debug, PartialEq, Eq)]
+    struct AssetFees {
+        address asset;
+        uint256 total;
+    }
+
+    /// Value of one of Angstrom's storage slots at the end of the proven range.
+    #[derive(Debug, PartialEq, Eq)]
+    struct StorageSlot {
+        bytes32 slot;
+        uint256 value;
+    }
+
+    /// Public values committed by the Santa program. Encoded with ABI rules so that contracts can
+    /// `abi.decode(publicValues, (SantaPublicValues))` them.
+    ///
+    /// `chain_last` is the hash of block `last_block_number` and `chain_parent` the hash of block
+    /// `first_block_number - 1`, allowing contracts to anchor them via `blockhash` or the EIP-2935
+    /// history contract.
+    ///
+    /// `angstrom_storage` holds the values of the proven storage slots at block `last_block_number`,
+    /// allowing the fees to be cross-checked against Angstrom's state.
+    #[derive(Debug, PartialEq, Eq)]
+    struct SantaPublicValues {
+        address angstrom;
+        bytes32 chain_parent;
+        bytes32 chain_last;
+        uint64 first_block_number;
+        uint64 last_block_number;
+        uint64 first_block_timestamp;
+        uint64 last_block_timestamp;
+        uint32 reward_block_count;
+        AssetFees[] fees;
+        StorageSlot[] angstrom_storage;
+    }
+
+    /// Public values committed by the aggregation program, binding the combined values to the
+    /// verifying key of the program that proved the individual segments.
+    #[derive(Debug, PartialEq, Eq)]
+    struct SantaAggregatePublicValues {
+        bytes32 segment_vkey;
+        SantaPublicValues combined;
+    }
+}
+
+impl SantaPublicValues {
+    /// Builds the public values for a validated payload, ordering the fees by asset address so
+    /// that the encoding is deterministic.
+    pub fn new(angstrom: Address, output: SantaOutput) -> Self {
+        let mut fees: Vec<AssetFees> = output
+            .sums
+            .into_iter()
+            .map(|(asset, total)| AssetFees { asset, total })
+            .collect();
+        fees.sort_unstable_by_key(|fee| fee.asset);
+
+        Self {
+            angstrom,
+            chain_parent: output.chain_parent,
+            chain_last: output.chain_last,
+            first_block_number: output.first_block_number,
+            last_block_number: output.last_block_number,
+            first_block_timestamp: output.first_block_timestamp,
+            last_block_timestamp: output.last_block_timestamp,
+            reward_block_count: output.reward_block_count,
+            fees,
+            angstrom_storage: output
+                .angstrom_storage
+                .into_iter()
+                .map(|(slot, value)| StorageSlot { slot, value })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{B256, U256};
+    use alloy_sol_types::SolValue;
+
+    #[test]
+    fn abi_round_trip_with_sorted_fees() {
+        let output = SantaOutput {
+            chain_parent: B256::repeat_byte(0x11),
+            chain_last: B256::repeat_byte(0x22),
+            first_block_number: 100,
+            last_block_number: 199,
+            first_block_timestamp: 1_700_000_000,
+            last_block_timestamp: 1_700_001_188,
+            reward_block_count: 3,
+            sums: [
+                (Address::repeat_byte(0xbb), U256::from(2)),
+                (Address::repeat_byte(0xaa), U256::from(1)),
+            ]
+            .into_iter()
+            .collect(),
+            reward_transactions: vec![],
+            angstrom_storage: vec![(B256::with_last_byte(3), U256::from(5))],
+        };
+        let public_values = SantaPublicValues::new(Address::repeat_byte(0x01), output);
+        assert_eq!(public_values.fees[0].asset, Address::repeat_byte(0xaa));
+        assert_eq!(public_values.fees[1].asset, Address::repeat_byte(0xbb));
+
+        let encoded = public_values.abi_encode();
+        // Dynamic struct is encoded behind an offset, as `abi.decode(data, (SantaPublicValues))`
+        // expects.
+        assert_eq!(encoded[..32], U256::from(32).to_be_bytes::<32>());
+
+        let decoded = SantaPublicValues::abi_decode(&encoded, true).unwrap();
+        assert_eq!(decoded, public_values);
+    }
+}
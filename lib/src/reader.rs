@@ -1,23 +1,99 @@
 #[derive(Debug, Clone)]
-pub struct Reader<'a>(&'a [u8]);
+pub struct Reader<'a> {
+    remaining: &'a [u8],
+    position: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReaderError {
+    /// Attempted to read `requested` bytes at `position` with only `remaining` bytes left.
+    UnexpectedEnd {
+        position: usize,
+        requested: usize,
+        remaining: usize,
+    },
+}
+
+impl std::fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedEnd {
+                position,
+                requested,
+                remaining,
+            } => write!(
+                f,
+                "unexpected end of input at position {}: requested {} bytes, {} remaining",
+                position, requested, remaining
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReaderError {}
 
 impl<'a> Reader<'a> {
+    /// Number of bytes consumed since the reader was created.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn unexpected_end(&self, requested: usize) -> ReaderError {
+        ReaderError::UnexpectedEnd {
+            position: self.position,
+            requested,
+            remaining: self.remaining.len(),
+        }
+    }
+
+    /// Returns the next byte without consuming it.
+    pub fn peek(&self) -> Result<u8, ReaderError> {
+        self.remaining
+            .first()
+            .copied()
+            .ok_or_else(|| self.unexpected_end(1))
+    }
+
+    pub fn try_read_byte(&mut self) -> Result<u8, ReaderError> {
+        let b = self.peek()?;
+        self.remaining = &self.remaining[1..];
+        self.position += 1;
+        Ok(b)
+    }
+
+    pub fn try_read_next(&mut self, size: usize) -> Result<&'a [u8], ReaderError> {
+        if size > self.remaining.len() {
+            return Err(self.unexpected_end(size));
+        }
+        let (slice, rem) = self.remaining.split_at(size);
+        self.remaining = rem;
+        self.position += size;
+        Ok(slice)
+    }
+
+    pub fn try_read_array<const N: usize>(&mut self) -> Result<&'a [u8; N], ReaderError> {
+        self.try_read_next(N)
+            .map(|slice| slice.try_into().expect("slice has length N"))
+    }
+
+    /// Panicking version of [`Self::try_read_byte`].
     pub fn read_byte(&mut self) -> u8 {
-        let b = self.0[0];
-        self.0 = &self.0[1..];
-        b
+        self.try_read_byte().unwrap_or_else(|err| panic!("{}", err))
     }
 
+    /// Panicking version of [`Self::try_read_next`].
     pub fn read_next(&mut self, size: usize) -> &'a [u8] {
-        let (slice, rem) = self.0.split_at(size);
-        self.0 = rem;
-        slice
+        self.try_read_next(size)
+            .unwrap_or_else(|err| panic!("{}", err))
     }
 }
 
 impl<'a> From<&'a [u8]> for Reader<'a> {
     fn from(value: &'a [u8]) -> Self {
-        Self(value)
+        Self {
+            remaining: value,
+            position: 0,
+        }
     }
 }
 
@@ -25,12 +101,55 @@ impl<'a> std::ops::Deref for Reader<'a> {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        self.0
+        self.remaining
     }
 }
 
 impl<'a> AsRef<[u8]> for Reader<'a> {
     fn as_ref(&self) -> &[u8] {
-        self.0
+        self.remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_track_position() {
+        let bytes = [1u8, 2, 3, 4, 5];
+        let mut reader = Reader::from(&bytes[..]);
+
+        assert_eq!(reader.peek(), Ok(1));
+        assert_eq!(reader.position(), 0);
+        assert_eq!(reader.try_read_byte(), Ok(1));
+        assert_eq!(reader.try_read_next(2), Ok(&bytes[1..3]));
+        assert_eq!(reader.try_read_array::<2>(), Ok(&[4u8, 5]));
+        assert_eq!(reader.position(), 5);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn out_of_bounds_reads_fail_without_consuming() {
+        let bytes = [1u8, 2, 3];
+        let mut reader = Reader::from(&bytes[..]);
+        reader.try_read_byte().unwrap();
+
+        let expected = ReaderError::UnexpectedEnd {
+            position: 1,
+            requested: 3,
+            remaining: 2,
+        };
+        assert_eq!(reader.try_read_next(3), Err(expected));
+        assert_eq!(reader.position(), 1);
+
+        reader.try_read_next(2).unwrap();
+        let expected = ReaderError::UnexpectedEnd {
+            position: 3,
+            requested: 1,
+            remaining: 0,
+        };
+        assert_eq!(reader.peek(), Err(expected));
+        assert_eq!(reader.try_read_byte(), Err(expected));
     }
 }
@@ -1,25 +1,32 @@
+use crate::compact_proof::CompactProof;
 use crate::rlp::*;
 use crate::trie_path::TriePath;
 use crate::Keccak256;
-use crate::Reader;
+use crate::{Reader, ReaderError};
 
 use alloy_eips::Encodable2718;
-use alloy_primitives::{Bytes, B256};
-use alloy_rlp::{encode_fixed_size, length_of_length, Encodable, Rlp};
+use alloy_primitives::{map::HashMap, Bytes, B256};
+use alloy_rlp::{encode_fixed_size, length_of_length, Header, EMPTY_LIST_CODE, EMPTY_STRING_CODE};
 use alloy_trie::{
     proof::ProofNodes, proof::ProofRetainer, root::adjust_index_for_rlp, HashBuilder, Nibbles,
 };
 
 pub fn get_trie_proof_nodes(items: &[impl Encodable2718], index: u32) -> ProofNodes {
-    assert!((index as usize) < items.len());
+    get_trie_multiproof_nodes(items, &[index])
+}
+
+/// Retains the nodes on the paths to all items at `indices`.
+pub fn get_trie_multiproof_nodes(items: &[impl Encodable2718], indices: &[u32]) -> ProofNodes {
+    assert!(indices.iter().all(|&index| (index as usize) < items.len()));
 
     let mut value_buffer = Vec::new();
 
-    let retainer = {
-        let mut encoded_index_buffer = Vec::<u8>::new();
-        index.encode(&mut encoded_index_buffer);
-        ProofRetainer::new(vec![Nibbles::unpack(encoded_index_buffer)])
-    };
+    let retainer = ProofRetainer::new(
+        indices
+            .iter()
+            .map(|index| Nibbles::unpack(alloy_rlp::encode(index)))
+            .collect(),
+    );
     let mut hb = HashBuilder::default().with_proof_retainer(retainer);
 
     let items_len = items.len();
@@ -39,50 +46,125 @@ pub fn get_trie_proof_nodes(items: &[impl Encodable2718], index: u32) -> ProofNo
     hb.take_proof_nodes()
 }
 
-fn rlp_decode(payload: &[u8]) -> alloy_rlp::Result<Vec<Bytes>> {
-    let mut view = Rlp::new(payload)?;
-    let mut list = vec![];
+/// Splits an encoded RLP list into the encodings of its items.
+pub(crate) fn rlp_list_items(encoded: &[u8]) -> alloy_rlp::Result<Vec<&[u8]>> {
+    let mut payload = encoded;
+    let header = Header::decode(&mut payload)?;
+    if !header.list {
+        return Err(alloy_rlp::Error::UnexpectedString);
+    }
+    let mut payload = payload
+        .get(..header.payload_length)
+        .ok_or(alloy_rlp::Error::InputTooShort)?;
 
-    while let Some(next) = view.get_next()? {
-        list.push(next);
+    let mut items = vec![];
+    while !payload.is_empty() {
+        let mut rest = payload;
+        let item_header = Header::decode(&mut rest)?;
+        let item_length = payload.len() - rest.len() + item_header.payload_length;
+        let (item, rest) = payload
+            .split_at_checked(item_length)
+            .ok_or(alloy_rlp::Error::InputTooShort)?;
+        items.push(item);
+        payload = rest;
     }
 
-    Ok(list)
+    Ok(items)
 }
 
+/// Decodes the path of a leaf or extension node.
+fn decode_path(mut item: &[u8]) -> &[u8] {
+    Header::decode_bytes(&mut item, false).unwrap()
+}
+
+/// Converts the encoding of a branch child into a 32-byte hash or an inline node.
+pub(crate) fn branch_child(item: &[u8]) -> &[u8] {
+    match item {
+        [EMPTY_STRING_CODE] => &[],
+        [head, hash @ ..] if *head == RLP_STR_OFFSET + 32 && hash.len() == 32 => hash,
+        inline => inline,
+    }
+}
+
+/// Builds the compact proof for the receipt at `index`, see [`crate::compact_proof`]. Works the same
+/// for any list of EIP-2718 items committed to by an ordered trie, such as transactions.
 pub fn get_proof_for_receipt<R>(items: &[R], index: u32) -> Vec<u8>
 where
     R: Encodable2718,
 {
     let proof_nodes = get_trie_proof_nodes(items, index);
-    let mut proof_steps = proof_nodes.into_inner().into_iter().collect::<Vec<_>>();
-    proof_steps.sort_by_key(|(key, _)| std::cmp::Reverse(key.len()));
+    let key = Nibbles::unpack(alloy_rlp::encode(index));
+    CompactProof::from_proof_nodes(&proof_nodes, &key)
+        .expect("Proof nodes from hash builder")
+        .encode()
+}
 
-    let leaf_path = {
-        let (_, full_bytes) = &proof_steps[0];
-        let mut as_list = rlp_decode(full_bytes).unwrap();
-        as_list.swap_remove(0)
-    };
-    let mut proof_builder = ProofBuilder::with_leaf_rest_path_compact(leaf_path);
-
-    let index_as_key = Nibbles::unpack({
-        let mut buf = Vec::<u8>::new();
-        index.encode(&mut buf);
-        buf
-    });
-
-    proof_steps[1..].iter().for_each(|(key, value)| {
-        let as_list = rlp_decode(value).unwrap();
-        if as_list.len() == 2 {
-            proof_builder.add_extension(&as_list[0]);
+/// Sorts receipt indices by their position in the trie, the order in which a multiproof expects the
+/// proven receipts.
+pub fn sort_by_trie_key(indices: &mut [u32]) {
+    indices.sort_by_cached_key(|index| alloy_rlp::encode(index));
+}
+
+/// Builds a proof for the distinct receipts at `indices` that contains every node shared between
+/// their paths once, see [`receipt_trie_root_from_multiproof`] for the format.
+pub fn get_multiproof_for_receipts<R>(items: &[R], indices: &[u32]) -> Vec<u8>
+where
+    R: Encodable2718,
+{
+    assert!(!indices.is_empty(), "Expected at least one index");
+    let nodes = get_trie_multiproof_nodes(items, indices).into_inner();
+
+    let mut proof = vec![MULTIPROOF_VERSION];
+    write_multiproof_node(&nodes, Nibbles::default(), &mut proof);
+    proof
+}
+
+fn write_multiproof_node(nodes: &HashMap<Nibbles, Bytes>, prefix: Nibbles, proof: &mut Vec<u8>) {
+    let as_list = rlp_list_items(&nodes[&prefix]).unwrap();
+    if as_list.len() == 2 {
+        let path = TriePath::new(decode_path(as_list[0]));
+        if path.is_leaf() {
+            proof.push(MULTIPROOF_LEAF);
+        } else {
+            write_multiproof_node(nodes, prefix.join(&path.to_nibbles()), proof);
+            proof.push(MULTIPROOF_EXTENSION);
+        }
+        proof.push(path.nibbles());
+        path.write_bytes(proof);
+        return;
+    }
+
+    assert_eq!(as_list.len(), 17, "Expected branch");
+    assert_eq!(
+        as_list[16],
+        [EMPTY_STRING_CODE],
+        "Expected empty branch value"
+    );
+    let mut stack_map = 0u16;
+    let mut sibling_map = 0u16;
+    let mut siblings = Vec::new();
+    for (i, item) in as_list[..16].iter().enumerate() {
+        let mut child_prefix = prefix.clone();
+        child_prefix.push(i as u8);
+        if nodes.contains_key(&child_prefix) {
+            write_multiproof_node(nodes, child_prefix, proof);
+            stack_map |= 1 << i;
         } else {
-            assert_eq!(as_list.len(), 17, "Expected branch");
-            let index = index_as_key[key.len()];
-            proof_builder.add_branch(index, as_list);
+            let child = branch_child(item);
+            if !child.is_empty() {
+                sibling_map |= 1 << i;
+                siblings.push(child);
+            }
         }
-    });
+    }
 
-    proof_builder.build()
+    proof.push(MULTIPROOF_BRANCH);
+    proof.extend_from_slice(&stack_map.to_be_bytes());
+    proof.extend_from_slice(&sibling_map.to_be_bytes());
+    for sibling in siblings {
+        proof.push(sibling.len() as u8);
+        proof.extend_from_slice(sibling);
+    }
 }
 
 pub(crate) const PATH_FLAG_MASK: u8 = 0x20;
@@ -91,175 +173,818 @@ pub(crate) const EXTENSION_PATH_FLAG: u8 = 0x00;
 pub(crate) const ODD_NIBBLES_FLAG: u8 = 0x10;
 pub(crate) const NIBBLE_MASK: u8 = 0xf;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofError {
+    Truncated(ReaderError),
+    /// Children of the branch node at `position` don't add up to its declared payload length.
+    BranchLengthMismatch {
+        position: usize,
+        declared: usize,
+        actual: usize,
+    },
+    /// Proof starts with a format version other than [`COMPACT_PROOF_VERSION`], or
+    /// [`MULTIPROOF_VERSION`] for multiproofs.
+    UnsupportedVersion {
+        version: u8,
+    },
+    /// Node at `position` has an unknown type.
+    InvalidNodeType {
+        position: usize,
+        node_type: u8,
+    },
+    /// Multiproof node at `position` refers to more child nodes than were proven before it.
+    MissingChildNodes {
+        position: usize,
+    },
+    /// Multiproof branch at `position` lists a child both as proven and as sibling.
+    OverlappingChildren {
+        position: usize,
+    },
+    /// Path or branch index at `position` doesn't lead to the key of the expected index.
+    KeyMismatch {
+        position: usize,
+    },
+    /// Branch at `position` doesn't list the proven child in its branch map.
+    MissingProvenChild {
+        position: usize,
+    },
+    /// Sibling at `position` is longer than a hash.
+    InvalidChildLength {
+        position: usize,
+        length: usize,
+    },
+    /// Sibling at `position` is shorter than a hash but not a complete RLP list, the encoding of
+    /// an inline node.
+    InvalidInlineChild {
+        position: usize,
+    },
+    /// Multiproof doesn't combine all proven nodes into a single root.
+    UnconnectedNodes {
+        roots: usize,
+    },
+    ReceiptCountMismatch {
+        proven: usize,
+        provided: usize,
+    },
+    /// Multiproof receipts weren't given with one index each.
+    IndexCountMismatch {
+        indices: usize,
+        receipts: usize,
+    },
+}
+
+impl From<ReaderError> for ProofError {
+    fn from(err: ReaderError) -> Self {
+        Self::Truncated(err)
+    }
+}
+
+impl std::fmt::Display for ProofError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated(err) => write!(f, "truncated proof: {}", err),
+            Self::BranchLengthMismatch {
+                position,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "branch at position {} declares payload length {} but has {}",
+                position, declared, actual
+            ),
+            Self::UnsupportedVersion { version } => {
+                write!(f, "unsupported proof version {}", version)
+            }
+            Self::InvalidNodeType {
+                position,
+                node_type,
+            } => write!(
+                f,
+                "invalid node type {:x} at position {}",
+                node_type, position
+            ),
+            Self::MissingChildNodes { position } => {
+                write!(f, "node at position {} is missing child nodes", position)
+            }
+            Self::OverlappingChildren { position } => write!(
+                f,
+                "branch at position {} has overlapping proven and sibling children",
+                position
+            ),
+            Self::KeyMismatch { position } => write!(
+                f,
+                "proof diverges from the expected key at position {}",
+                position
+            ),
+            Self::MissingProvenChild { position } => write!(
+                f,
+                "branch at position {} doesn't list the proven child",
+                position
+            ),
+            Self::InvalidChildLength { position, length } => write!(
+                f,
+                "sibling at position {} has invalid length {}",
+                position, length
+            ),
+            Self::InvalidInlineChild { position } => write!(
+                f,
+                "inline sibling at position {} is not an RLP list",
+                position
+            ),
+            Self::UnconnectedNodes { roots } => {
+                write!(f, "multiproof ends with {} instead of 1 root", roots)
+            }
+            Self::ReceiptCountMismatch { proven, provided } => write!(
+                f,
+                "multiproof proves {} receipts but {} were provided",
+                proven, provided
+            ),
+            Self::IndexCountMismatch { indices, receipts } => write!(
+                f,
+                "{} indices were provided for {} receipts",
+                indices, receipts
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
 const fn encoded_length(payload_length: usize) -> usize {
     length_of_length(payload_length) + payload_length
 }
 
-fn encode_header(keccak: &mut Keccak256, offset: u8, payload_length: usize) {
+/// Destination of an encoded trie node. Nodes are hashed unless their encoding is shorter than 32
+/// bytes, in which case they're embedded in their parent as is.
+trait NodeSink {
+    fn update(&mut self, bytes: &[u8]);
+}
+
+impl NodeSink for Keccak256 {
+    fn update(&mut self, bytes: &[u8]) {
+        Keccak256::update(self, bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct InlineNode {
+    encoded: [u8; 31],
+    len: usize,
+}
+
+impl NodeSink for InlineNode {
+    fn update(&mut self, bytes: &[u8]) {
+        // Overflowing bytes are dropped, callers reject nodes whose length is inconsistent.
+        if let Some(dest) = self.encoded.get_mut(self.len..self.len + bytes.len()) {
+            dest.copy_from_slice(bytes);
+        }
+        self.len += bytes.len();
+    }
+}
+
+/// How a node is referenced by its parent.
+#[derive(Debug, Clone, Copy)]
+enum NodeRef {
+    Hash(B256),
+    Inline(InlineNode),
+}
+
+impl NodeRef {
+    fn encoded_length(&self) -> usize {
+        match self {
+            Self::Hash(_) => 33,
+            Self::Inline(node) => node.len,
+        }
+    }
+
+    fn write(&self, sink: &mut dyn NodeSink) {
+        match self {
+            Self::Hash(hash) => {
+                encode_str_header(sink, 32);
+                sink.update(hash.as_slice());
+            }
+            Self::Inline(node) => sink.update(&node.encoded[..node.len.min(31)]),
+        }
+    }
+
+    fn into_root(self, keccak: &mut Keccak256) -> B256 {
+        match self {
+            Self::Hash(hash) => hash,
+            Self::Inline(node) => {
+                keccak.update(&node.encoded[..node.len.min(31)]);
+                let mut hash = [0u8; 32];
+                keccak.finalize_and_reset(&mut hash);
+                B256::from(hash)
+            }
+        }
+    }
+}
+
+/// Encodes a node of `encoded_length` bytes with `write`, hashing it unless it's short enough to
+/// be inlined.
+fn encode_node(
+    keccak: &mut Keccak256,
+    encoded_length: usize,
+    write: impl FnOnce(&mut dyn NodeSink) -> Result<(), ProofError>,
+) -> Result<NodeRef, ProofError> {
+    if encoded_length < 32 {
+        let mut node = InlineNode::default();
+        write(&mut node)?;
+        Ok(NodeRef::Inline(node))
+    } else {
+        write(keccak)?;
+        let mut hash = [0u8; 32];
+        keccak.finalize_and_reset(&mut hash);
+        Ok(NodeRef::Hash(B256::from(hash)))
+    }
+}
+
+fn encode_header(sink: &mut dyn NodeSink, offset: u8, payload_length: usize) {
     if payload_length <= RLP_MAX_PACKED_LEN as usize {
         let head_byte = offset + payload_length as u8;
-        keccak.update(&[head_byte]);
+        sink.update(&[head_byte]);
     } else {
         let length_bytes_length: usize = length_of_length(payload_length) - 1;
         let head_byte = offset + RLP_MAX_PACKED_LEN + length_bytes_length as u8;
-        keccak.update(&[head_byte]);
+        sink.update(&[head_byte]);
 
         let bytes = payload_length.to_be_bytes();
-        keccak.update(&bytes[(usize::BITS / 8) as usize - length_bytes_length..]);
+        sink.update(&bytes[(usize::BITS / 8) as usize - length_bytes_length..]);
     }
 }
 
-fn encode_list_header(hasher: &mut Keccak256, payload_length: usize) {
-    encode_header(hasher, RLP_LIST_OFFSET, payload_length)
+fn encode_list_header(sink: &mut dyn NodeSink, payload_length: usize) {
+    encode_header(sink, RLP_LIST_OFFSET, payload_length)
 }
 
-fn encode_str_header(hasher: &mut Keccak256, payload_length: usize) {
-    encode_header(hasher, RLP_STR_OFFSET, payload_length)
+fn encode_str_header(sink: &mut dyn NodeSink, payload_length: usize) {
+    encode_header(sink, RLP_STR_OFFSET, payload_length)
+}
+
+/// Value of a leaf or extension node: an RLP string or a child node.
+#[derive(Clone, Copy)]
+enum PathNodeValue<'a> {
+    Bytes(&'a [u8]),
+    Child(&'a NodeRef),
+}
+
+impl PathNodeValue<'_> {
+    fn encoded_length(&self) -> usize {
+        match self {
+            Self::Bytes([b]) if *b < RLP_STR_OFFSET => 1,
+            Self::Bytes(bytes) => encoded_length(bytes.len()),
+            Self::Child(child) => child.encoded_length(),
+        }
+    }
+
+    fn write(&self, sink: &mut dyn NodeSink) {
+        match self {
+            Self::Bytes([b]) if *b < RLP_STR_OFFSET => sink.update(&[*b]),
+            Self::Bytes(bytes) => {
+                encode_str_header(sink, bytes.len());
+                sink.update(bytes);
+            }
+            Self::Child(child) => child.write(sink),
+        }
+    }
 }
 
 fn hash_node_with_path(
     keccak: &mut Keccak256,
     proof: &mut Reader,
     path_flag: u8,
-    encoded_internal_node: &[u8],
-) -> B256 {
+    value: PathNodeValue,
+) -> Result<NodeRef, ProofError> {
     // Determine length of encoded key.
-    let leaf_key_nibbles = proof.read_byte();
+    let leaf_key_nibbles = proof.try_read_byte()?;
     let key_bytes = leaf_key_nibbles as usize / 2;
     let encoded_key_length = encoded_length(key_bytes + 1) - (key_bytes == 0) as usize;
 
-    let encoded_receipt_length = encoded_length(encoded_internal_node.len());
+    let rlp_list_payload_length = encoded_key_length + value.encoded_length();
 
-    // Push head.
-    let rlp_list_payload_length = encoded_key_length + encoded_receipt_length;
-    encode_list_header(keccak, rlp_list_payload_length);
+    encode_node(keccak, encoded_length(rlp_list_payload_length), |sink| {
+        // Push head.
+        encode_list_header(sink, rlp_list_payload_length);
 
-    // Push key
-    let first_byte = if leaf_key_nibbles % 2 == 0 {
-        path_flag
-    } else {
-        let odd_nibble = proof.read_byte() & NIBBLE_MASK;
-        path_flag | ODD_NIBBLES_FLAG | odd_nibble
-    };
-    if key_bytes >= 1 || first_byte > 0x7f || first_byte == 0 {
-        encode_str_header(keccak, key_bytes + 1);
-    }
-    keccak.update(&[first_byte]);
-    keccak.update(proof.read_next(key_bytes));
+        // Push key
+        let first_byte = if leaf_key_nibbles % 2 == 0 {
+            path_flag
+        } else {
+            let odd_nibble = proof.try_read_byte()? & NIBBLE_MASK;
+            path_flag | ODD_NIBBLES_FLAG | odd_nibble
+        };
+        if key_bytes >= 1 || first_byte > 0x7f || first_byte == 0 {
+            encode_str_header(sink, key_bytes + 1);
+        }
+        sink.update(&[first_byte]);
+        sink.update(proof.try_read_next(key_bytes)?);
+
+        // Push value
+        value.write(sink);
+        Ok(())
+    })
+}
+
+fn hash_leaf(
+    keccak: &mut Keccak256,
+    proof: &mut Reader,
+    encoded_receipt: &[u8],
+) -> Result<NodeRef, ProofError> {
+    hash_node_with_path(
+        keccak,
+        proof,
+        LEAF_PATH_FLAG,
+        PathNodeValue::Bytes(encoded_receipt),
+    )
+}
 
-    // Push receipt
-    encode_str_header(keccak, encoded_internal_node.len());
-    keccak.update(encoded_internal_node);
+fn hash_extension(
+    keccak: &mut Keccak256,
+    proof: &mut Reader,
+    child: &NodeRef,
+) -> Result<NodeRef, ProofError> {
+    hash_node_with_path(
+        keccak,
+        proof,
+        EXTENSION_PATH_FLAG,
+        PathNodeValue::Child(child),
+    )
+}
 
-    let mut hash = [0u8; 32];
-    keccak.finalize_and_reset(&mut hash);
-    B256::from(hash)
+pub(crate) fn read_u32_length(proof: &mut Reader) -> Result<usize, ReaderError> {
+    Ok(u32::from_be_bytes(*proof.try_read_array::<4>()?) as usize)
 }
 
-fn hash_leaf(keccak: &mut Keccak256, proof: &mut Reader, encoded_receipt: &[u8]) -> B256 {
-    hash_node_with_path(keccak, proof, LEAF_PATH_FLAG, encoded_receipt)
+/// Reads a sibling in the length-prefixed branch layout, either a hash or an inline node shorter
+/// than a hash.
+pub(crate) fn read_sibling<'a>(proof: &mut Reader<'a>) -> Result<&'a [u8], ProofError> {
+    let position = proof.position();
+    let length = read_u32_length(proof)?;
+    if length > 32 {
+        return Err(ProofError::InvalidChildLength { position, length });
+    }
+    let sibling = proof.try_read_next(length)?;
+    if length < 32 {
+        check_inline_child(position, sibling)?;
+    }
+    Ok(sibling)
 }
 
-fn hash_extension(keccak: &mut Keccak256, proof: &mut Reader, encoded_receipt: &[u8]) -> B256 {
-    hash_node_with_path(keccak, proof, EXTENSION_PATH_FLAG, encoded_receipt)
+/// Checks that a sibling shorter than a hash is an inline node, i.e. a complete RLP list. Inline
+/// siblings are hashed as is, so any other bytes could shift parts of other children into them.
+fn check_inline_child(position: usize, child: &[u8]) -> Result<(), ProofError> {
+    match child {
+        [header, payload @ ..]
+            if !payload.is_empty()
+                && usize::from(*header) == usize::from(EMPTY_LIST_CODE) + payload.len() =>
+        {
+            Ok(())
+        }
+        _ => Err(ProofError::InvalidInlineChild { position }),
+    }
 }
 
-/// Computes the hash of a branch node with one hash of a previous node, assumes that all other
-/// paths are either empty or themselves 32-byte hashes.
+/// Reads the map of a branch's non-empty children, which has to include the proven child at
+/// `index`.
+pub(crate) fn read_branch_map(proof: &mut Reader, index: u8) -> Result<u16, ProofError> {
+    let position = proof.position();
+    let branch_map = u16::from_be_bytes(*proof.try_read_array::<2>()?);
+    if branch_map & (1 << index) == 0 {
+        return Err(ProofError::MissingProvenChild { position });
+    }
+    Ok(branch_map)
+}
+
+/// Computes the hash of a branch node with one hash of a previous node. Unless `weird_branches` is
+/// set assumes that all other paths are either empty or themselves 32-byte hashes. Otherwise the
+/// children are length-prefixed, 32-byte children being hashes and shorter ones inline nodes.
 fn hash_branch(
     keccak: &mut Keccak256,
     proof: &mut Reader,
     weird_branches: bool,
     index: u8,
-    last_root: &[u8],
-) -> B256 {
-    let branch_map: u16 = u16::from_be_bytes([proof.read_byte(), proof.read_byte()]);
+    last_node: &NodeRef,
+) -> Result<NodeRef, ProofError> {
+    let position = proof.position();
+    let branch_map = read_branch_map(proof, index)?;
 
     let payload_length = if weird_branches {
-        u32::from_be_bytes([
-            proof.read_byte(),
-            proof.read_byte(),
-            proof.read_byte(),
-            proof.read_byte(),
-        ])
-        .try_into()
-        .unwrap()
+        read_u32_length(proof)?
     } else {
         TryInto::<usize>::try_into(branch_map.count_ones()).unwrap() * 32 + 17
     };
 
-    encode_list_header(keccak, payload_length);
-
-    let mut add_sibling = |k: &mut Keccak256, i: u8| {
-        if branch_map & (1 << i) == 0 {
-            encode_str_header(k, 0);
-        } else if weird_branches {
-            let payload_length = u32::from_be_bytes([
-                proof.read_byte(),
-                proof.read_byte(),
-                proof.read_byte(),
-                proof.read_byte(),
-            ])
-            .try_into()
-            .unwrap();
-            encode_str_header(k, payload_length);
-            k.update(proof.read_next(payload_length));
-        } else {
-            encode_str_header(k, 32);
-            k.update(proof.read_next(32));
+    let mut actual_length = 0;
+    let node = encode_node(keccak, encoded_length(payload_length), |sink| {
+        encode_list_header(sink, payload_length);
+
+        let mut add_sibling = |sink: &mut dyn NodeSink, i: u8| -> Result<usize, ProofError> {
+            if branch_map & (1 << i) == 0 {
+                encode_str_header(sink, 0);
+                Ok(1)
+            } else if weird_branches {
+                let child = read_sibling(proof)?;
+                if child.len() == 32 {
+                    encode_str_header(sink, 32);
+                    sink.update(child);
+                    Ok(33)
+                } else {
+                    sink.update(child);
+                    Ok(child.len())
+                }
+            } else {
+                encode_str_header(sink, 32);
+                sink.update(proof.try_read_next(32)?);
+                Ok(33)
+            }
+        };
+
+        for i in 0..index {
+            actual_length += add_sibling(sink, i)?;
         }
-    };
 
-    for i in 0..index {
-        add_sibling(keccak, i);
-    }
+        last_node.write(sink);
+        actual_length += last_node.encoded_length();
+
+        for i in index + 1..16 {
+            actual_length += add_sibling(sink, i)?;
+        }
 
-    encode_str_header(keccak, 32);
-    keccak.update(last_root);
+        // Empty branch node value.
+        encode_str_header(sink, 0);
+        actual_length += 1;
+        Ok(())
+    })?;
 
-    for i in index + 1..16 {
-        add_sibling(keccak, i);
+    if actual_length != payload_length {
+        return Err(ProofError::BranchLengthMismatch {
+            position,
+            declared: payload_length,
+            actual: actual_length,
+        });
     }
+    Ok(node)
+}
+
+/// Version of the compact proof format, the first byte of every proof.
+pub const COMPACT_PROOF_VERSION: u8 = 0x01;
 
-    // Empty branch node value.
-    encode_str_header(keccak, 0);
+pub(crate) const EXTENSION_NODE_FLAG: u8 = 0x00u8;
+pub(crate) const BRANCH_NODE_FLAG: u8 = 0x20u8;
+pub(crate) const WEIRD_BRANCHES_FLAG: u8 = 0x10u8;
+pub(crate) const BRANCH_NODE_INDEX_MASK: u8 = 0x0fu8;
+
+/// Node a compact proof step adds on top of the nodes proven before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StepKind {
+    Extension,
+    Branch { index: u8, weird_branches: bool },
+}
 
-    let mut node_hash = [0u8; 32];
-    keccak.finalize_and_reset(&mut node_hash);
-    B256::from(node_hash)
+pub(crate) fn read_version(proof: &mut Reader) -> Result<(), ProofError> {
+    match proof.try_read_byte()? {
+        COMPACT_PROOF_VERSION => Ok(()),
+        version => Err(ProofError::UnsupportedVersion { version }),
+    }
 }
 
-const PROOF_PART_TYPE_MASK: u8 = 0x20u8;
-const EXTENSION_NODE_FLAG: u8 = 0x00u8;
-const BRANCH_NODE_FLAG: u8 = 0x20u8;
-const WEIRD_BRANCHES_FLAG: u8 = 0x10u8;
-const BRANCH_NODE_INDEX_MASK: u8 = 0x0fu8;
+pub(crate) fn read_step_kind(proof: &mut Reader) -> Result<StepKind, ProofError> {
+    let position = proof.position();
+    let control_byte = proof.try_read_byte()?;
+    let index = control_byte & BRANCH_NODE_INDEX_MASK;
+    match control_byte & !BRANCH_NODE_INDEX_MASK {
+        EXTENSION_NODE_FLAG if index == 0 => Ok(StepKind::Extension),
+        BRANCH_NODE_FLAG => Ok(StepKind::Branch {
+            index,
+            weird_branches: false,
+        }),
+        flags if flags == BRANCH_NODE_FLAG | WEIRD_BRANCHES_FLAG => Ok(StepKind::Branch {
+            index,
+            weird_branches: true,
+        }),
+        _ => Err(ProofError::InvalidNodeType {
+            position,
+            node_type: control_byte,
+        }),
+    }
+}
 
+/// Recomputes the receipts root from a compact proof built by [`get_proof_for_receipt`], failing
+/// if the proof is malformed. See [`crate::compact_proof`] for the format.
+///
+/// Works the same for any trie of EIP-2718 items keyed by their index such as the transactions
+/// trie, but doesn't check which index the item is at, see [`trie_root_from_indexed_proof`].
 pub fn receipt_trie_root_from_proof(
     keccak: &mut Keccak256,
     proof: impl AsRef<[u8]>,
     encoded_receipt: impl AsRef<[u8]>,
-) -> B256 {
-    let mut proof = Reader::from(proof.as_ref());
-    let mut current_root = hash_leaf(keccak, &mut proof, encoded_receipt.as_ref());
+) -> Result<B256, ProofError> {
+    trie_root_from_proof(keccak, proof.as_ref(), encoded_receipt.as_ref(), None)
+}
+
+/// Recomputes the root of a trie of EIP-2718 items from a compact proof, failing unless the proof
+/// is for the item at `index`. Allows binding items of different tries like a transaction and its
+/// receipt to each other.
+pub fn trie_root_from_indexed_proof(
+    keccak: &mut Keccak256,
+    proof: impl AsRef<[u8]>,
+    index: u32,
+    encoded_item: impl AsRef<[u8]>,
+) -> Result<B256, ProofError> {
+    let key = ExpectedKey::new(index);
+    trie_root_from_proof(keccak, proof.as_ref(), encoded_item.as_ref(), Some(key))
+}
+
+fn trie_root_from_proof(
+    keccak: &mut Keccak256,
+    proof: &[u8],
+    encoded_item: &[u8],
+    mut key: Option<ExpectedKey>,
+) -> Result<B256, ProofError> {
+    let mut proof = Reader::from(proof);
+    read_version(&mut proof)?;
+    if let Some(key) = &mut key {
+        key.strip_path(proof.clone())?;
+    }
+    let mut current_node = hash_leaf(keccak, &mut proof, encoded_item)?;
 
     while !proof.is_empty() {
-        let control_byte = proof.read_byte();
-        if control_byte & PROOF_PART_TYPE_MASK == BRANCH_NODE_FLAG {
-            let index = control_byte & BRANCH_NODE_INDEX_MASK;
-            current_root = hash_branch(
-                keccak,
-                &mut proof,
-                control_byte & WEIRD_BRANCHES_FLAG != 0,
+        let position = proof.position();
+        current_node = match read_step_kind(&mut proof)? {
+            StepKind::Branch {
                 index,
-                current_root.as_slice(),
-            );
-        } else {
-            current_root = hash_extension(keccak, &mut proof, current_root.as_slice());
+                weird_branches,
+            } => {
+                if let Some(key) = &mut key {
+                    key.strip_suffix(position, 1, [index].into_iter())?;
+                }
+                hash_branch(keccak, &mut proof, weird_branches, index, &current_node)?
+            }
+            StepKind::Extension => {
+                if let Some(key) = &mut key {
+                    key.strip_path(proof.clone())?;
+                }
+                hash_extension(keccak, &mut proof, &current_node)?
+            }
+        };
+    }
+
+    if key.is_some_and(|key| key.remaining != 0) {
+        return Err(ProofError::KeyMismatch {
+            position: proof.position(),
+        });
+    }
+    Ok(current_node.into_root(keccak))
+}
+
+/// Key of the item at an index, matched against the paths of a proof from the leaf up.
+#[derive(Debug, Clone, Copy)]
+struct ExpectedKey {
+    /// The RLP encoding of a `u32` is at most 5 bytes long.
+    nibbles: [u8; 10],
+    remaining: usize,
+}
+
+impl ExpectedKey {
+    fn new(index: u32) -> Self {
+        let encoded = encode_fixed_size(&index);
+        let mut nibbles = [0u8; 10];
+        for (pair, byte) in nibbles.chunks_exact_mut(2).zip(encoded.iter()) {
+            pair.copy_from_slice(&[byte >> 4, byte & NIBBLE_MASK]);
+        }
+        Self {
+            nibbles,
+            remaining: 2 * encoded.len(),
         }
     }
 
-    current_root
+    /// Matches the `len` nibbles of `path` against the end of the key that wasn't matched yet.
+    fn strip_suffix(
+        &mut self,
+        position: usize,
+        len: usize,
+        path: impl Iterator<Item = u8>,
+    ) -> Result<(), ProofError> {
+        let start = self
+            .remaining
+            .checked_sub(len)
+            .ok_or(ProofError::KeyMismatch { position })?;
+        if !path.eq(self.nibbles[start..self.remaining].iter().copied()) {
+            return Err(ProofError::KeyMismatch { position });
+        }
+        self.remaining = start;
+        Ok(())
+    }
+
+    /// Matches the path at the start of `proof`.
+    fn strip_path(&mut self, mut proof: Reader) -> Result<(), ProofError> {
+        let position = proof.position();
+        let nibble_count = proof.try_read_byte()?;
+        let odd_nibble = match nibble_count % 2 {
+            0 => None,
+            _ => Some(proof.try_read_byte()? & NIBBLE_MASK),
+        };
+        let packed = proof.try_read_next(usize::from(nibble_count / 2))?;
+        let path = odd_nibble.into_iter().chain(
+            packed
+                .iter()
+                .flat_map(|byte| [byte >> 4, byte & NIBBLE_MASK]),
+        );
+        self.strip_suffix(position, usize::from(nibble_count), path)
+    }
+}
+
+/// Version of the multiproof format, the first byte of every multiproof.
+pub const MULTIPROOF_VERSION: u8 = 0x01;
+
+const MULTIPROOF_LEAF: u8 = 0x00;
+const MULTIPROOF_EXTENSION: u8 = 0x01;
+const MULTIPROOF_BRANCH: u8 = 0x02;
+
+/// Recomputes the receipts root from a multiproof built by [`get_multiproof_for_receipts`], hashing
+/// every node once, and checks that each of `encoded_receipts` is the receipt at the index at the
+/// same position of `indices`. Both have to be ordered by the position in the trie, see
+/// [`sort_by_trie_key`].
+///
+/// The multiproof starts with [`MULTIPROOF_VERSION`] followed by the nodes spanned by the proven
+/// receipts in post-order, each node combining the most recent nodes before it:
+/// - `0x00 ‖ path`: leaf holding the next receipt.
+/// - `0x01 ‖ path`: extension of the last node.
+/// - `0x02 ‖ stack_map: u16 ‖ sibling_map: u16 ‖ (length: u8 ‖ sibling)*`: branch whose children
+///   in `stack_map` are the last nodes and in `sibling_map` 32-byte hashes or inline nodes.
+///
+/// Paths are encoded as in single receipt proofs.
+pub fn receipt_trie_root_from_multiproof<R: AsRef<[u8]>>(
+    keccak: &mut Keccak256,
+    proof: impl AsRef<[u8]>,
+    indices: &[u32],
+    encoded_receipts: &[R],
+) -> Result<B256, ProofError> {
+    if indices.len() != encoded_receipts.len() {
+        return Err(ProofError::IndexCountMismatch {
+            indices: indices.len(),
+            receipts: encoded_receipts.len(),
+        });
+    }
+    let mut proof = Reader::from(proof.as_ref());
+    match proof.try_read_byte()? {
+        MULTIPROOF_VERSION => {}
+        version => return Err(ProofError::UnsupportedVersion { version }),
+    }
+
+    let mut receipts = indices.iter().zip(encoded_receipts);
+    let mut proven = 0;
+    // Keys of the proven receipts, matched against the paths above them as the nodes are combined.
+    // The receipts below a node are consecutive, starting at the node's entry of `first_keys`.
+    let mut keys: Vec<ExpectedKey> = Vec::new();
+    let mut nodes: Vec<NodeRef> = Vec::new();
+    let mut first_keys: Vec<usize> = Vec::new();
+
+    while !proof.is_empty() {
+        let position = proof.position();
+        match proof.try_read_byte()? {
+            MULTIPROOF_LEAF => {
+                proven += 1;
+                let (&index, receipt) =
+                    receipts.next().ok_or(ProofError::ReceiptCountMismatch {
+                        proven,
+                        provided: encoded_receipts.len(),
+                    })?;
+                let mut key = ExpectedKey::new(index);
+                key.strip_path(proof.clone())?;
+                first_keys.push(keys.len());
+                keys.push(key);
+                nodes.push(hash_leaf(keccak, &mut proof, receipt.as_ref())?);
+            }
+            MULTIPROOF_EXTENSION => {
+                let child = nodes
+                    .pop()
+                    .ok_or(ProofError::MissingChildNodes { position })?;
+                let first_key = *first_keys.last().expect("one entry per node");
+                for key in &mut keys[first_key..] {
+                    key.strip_path(proof.clone())?;
+                }
+                nodes.push(hash_extension(keccak, &mut proof, &child)?);
+            }
+            MULTIPROOF_BRANCH => {
+                let stack_map = u16::from_be_bytes(*proof.try_read_array::<2>()?);
+                let children = stack_map.count_ones() as usize;
+                let first_child = nodes
+                    .len()
+                    .checked_sub(children)
+                    .ok_or(ProofError::MissingChildNodes { position })?;
+
+                // The proven children are the last nodes, in ascending order of their nibble.
+                let child_nibbles = (0..16u8).filter(|nibble| stack_map & (1 << nibble) != 0);
+                let key_ends = first_keys[first_child + 1..]
+                    .iter()
+                    .copied()
+                    .chain([keys.len()]);
+                for ((nibble, &start), end) in
+                    child_nibbles.zip(&first_keys[first_child..]).zip(key_ends)
+                {
+                    for key in &mut keys[start..end] {
+                        key.strip_suffix(position, 1, [nibble].into_iter())?;
+                    }
+                }
+
+                let node = hash_multiproof_branch(
+                    keccak,
+                    &mut proof,
+                    position,
+                    stack_map,
+                    &nodes[first_child..],
+                )?;
+                let first_key = first_keys.get(first_child).copied().unwrap_or(keys.len());
+                nodes.truncate(first_child);
+                nodes.push(node);
+                first_keys.truncate(first_child);
+                first_keys.push(first_key);
+            }
+            node_type => {
+                return Err(ProofError::InvalidNodeType {
+                    position,
+                    node_type,
+                })
+            }
+        }
+    }
+
+    if proven != encoded_receipts.len() {
+        return Err(ProofError::ReceiptCountMismatch {
+            proven,
+            provided: encoded_receipts.len(),
+        });
+    }
+    let [root] = nodes[..] else {
+        return Err(ProofError::UnconnectedNodes { roots: nodes.len() });
+    };
+    if keys.iter().any(|key| key.remaining != 0) {
+        return Err(ProofError::KeyMismatch {
+            position: proof.position(),
+        });
+    }
+    Ok(root.into_root(keccak))
+}
+
+/// Computes the hash of a multiproof branch, `children` being the proven children in `stack_map` in
+/// ascending order.
+fn hash_multiproof_branch(
+    keccak: &mut Keccak256,
+    proof: &mut Reader,
+    position: usize,
+    stack_map: u16,
+    children: &[NodeRef],
+) -> Result<NodeRef, ProofError> {
+    let sibling_map = u16::from_be_bytes(*proof.try_read_array::<2>()?);
+    if stack_map & sibling_map != 0 {
+        return Err(ProofError::OverlappingChildren { position });
+    }
+
+    // Determine the payload length up front, empty children and the value taking up a byte each.
+    let mut payload_length = (16 - (stack_map | sibling_map).count_ones() as usize) + 1;
+    payload_length += children.iter().map(NodeRef::encoded_length).sum::<usize>();
+    let mut siblings = proof.clone();
+    for _ in 0..sibling_map.count_ones() {
+        let length_position = siblings.position();
+        let length = siblings.try_read_byte()?;
+        if length > 32 {
+            return Err(ProofError::InvalidChildLength {
+                position: length_position,
+                length: usize::from(length),
+            });
+        }
+        let sibling = siblings.try_read_next(usize::from(length))?;
+        payload_length += if length == 32 {
+            33
+        } else {
+            check_inline_child(length_position, sibling)?;
+            sibling.len()
+        };
+    }
+
+    let mut children = children.iter();
+    encode_node(keccak, encoded_length(payload_length), |sink| {
+        encode_list_header(sink, payload_length);
+        for i in 0..16 {
+            if stack_map & (1 << i) != 0 {
+                children.next().expect("one child per set bit").write(sink);
+            } else if sibling_map & (1 << i) != 0 {
+                let length = proof.try_read_byte()?;
+                let sibling = proof.try_read_next(usize::from(length))?;
+                if length == 32 {
+                    encode_str_header(sink, 32);
+                }
+                sink.update(sibling);
+            } else {
+                encode_str_header(sink, 0);
+            }
+        }
+
+        // Empty branch node value.
+        encode_str_header(sink, 0);
+        Ok(())
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -270,7 +995,8 @@ impl ProofBuilder {
         let path = TriePath::new(path.as_ref());
         assert!(path.is_leaf(), "Not leaf path but extension node path");
 
-        let mut leaf = Vec::with_capacity(1 + path.bytes() as usize);
+        let mut leaf = Vec::with_capacity(2 + path.bytes() as usize);
+        leaf.push(COMPACT_PROOF_VERSION);
         leaf.push(path.nibbles());
         path.write_bytes(&mut leaf);
         Self(leaf)
@@ -286,32 +1012,58 @@ impl ProofBuilder {
         path.write_bytes(self);
     }
 
+    /// Adds a branch node whose child at `index` is the node proven so far. Each of the 16 `nodes`
+    /// is either empty, a 32-byte hash or the encoding of a node shorter than 32 bytes that's
+    /// inlined in the branch. The latter require the length-prefixed layout.
     pub fn add_branch<B: AsRef<[u8]>>(&mut self, index: u8, nodes: impl AsRef<[B]>) {
         assert!(index <= 15, "Not nibble: {}", index);
         let nodes = nodes.as_ref();
+        assert_eq!(nodes.len(), 16, "Expected 16 branch children");
 
         let mut branch_map = 0u16;
+        let mut weird_branches = false;
         for (i, node) in nodes.iter().enumerate() {
-            if node.as_ref().len() == 32 {
-                branch_map |= 1 << i;
-            } else {
-                assert!(
-                    node.as_ref().len() == 0,
-                    "Weird branches where nodes are not empty/hashes is not currently supported"
-                );
+            match node.as_ref().len() {
+                0 => {}
+                32 => branch_map |= 1 << i,
+                len => {
+                    assert!(len < 32, "Inline node must be shorter than 32 bytes");
+                    branch_map |= 1 << i;
+                    weird_branches = true;
+                }
             }
         }
 
-        self.push(BRANCH_NODE_FLAG | index);
-        self.extend_from_slice(&branch_map.to_be_bytes());
-
-        nodes
+        let siblings = nodes
             .iter()
             .enumerate()
-            .filter(|(i, node)| *i != index as usize && node.as_ref().len() == 32)
-            .for_each(|(_, node)| {
-                self.extend_from_slice(node.as_ref());
-            });
+            .filter(|(i, node)| *i != index as usize && !node.as_ref().is_empty())
+            .map(|(_, node)| node.as_ref());
+
+        if !weird_branches {
+            self.push(BRANCH_NODE_FLAG | index);
+            self.extend_from_slice(&branch_map.to_be_bytes());
+            siblings.for_each(|node| self.extend_from_slice(node));
+            return;
+        }
+
+        let payload_length: usize = nodes
+            .iter()
+            .map(|node| match node.as_ref().len() {
+                0 => 1,
+                32 => 33,
+                len => len,
+            })
+            .sum::<usize>()
+            + 1;
+
+        self.push(BRANCH_NODE_FLAG | WEIRD_BRANCHES_FLAG | index);
+        self.extend_from_slice(&branch_map.to_be_bytes());
+        self.extend_from_slice(&(payload_length as u32).to_be_bytes());
+        for node in siblings {
+            self.extend_from_slice(&(node.len() as u32).to_be_bytes());
+            self.extend_from_slice(node);
+        }
     }
 
     pub fn build(self) -> Vec<u8> {
@@ -332,3 +1084,364 @@ impl std::ops::DerefMut for ProofBuilder {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloy_eips::Typed2718;
+    use alloy_primitives::bytes::BufMut;
+    use alloy_trie::root::ordered_trie_root_with_encoder;
+
+    /// Trie item whose 2718 encoding is just its bytes, allowing for arbitrarily small leaves.
+    pub(crate) struct Blob(pub(crate) Vec<u8>);
+
+    impl Typed2718 for Blob {
+        fn ty(&self) -> u8 {
+            0
+        }
+    }
+
+    impl Encodable2718 for Blob {
+        fn encode_2718_len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn encode_2718(&self, out: &mut dyn BufMut) {
+            out.put_slice(&self.0);
+        }
+    }
+
+    fn assert_all_proofs_round_trip(items: &[Blob]) {
+        let expected_root =
+            ordered_trie_root_with_encoder(items, |item, out| item.encode_2718(out));
+        let mut keccak = Keccak256::default();
+        for (index, item) in items.iter().enumerate() {
+            let proof = get_proof_for_receipt(items, index as u32);
+            assert_eq!(
+                receipt_trie_root_from_proof(&mut keccak, &proof, &item.0),
+                Ok(expected_root),
+                "item #{} of {}",
+                index,
+                items.len()
+            );
+            assert_eq!(
+                trie_root_from_indexed_proof(&mut keccak, &proof, index as u32, &item.0),
+                Ok(expected_root)
+            );
+            for other_index in [index + 1, index + 128, index ^ 1] {
+                assert!(matches!(
+                    trie_root_from_indexed_proof(&mut keccak, &proof, other_index as u32, &item.0),
+                    Err(ProofError::KeyMismatch { .. })
+                ));
+            }
+        }
+    }
+
+    pub(crate) fn blobs(lengths: impl IntoIterator<Item = usize>) -> Vec<Blob> {
+        lengths
+            .into_iter()
+            .enumerate()
+            .map(|(i, len)| Blob(vec![0x40u8.wrapping_add(i as u8); len]))
+            .collect()
+    }
+
+    #[test]
+    fn hashed_nodes_round_trip() {
+        for count in [1, 2, 17, 129] {
+            assert_all_proofs_round_trip(&blobs((0..count).map(|i| 40 + i % 50)));
+        }
+    }
+
+    #[test]
+    fn inline_nodes_round_trip() {
+        // Single byte values below 0x80 are encoded without a string header.
+        assert_all_proofs_round_trip(&blobs([1]));
+        assert_all_proofs_round_trip(&[Blob(vec![0x81])]);
+        assert_all_proofs_round_trip(&blobs([1, 2, 3]));
+        assert_all_proofs_round_trip(&blobs([100, 1, 2, 70, 3]));
+        for count in [16, 17, 130] {
+            assert_all_proofs_round_trip(&blobs((0..count).map(|i| (i * 7) % 45 + 1)));
+        }
+    }
+
+    #[test]
+    fn weird_branch_layout() {
+        let mut builder = ProofBuilder::with_leaf_rest_path_compact([0x20]);
+        let inline_leaf = [0xc2, 0x20, 0x01];
+        let hash = [0xab; 32];
+        let mut children = [&[][..]; 16];
+        children[1] = &hash;
+        children[3] = &inline_leaf;
+        children[4] = &hash;
+        builder.add_branch(4, children);
+
+        let proof = builder.build();
+        let mut expected = vec![
+            COMPACT_PROOF_VERSION,
+            0,
+            BRANCH_NODE_FLAG | WEIRD_BRANCHES_FLAG | 4,
+        ];
+        expected.extend_from_slice(&0b11010u16.to_be_bytes());
+        expected.extend_from_slice(&(13u32 + 33 + 3 + 33 + 1).to_be_bytes());
+        expected.extend_from_slice(&32u32.to_be_bytes());
+        expected.extend_from_slice(&hash);
+        expected.extend_from_slice(&3u32.to_be_bytes());
+        expected.extend_from_slice(&inline_leaf);
+        assert_eq!(proof, expected);
+    }
+
+    #[test]
+    fn inconsistent_branch_length_errors() {
+        let items = blobs((0..20).map(|i| (i * 7) % 45 + 1));
+        let index = items
+            .iter()
+            .enumerate()
+            .position(|(index, item)| {
+                let proof = get_proof_for_receipt(&items, index as u32);
+                item.0.len() > 30 && proof.len() > 2
+            })
+            .unwrap();
+
+        let mut proof = get_proof_for_receipt(&items, index as u32);
+        let leaf_length = 2 + usize::from(proof[1] / 2) + usize::from(proof[1] % 2);
+        assert_ne!(proof[leaf_length] & WEIRD_BRANCHES_FLAG, 0);
+        // Declared payload length follows the control byte and the branch map.
+        let length_offset = leaf_length + 3;
+        proof[length_offset + 3] -= 1;
+
+        assert!(matches!(
+            receipt_trie_root_from_proof(&mut Keccak256::default(), &proof, &items[index].0),
+            Err(ProofError::BranchLengthMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn spliced_inline_siblings_error() {
+        use crate::compact_proof::{BranchLayout, CompactProof, ProofStep};
+
+        // Items 1 and 2 are inline leaves at nibbles 1 and 2 of a branch whose nibble 0 is empty.
+        let items = blobs([1, 2, 3]);
+        let real = CompactProof::decode(&get_proof_for_receipt(&items, 2)).unwrap();
+        let ProofStep::Branch {
+            index: 2,
+            siblings,
+            layout: BranchLayout::LengthPrefixed { payload_length },
+        } = &real.steps[0]
+        else {
+            panic!("Expected branch with inline children");
+        };
+        let leaf_1 = &siblings[1];
+
+        // Claim that item 2 is at nibble 1 by moving the empty child and leaf 1 in front of it into
+        // a raw sibling at nibble 0, and an empty sibling at nibble 2, which hashes the same bytes.
+        let mut forged = CompactProof {
+            leaf_path: real.leaf_path.clone(),
+            steps: Vec::new(),
+        }
+        .encode();
+        forged.push(BRANCH_NODE_FLAG | WEIRD_BRANCHES_FLAG | 1);
+        forged.extend_from_slice(&0b111u16.to_be_bytes());
+        forged.extend_from_slice(&payload_length.to_be_bytes());
+        let sibling_position = forged.len();
+        forged.extend_from_slice(&(1 + leaf_1.len() as u32).to_be_bytes());
+        forged.push(EMPTY_STRING_CODE);
+        forged.extend_from_slice(leaf_1);
+        forged.extend_from_slice(&0u32.to_be_bytes());
+        let rest = CompactProof {
+            leaf_path: real.leaf_path.clone(),
+            steps: real.steps[1..].to_vec(),
+        }
+        .encode();
+        let leaf_length = 2 + real.leaf_path.len().div_ceil(2);
+        forged.extend_from_slice(&rest[leaf_length..]);
+
+        assert_eq!(
+            trie_root_from_indexed_proof(&mut Keccak256::default(), &forged, 1, &items[2].0),
+            Err(ProofError::InvalidInlineChild {
+                position: sibling_position
+            })
+        );
+    }
+
+    fn multiproof_root(items: &[Blob], indices: &[u32]) -> Result<B256, ProofError> {
+        let mut sorted = indices.to_vec();
+        sort_by_trie_key(&mut sorted);
+        let receipts: Vec<&[u8]> = sorted.iter().map(|&i| &items[i as usize].0[..]).collect();
+        let proof = get_multiproof_for_receipts(items, indices);
+        receipt_trie_root_from_multiproof(&mut Keccak256::default(), &proof, &sorted, &receipts)
+    }
+
+    #[test]
+    fn multiproofs_round_trip() {
+        for items in [
+            blobs((0..200).map(|i| 40 + i % 50)),
+            blobs((0..140).map(|i| (i * 7) % 45 + 1)),
+        ] {
+            let expected_root =
+                ordered_trie_root_with_encoder(&items, |item, out| item.encode_2718(out));
+            for indices in [
+                &[0][..],
+                &[3, 4],
+                &[0, 1, 127, 128, 129],
+                &[199 % items.len() as u32, 5, 17, 64],
+                &(0..items.len() as u32).collect::<Vec<_>>(),
+            ] {
+                assert_eq!(multiproof_root(&items, indices), Ok(expected_root));
+            }
+        }
+    }
+
+    #[test]
+    fn multiproof_shares_nodes() {
+        let items = blobs((0..200).map(|i| 40 + i % 50));
+        let indices = [1, 2, 3, 130, 131];
+        let single_proofs: usize = indices
+            .iter()
+            .map(|&i| get_proof_for_receipt(&items, i).len())
+            .sum();
+        let multiproof = get_multiproof_for_receipts(&items, &indices);
+        assert!(multiproof.len() < single_proofs);
+    }
+
+    #[test]
+    fn malformed_multiproofs_error() {
+        let items = blobs((0..40).map(|i| 40 + i % 50));
+        let indices = [2, 30];
+        let receipts = [&items[2].0[..], &items[30].0[..]];
+        let proof = get_multiproof_for_receipts(&items, &indices);
+        let mut keccak = Keccak256::default();
+
+        assert_eq!(
+            receipt_trie_root_from_multiproof(&mut keccak, &proof, &indices[..1], &receipts[..1]),
+            Err(ProofError::ReceiptCountMismatch {
+                proven: 2,
+                provided: 1
+            })
+        );
+        let extra = [receipts[0], receipts[1], receipts[1]];
+        assert_eq!(
+            receipt_trie_root_from_multiproof(&mut keccak, &proof, &[2, 30, 30], &extra),
+            Err(ProofError::ReceiptCountMismatch {
+                proven: 2,
+                provided: 3
+            })
+        );
+        assert_eq!(
+            receipt_trie_root_from_multiproof(&mut keccak, &proof, &indices[..1], &receipts),
+            Err(ProofError::IndexCountMismatch {
+                indices: 1,
+                receipts: 2
+            })
+        );
+
+        let mut unsupported = proof.clone();
+        unsupported[0] = MULTIPROOF_VERSION + 1;
+        assert_eq!(
+            receipt_trie_root_from_multiproof(&mut keccak, &unsupported, &indices, &receipts),
+            Err(ProofError::UnsupportedVersion {
+                version: MULTIPROOF_VERSION + 1
+            })
+        );
+
+        // Dropping the root branch, which has hashed siblings at nibbles 2 and 8, leaves two
+        // unconnected subtrees.
+        let last_branch = proof.len() - 5 - 2 * 33;
+        assert_eq!(proof[last_branch], MULTIPROOF_BRANCH);
+        assert!(matches!(
+            receipt_trie_root_from_multiproof(
+                &mut keccak,
+                &proof[..last_branch],
+                &indices,
+                &receipts
+            ),
+            Err(ProofError::UnconnectedNodes { .. })
+        ));
+
+        let mut invalid_type = proof.clone();
+        invalid_type[1] = 0x07;
+        assert_eq!(
+            receipt_trie_root_from_multiproof(&mut keccak, &invalid_type, &indices, &receipts),
+            Err(ProofError::InvalidNodeType {
+                position: 1,
+                node_type: 0x07
+            })
+        );
+
+        let valid_root =
+            receipt_trie_root_from_multiproof(&mut keccak, &proof, &indices, &receipts);
+        assert!(valid_root.is_ok());
+        let swapped = [receipts[1], receipts[0]];
+        assert_ne!(
+            receipt_trie_root_from_multiproof(&mut keccak, &proof, &indices, &swapped),
+            valid_root
+        );
+    }
+
+    #[test]
+    fn multiproofs_check_indices() {
+        let items = blobs((0..200).map(|i| 40 + i % 50));
+        let mut indices = vec![1, 2, 3, 130, 131];
+        sort_by_trie_key(&mut indices);
+        let receipts: Vec<&[u8]> = indices.iter().map(|&i| &items[i as usize].0[..]).collect();
+        let proof = get_multiproof_for_receipts(&items, &indices);
+        let mut keccak = Keccak256::default();
+        let root = ordered_trie_root_with_encoder(&items, |item, out| item.encode_2718(out));
+        assert_eq!(
+            receipt_trie_root_from_multiproof(&mut keccak, &proof, &indices, &receipts),
+            Ok(root)
+        );
+
+        // The root is right, but not every receipt is claimed at its own index.
+        let mut swapped = indices.clone();
+        swapped.swap(1, 3);
+        assert!(matches!(
+            receipt_trie_root_from_multiproof(&mut keccak, &proof, &swapped, &receipts),
+            Err(ProofError::KeyMismatch { .. })
+        ));
+        for wrong in [0, 4, 129, 132, 1000] {
+            let mut wrong_indices = indices.clone();
+            wrong_indices[2] = wrong;
+            assert!(matches!(
+                receipt_trie_root_from_multiproof(&mut keccak, &proof, &wrong_indices, &receipts),
+                Err(ProofError::KeyMismatch { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn spliced_multiproof_siblings_error() {
+        // Items 1 and 2 are inline leaves at nibbles 1 and 2 of a branch whose nibble 0 is empty.
+        let items = blobs([1, 2, 3]);
+        let real = get_multiproof_for_receipts(&items, &[2]);
+        let branch = 3;
+        assert_eq!(real[branch], MULTIPROOF_BRANCH);
+        assert_eq!(real[branch + 1..branch + 5], [0, 1 << 2, 0, 1 << 1]);
+        let leaf_1 = &real[branch + 6..branch + 6 + usize::from(real[branch + 5])];
+
+        // Same splice as for single proofs: the empty child and leaf 1 move into a raw sibling at
+        // nibble 0 and item 2 is proven at nibble 1, the key of index 1, followed by an empty
+        // sibling at nibble 2.
+        let mut forged = real[..branch].to_vec();
+        forged.push(MULTIPROOF_BRANCH);
+        forged.extend_from_slice(&(1u16 << 1).to_be_bytes());
+        forged.extend_from_slice(&0b101u16.to_be_bytes());
+        let sibling_position = forged.len();
+        forged.push(1 + leaf_1.len() as u8);
+        forged.push(EMPTY_STRING_CODE);
+        forged.extend_from_slice(leaf_1);
+        forged.push(0);
+        forged.extend_from_slice(&real[branch + 6 + leaf_1.len()..]);
+
+        assert_eq!(
+            receipt_trie_root_from_multiproof(
+                &mut Keccak256::default(),
+                &forged,
+                &[1],
+                &[&items[2].0]
+            ),
+            Err(ProofError::InvalidInlineChild {
+                position: sibling_position
+            })
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! Verification of account and storage proofs as returned by `eth_getProof`. Such a proof lists the
+//! RLP encoded trie nodes from the root towards the key, which in the state and storage tries is the
+//! keccak hash of the address or storage slot.
+
+use crate::receipt_trie::{
+    branch_child, rlp_list_items, LEAF_PATH_FLAG, NIBBLE_MASK, ODD_NIBBLES_FLAG,
+};
+use crate::Keccak256;
+use alloy_primitives::{Address, Bytes, B256, U256};
+use alloy_rlp::Header;
+use alloy_trie::{TrieAccount, EMPTY_ROOT_HASH};
+use serde::{Deserialize, Serialize};
+
+/// Proof of an account and some of its storage slots against a state root.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StateProof {
+    pub account_proof: Vec<Bytes>,
+    pub storage_proofs: Vec<StorageProof>,
+}
+
+/// Proof of a storage slot against the storage root of the account proven alongside it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StorageProof {
+    pub slot: B256,
+    pub proof: Vec<Bytes>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateProofError {
+    /// Node `node` doesn't hash to the root or to the reference its parent holds.
+    HashMismatch {
+        node: usize,
+        expected: B256,
+        computed: B256,
+    },
+    MalformedNode {
+        node: usize,
+        error: alloy_rlp::Error,
+    },
+    /// Proof ends before reaching either the value or a node showing the key absent.
+    Incomplete { nodes: usize },
+    /// Key was already resolved by the nodes before node `node`.
+    UnusedNodes { node: usize },
+    /// Value stored at the key is not a valid account or storage value.
+    MalformedValue(alloy_rlp::Error),
+}
+
+impl std::fmt::Display for StateProofError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HashMismatch {
+                node,
+                expected,
+                computed,
+            } => write!(
+                f,
+                "node #{} hashes to {} instead of {}",
+                node, computed, expected
+            ),
+            Self::MalformedNode { node, error } => {
+                write!(f, "node #{} is malformed: {}", node, error)
+            }
+            Self::Incomplete { nodes } => {
+                write!(f, "proof of {} nodes ends before resolving the key", nodes)
+            }
+            Self::UnusedNodes { node } => {
+                write!(f, "key resolved before node #{}, proof is too long", node)
+            }
+            Self::MalformedValue(error) => write!(f, "malformed value: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for StateProofError {}
+
+/// Nibbles of a key not yet matched against the nodes of a proof.
+struct KeyNibbles<'k> {
+    key: &'k [u8],
+    position: usize,
+}
+
+impl KeyNibbles<'_> {
+    fn is_empty(&self) -> bool {
+        self.position == 2 * self.key.len()
+    }
+}
+
+impl Iterator for KeyNibbles<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.key.get(self.position / 2)?;
+        let nibble = if self.position & 1 == 0 {
+            byte >> 4
+        } else {
+            byte & NIBBLE_MASK
+        };
+        self.position += 1;
+        Some(nibble)
+    }
+}
+
+/// Where following the key through a single node leads.
+enum Step<'p> {
+    /// Hash of the next node or the child itself if it's inlined.
+    Child(&'p [u8]),
+    Value(&'p [u8]),
+    Absent,
+}
+
+fn follow_key<'p>(node: &'p [u8], key: &mut KeyNibbles) -> alloy_rlp::Result<Step<'p>> {
+    let items = rlp_list_items(node)?;
+    match items[..] {
+        [mut path, mut child] => {
+            let path = Header::decode_bytes(&mut path, false)?;
+            let (&flags, packed) = path.split_first().ok_or(alloy_rlp::Error::InputTooShort)?;
+            if flags & !(LEAF_PATH_FLAG | ODD_NIBBLES_FLAG | NIBBLE_MASK) != 0
+                || (flags & ODD_NIBBLES_FLAG == 0 && flags & NIBBLE_MASK != 0)
+            {
+                return Err(alloy_rlp::Error::Custom("invalid path flags"));
+            }
+            let odd_nibble = (flags & ODD_NIBBLES_FLAG != 0).then_some(flags & NIBBLE_MASK);
+            let mut path_nibbles = odd_nibble.into_iter().chain(
+                packed
+                    .iter()
+                    .flat_map(|byte| [byte >> 4, byte & NIBBLE_MASK]),
+            );
+            if !path_nibbles.all(|nibble| key.next() == Some(nibble)) {
+                return Ok(Step::Absent);
+            }
+
+            if flags & LEAF_PATH_FLAG == 0 {
+                Ok(Step::Child(branch_child(child)))
+            } else if key.is_empty() {
+                Ok(Step::Value(Header::decode_bytes(&mut child, false)?))
+            } else {
+                Ok(Step::Absent)
+            }
+        }
+        _ if items.len() == 17 => match key.next() {
+            Some(nibble) => match branch_child(items[nibble as usize]) {
+                [] => Ok(Step::Absent),
+                child => Ok(Step::Child(child)),
+            },
+            None => {
+                let mut value = items[16];
+                match Header::decode_bytes(&mut value, false)? {
+                    [] => Ok(Step::Absent),
+                    value => Ok(Step::Value(value)),
+                }
+            }
+        },
+        _ => Err(alloy_rlp::Error::Custom("unexpected number of node items")),
+    }
+}
+
+/// Verifies `proof` of `key` against `root`, returning the value stored at the key or `None` if the
+/// proof shows that the key is absent. Nodes short enough to be inlined into their parent may also
+/// be repeated as nodes of their own, as some clients do.
+pub fn verify_proof<'p, N: AsRef<[u8]>>(
+    keccak: &mut Keccak256,
+    root: &B256,
+    key: &[u8],
+    proof: &'p [N],
+) -> Result<Option<&'p [u8]>, StateProofError> {
+    if proof.is_empty() && *root == EMPTY_ROOT_HASH {
+        return Ok(None);
+    }
+
+    let mut key = KeyNibbles { key, position: 0 };
+    let mut expected = *root;
+    let mut hash_out = [0u8; 32];
+    let mut index = 0;
+    'nodes: while let Some(node) = proof.get(index) {
+        let mut node = node.as_ref();
+        keccak.complete(node, &mut hash_out);
+        if hash_out != expected {
+            return Err(StateProofError::HashMismatch {
+                node: index,
+                expected,
+                computed: B256::from(hash_out),
+            });
+        }
+
+        // Follow the key through the node and any children inlined into it.
+        let value = loop {
+            let step = follow_key(node, &mut key)
+                .map_err(|error| StateProofError::MalformedNode { node: index, error })?;
+            match step {
+                Step::Value(value) => break Some(value),
+                Step::Absent => break None,
+                Step::Child(hash) if hash.len() == 32 => {
+                    expected = B256::from_slice(hash);
+                    index += 1;
+                    continue 'nodes;
+                }
+                Step::Child(inline) => {
+                    if proof
+                        .get(index + 1)
+                        .is_some_and(|next| next.as_ref() == inline)
+                    {
+                        index += 1;
+                    }
+                    node = inline;
+                }
+            }
+        };
+
+        if index + 1 != proof.len() {
+            return Err(StateProofError::UnusedNodes { node: index + 1 });
+        }
+        return Ok(value);
+    }
+
+    Err(StateProofError::Incomplete { nodes: proof.len() })
+}
+
+/// Verifies the proof of the account at `address` against `state_root`, returning `None` if the
+/// account doesn't exist.
+pub fn verify_account_proof<N: AsRef<[u8]>>(
+    keccak: &mut Keccak256,
+    state_root: &B256,
+    address: &Address,
+    proof: &[N],
+) -> Result<Option<TrieAccount>, StateProofError> {
+    let mut key = [0u8; 32];
+    keccak.complete(address.as_slice(), &mut key);
+    verify_proof(keccak, state_root, &key, proof)?
+        .map(alloy_rlp::decode_exact)
+        .transpose()
+        .map_err(StateProofError::MalformedValue)
+}
+
+/// Verifies the proof of storage `slot` against an account's `storage_root`, returning its value.
+/// Slots absent from the trie hold zero.
+pub fn verify_storage_proof<N: AsRef<[u8]>>(
+    keccak: &mut Keccak256,
+    storage_root: &B256,
+    slot: &B256,
+    proof: &[N],
+) -> Result<U256, StateProofError> {
+    let mut key = [0u8; 32];
+    keccak.complete(slot.as_slice(), &mut key);
+    verify_proof(keccak, storage_root, &key, proof)?
+        .map_or(Ok(U256::ZERO), alloy_rlp::decode_exact)
+        .map_err(StateProofError::MalformedValue)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::receipt_trie::get_trie_proof_nodes;
+    use crate::receipt_trie::tests::blobs;
+    use alloy_primitives::keccak256;
+    use alloy_trie::{proof::ProofRetainer, root::ordered_trie_root_with_encoder};
+    use alloy_trie::{HashBuilder, Nibbles};
+    use std::collections::BTreeMap;
+
+    /// Builds the secure trie of `leaves` keyed by the hash of their key, returning its root and
+    /// the proofs of `targets` as `eth_getProof` would.
+    pub(crate) fn secure_trie_proofs(
+        leaves: impl IntoIterator<Item = (impl AsRef<[u8]>, Vec<u8>)>,
+        targets: &[&[u8]],
+    ) -> (B256, Vec<Vec<Bytes>>) {
+        let leaves: BTreeMap<_, _> = leaves
+            .into_iter()
+            .map(|(key, value)| (Nibbles::unpack(keccak256(key)), value))
+            .collect();
+        let targets: Vec<_> = targets
+            .iter()
+            .map(|target| Nibbles::unpack(keccak256(target)))
+            .collect();
+
+        let mut hb =
+            HashBuilder::default().with_proof_retainer(ProofRetainer::new(targets.clone()));
+        for (key, value) in &leaves {
+            hb.add_leaf(key.clone(), value);
+        }
+        let root = hb.root();
+        let nodes = hb.take_proof_nodes();
+        let proofs = targets
+            .iter()
+            .map(|target| {
+                nodes
+                    .matching_nodes_sorted(target)
+                    .into_iter()
+                    .map(|(_, node)| node)
+                    .collect()
+            })
+            .collect();
+        (root, proofs)
+    }
+
+    fn slot(i: u8) -> B256 {
+        B256::with_last_byte(i)
+    }
+
+    fn storage_leaves() -> impl Iterator<Item = (B256, Vec<u8>)> {
+        (0..40).map(|i| (slot(i), alloy_rlp::encode(U256::from(1000 * i as u64 + 1))))
+    }
+
+    #[test]
+    fn proves_accounts_and_storage() {
+        let mut keccak = Keccak256::default();
+
+        let (storage_root, proofs) = secure_trie_proofs(
+            storage_leaves(),
+            &[slot(7).as_slice(), slot(200).as_slice()],
+        );
+        assert_eq!(
+            verify_storage_proof(&mut keccak, &storage_root, &slot(7), &proofs[0]),
+            Ok(U256::from(7001))
+        );
+        assert_eq!(
+            verify_storage_proof(&mut keccak, &storage_root, &slot(200), &proofs[1]),
+            Ok(U256::ZERO)
+        );
+        let no_nodes: [Bytes; 0] = [];
+        assert_eq!(
+            verify_storage_proof(&mut keccak, &EMPTY_ROOT_HASH, &slot(7), &no_nodes),
+            Ok(U256::ZERO)
+        );
+
+        let account = |i: u8| TrieAccount {
+            nonce: i.into(),
+            balance: U256::from(i),
+            storage_root: if i == 3 {
+                storage_root
+            } else {
+                EMPTY_ROOT_HASH
+            },
+            ..Default::default()
+        };
+        let address = Address::with_last_byte;
+        let (state_root, proofs) = secure_trie_proofs(
+            (0..30).map(|i| (address(i), alloy_rlp::encode(account(i)))),
+            &[address(3).as_slice(), address(100).as_slice()],
+        );
+        assert_eq!(
+            verify_account_proof(&mut keccak, &state_root, &address(3), &proofs[0]),
+            Ok(Some(account(3)))
+        );
+        assert_eq!(
+            verify_account_proof(&mut keccak, &state_root, &address(100), &proofs[1]),
+            Ok(None)
+        );
+        // Proof of another key.
+        assert!(verify_account_proof(&mut keccak, &state_root, &address(4), &proofs[0]).is_err());
+    }
+
+    #[test]
+    fn follows_inline_nodes() {
+        let items = blobs((0..40).map(|i| 1 + i % 3));
+        let root =
+            ordered_trie_root_with_encoder(&items, |item, out| out.extend_from_slice(&item.0));
+        let mut keccak = Keccak256::default();
+        let mut saw_inline_nodes = false;
+
+        for (index, item) in (0u32..).zip(&items) {
+            let key = alloy_rlp::encode(index);
+            let nodes: Vec<Bytes> = get_trie_proof_nodes(&items, index)
+                .matching_nodes_sorted(&Nibbles::unpack(&key))
+                .into_iter()
+                .map(|(_, node)| node)
+                .collect();
+            assert_eq!(
+                verify_proof(&mut keccak, &root, &key, &nodes),
+                Ok(Some(item.0.as_slice())),
+                "item #{}",
+                index
+            );
+
+            // Clients may leave out nodes inlined into their parents.
+            let hashed_nodes: Vec<_> = nodes
+                .iter()
+                .enumerate()
+                .filter(|(i, node)| *i == 0 || node.len() >= 32)
+                .map(|(_, node)| node)
+                .collect();
+            saw_inline_nodes |= hashed_nodes.len() < nodes.len();
+            assert_eq!(
+                verify_proof(&mut keccak, &root, &key, &hashed_nodes),
+                Ok(Some(item.0.as_slice())),
+                "item #{} without inlined nodes",
+                index
+            );
+        }
+        assert!(saw_inline_nodes);
+    }
+
+    #[test]
+    fn rejects_invalid_proofs() {
+        let mut keccak = Keccak256::default();
+        let (root, proofs) = secure_trie_proofs(storage_leaves(), &[slot(7).as_slice()]);
+        let proof = &proofs[0];
+        let last = proof.len() - 1;
+
+        let mut tampered = proof.clone();
+        let mut leaf = tampered[last].to_vec();
+        *leaf.last_mut().unwrap() ^= 1;
+        tampered[last] = leaf.into();
+        assert!(matches!(
+            verify_storage_proof(&mut keccak, &root, &slot(7), &tampered),
+            Err(StateProofError::HashMismatch { node, .. }) if node == last
+        ));
+        assert!(matches!(
+            verify_storage_proof(&mut keccak, &B256::ZERO, &slot(7), proof),
+            Err(StateProofError::HashMismatch { node: 0, .. })
+        ));
+        assert_eq!(
+            verify_storage_proof(&mut keccak, &root, &slot(7), &proof[..last]),
+            Err(StateProofError::Incomplete { nodes: last })
+        );
+
+        let mut extended = proof.clone();
+        extended.push(proof[last].clone());
+        assert_eq!(
+            verify_storage_proof(&mut keccak, &root, &slot(7), &extended),
+            Err(StateProofError::UnusedNodes { node: last + 1 })
+        );
+
+        let not_a_node = Bytes::from(alloy_rlp::encode(B256::ZERO));
+        assert!(matches!(
+            verify_storage_proof(
+                &mut keccak,
+                &keccak256(&not_a_node),
+                &slot(7),
+                &[not_a_node]
+            ),
+            Err(StateProofError::MalformedNode { node: 0, .. })
+        ));
+
+        // Storage values must be RLP encoded integers.
+        let (root, proofs) = secure_trie_proofs([(slot(7), vec![0xc0])], &[slot(7).as_slice()]);
+        assert!(matches!(
+            verify_storage_proof(&mut keccak, &root, &slot(7), &proofs[0]),
+            Err(StateProofError::MalformedValue(_))
+        ));
+    }
+}
@@ -1,5 +1,9 @@
 use crate::fee_summary::FeeEntry;
-use alloy_consensus::{proofs::calculate_receipt_root, Header, ReceiptEnvelope};
+use crate::payload::RewardEvent;
+use alloy_consensus::{
+    proofs::{calculate_receipt_root, calculate_transaction_root},
+    Header, ReceiptEnvelope, TxEip4844Variant, TxEnvelope,
+};
 use alloy_primitives::{keccak256, Address, Log, B256};
 use rand::{
     distr::{Bernoulli, Distribution},
@@ -10,6 +14,7 @@ use std::collections::BTreeMap;
 #[derive(Debug, Clone)]
 pub struct LogInjector {
     angstrom: Address,
+    reward_event: RewardEvent,
     possible_assets: Vec<Address>,
     rng: rand::rngs::ThreadRng,
     solo: Bernoulli,
@@ -17,11 +22,17 @@ pub struct LogInjector {
 }
 
 impl LogInjector {
-    pub fn new(angstrom: Address, mut possible_assets: Vec<Address>, solo_log_prob: f32) -> Self {
+    pub fn new(
+        angstrom: Address,
+        reward_event: RewardEvent,
+        mut possible_assets: Vec<Address>,
+        solo_log_prob: f32,
+    ) -> Self {
         possible_assets.sort();
 
         Self {
             angstrom,
+            reward_event,
             possible_assets,
             rng: rand::rng(),
             solo: Bernoulli::new(solo_log_prob.into()).expect("Failed to initialize bernouli"),
@@ -43,17 +54,26 @@ impl LogInjector {
         self.hash_to_entry_oracle
             .insert(hash, entries.into_boxed_slice());
 
-        let data = hash.into();
+        let mut data = vec![0u8; self.reward_event.data_len as usize];
+        data[self.reward_event.hash_offset as usize..][..32].copy_from_slice(hash.as_slice());
 
-        Log::new(self.angstrom, vec![], data).unwrap()
+        Log::new(
+            self.angstrom,
+            vec![self.reward_event.signature],
+            data.into(),
+        )
+        .unwrap()
     }
 
+    /// Injects a reward log into a random receipt, turning its transaction into a call to Angstrom.
     pub fn inject_random_summaries(
         &mut self,
         header: &mut Header,
+        transactions: &mut [TxEnvelope],
         receipts: &mut Vec<ReceiptEnvelope>,
     ) {
         let i = self.rng.random_range(0..receipts.len());
+        set_call_target(&mut transactions[i], self.angstrom);
         match &mut receipts[i] {
             ReceiptEnvelope::Legacy(r)
             | ReceiptEnvelope::Eip2930(r)
@@ -70,6 +90,7 @@ impl LogInjector {
                 }
             }
         }
+        header.transactions_root = calculate_transaction_root(transactions);
         header.receipts_root = calculate_receipt_root(receipts.as_slice());
     }
 
@@ -77,3 +98,17 @@ impl LogInjector {
         self.hash_to_entry_oracle
     }
 }
+
+/// Turns `tx` into a call to `to`, leaving its now invalid signature as is.
+fn set_call_target(tx: &mut TxEnvelope, to: Address) {
+    match tx {
+        TxEnvelope::Legacy(tx) => tx.tx_mut().to = to.into(),
+        TxEnvelope::Eip2930(tx) => tx.tx_mut().to = to.into(),
+        TxEnvelope::Eip1559(tx) => tx.tx_mut().to = to.into(),
+        TxEnvelope::Eip4844(tx) => match tx.tx_mut() {
+            TxEip4844Variant::TxEip4844(tx) => tx.to = to,
+            TxEip4844Variant::TxEip4844WithSidecar(tx) => tx.tx.to = to,
+        },
+        TxEnvelope::Eip7702(tx) => tx.tx_mut().to = to,
+    }
+}
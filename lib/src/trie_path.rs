@@ -1,4 +1,5 @@
 use crate::receipt_trie::*;
+use alloy_trie::Nibbles;
 
 #[derive(Debug, Clone)]
 pub(crate) struct TriePath<'a>(&'a [u8]);
@@ -44,6 +45,17 @@ impl<'a> TriePath<'a> {
         (self.len() as u8 - 1) + self.is_odd() as u8
     }
 
+    pub(crate) fn to_nibbles(&self) -> Nibbles {
+        let mut nibbles = Vec::with_capacity(self.nibbles() as usize);
+        if self.is_odd() {
+            nibbles.push(self.0[0] & NIBBLE_MASK);
+        }
+        for byte in &self[1..] {
+            nibbles.extend_from_slice(&[byte >> 4, byte & NIBBLE_MASK]);
+        }
+        Nibbles::from_vec_unchecked(nibbles)
+    }
+
     pub(crate) fn write_bytes(&self, buf: &mut Vec<u8>) {
         if self.is_odd() {
             buf.push(self.0[0] & NIBBLE_MASK);
@@ -0,0 +1,1288 @@
+use crate::cycles::{span, Phase};
+use crate::fee_summary::{FeeSummaryInspector, FEE_ENTRY_SIZE};
+use crate::header_lens::{EncodedHeaderLens, HeaderLensError};
+use crate::lazy_header::{
+    NumberInspector, ParentHashInspector, ReceiptsRootInspector, StateRootInspector, Timestamp,
+    TimestampInspector,
+};
+use crate::payload::{Payload, RewardBlock};
+use crate::receipt_trie::{trie_root_from_indexed_proof, ProofError};
+use crate::state_proof::{verify_account_proof, verify_storage_proof, StateProof, StateProofError};
+use crate::{Keccak256, Reader};
+use alloy_consensus::Transaction;
+use alloy_eips::eip2718::Encodable2718;
+use alloy_primitives::{Address, B256, U256};
+use std::collections::HashMap;
+
+/// Result of successfully validating a [`Payload`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SantaOutput {
+    /// Parent hash of the first header, the block the proven chain builds on.
+    pub chain_parent: B256,
+    /// Hash of the last header in the payload, the hash of block `last_block_number`.
+    pub chain_last: B256,
+    /// Number of the first header in the payload, the block following `chain_parent`.
+    pub first_block_number: u64,
+    /// Number of the last header in the payload.
+    pub last_block_number: u64,
+    /// Timestamps of the first and last header.
+    pub first_block_timestamp: u64,
+    pub last_block_timestamp: u64,
+    /// Number of reward blocks whose fees were aggregated.
+    pub reward_block_count: u32,
+    /// Total fees per asset across all reward blocks.
+    pub sums: HashMap<Address, U256>,
+    /// Hashes of the transactions that emitted the reward logs, in the order of the reward blocks.
+    pub reward_transactions: Vec<B256>,
+    /// Values of Angstrom's proven storage slots at the last block, in the order of the proofs.
+    pub angstrom_storage: Vec<(B256, U256)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    NoHeaders,
+    MalformedHeader {
+        header_index: u32,
+        error: HeaderLensError,
+    },
+    BrokenParentLink {
+        header_index: u32,
+        expected: B256,
+        found: B256,
+    },
+    LogIndexOutOfBounds {
+        reward_block: usize,
+        log_index: u32,
+        logs: usize,
+    },
+    WrongLogAddress {
+        reward_block: usize,
+        expected: Address,
+        found: Address,
+    },
+    /// Reward logs within a block must be ordered by receipt and log index without duplicates.
+    UnorderedRewardLogs {
+        reward_block: usize,
+    },
+    /// The receipt holding the reward log is of a reverted transaction.
+    FailedReceipt {
+        reward_block: usize,
+    },
+    /// The reward log's first topic is not the configured event signature.
+    WrongEventSignature {
+        reward_block: usize,
+        expected: B256,
+        found: Option<B256>,
+    },
+    /// The reward log's data doesn't match the configured event's data layout.
+    InvalidLogData {
+        reward_block: usize,
+        length: usize,
+    },
+    FeeEntriesOutOfBounds {
+        reward_block: usize,
+        fee_entry_offset: usize,
+        fee_entries: u32,
+        available: usize,
+    },
+    RewardHashMismatch {
+        reward_block: usize,
+        computed: B256,
+        logged: B256,
+    },
+    MalformedProof {
+        reward_block: usize,
+        error: ProofError,
+    },
+    ReceiptsRootMismatch {
+        header_index: u32,
+        reward_block: usize,
+        computed: B256,
+        expected: B256,
+    },
+    /// The transaction that emitted the reward log isn't a call to Angstrom.
+    WrongTransactionTarget {
+        reward_block: usize,
+        expected: Address,
+        found: Option<Address>,
+    },
+    MalformedTransactionProof {
+        reward_block: usize,
+        error: ProofError,
+    },
+    TransactionsRootMismatch {
+        header_index: u32,
+        reward_block: usize,
+        computed: B256,
+        expected: B256,
+    },
+    /// Angstrom's account proof doesn't verify against the state root of the last header.
+    InvalidAccountProof {
+        error: StateProofError,
+    },
+    /// Angstrom's account doesn't exist at the last header.
+    MissingAngstromAccount,
+    InvalidStorageProof {
+        slot: B256,
+        error: StateProofError,
+    },
+    /// Block numbers of the first and last header don't match the number of headers.
+    InconsistentBlockNumbers {
+        first: u64,
+        last: u64,
+        headers: u32,
+    },
+    /// Reward blocks that were never matched to a header, either because their `block_index` lies
+    /// beyond the last header or because they're not sorted by `block_index`.
+    UnusedRewardBlocks {
+        first_unused: usize,
+    },
+    /// More reward blocks than fit the `u32` count of the output.
+    TooManyRewardBlocks {
+        reward_blocks: usize,
+    },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoHeaders => write!(f, "payload contains no headers"),
+            Self::MalformedHeader {
+                header_index,
+                error,
+            } => write!(f, "header #{}: malformed encoding: {}", header_index, error),
+            Self::BrokenParentLink {
+                header_index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "header #{}: broken parent-child-link, expected parent {} found {}",
+                header_index, expected, found
+            ),
+            Self::LogIndexOutOfBounds {
+                reward_block,
+                log_index,
+                logs,
+            } => write!(
+                f,
+                "reward block #{}: log index {} out of bounds for receipt with {} logs",
+                reward_block, log_index, logs
+            ),
+            Self::WrongLogAddress {
+                reward_block,
+                expected,
+                found,
+            } => write!(
+                f,
+                "reward block #{}: log emitted by {} instead of {}",
+                reward_block, found, expected
+            ),
+            Self::UnorderedRewardLogs { reward_block } => write!(
+                f,
+                "reward block #{}: not ordered after previous reward log of its block",
+                reward_block
+            ),
+            Self::FailedReceipt { reward_block } => write!(
+                f,
+                "reward block #{}: receipt of reverted transaction",
+                reward_block
+            ),
+            Self::WrongEventSignature {
+                reward_block,
+                expected,
+                found,
+            } => write!(
+                f,
+                "reward block #{}: log has topic0 {:?} instead of {}",
+                reward_block, found, expected
+            ),
+            Self::InvalidLogData {
+                reward_block,
+                length,
+            } => write!(
+                f,
+                "reward block #{}: log data of length {} does not match reward event layout",
+                reward_block, length
+            ),
+            Self::FeeEntriesOutOfBounds {
+                reward_block,
+                fee_entry_offset,
+                fee_entries,
+                available,
+            } => write!(
+                f,
+                "reward block #{}: {} fee entries from offset {} out of bounds, payload holds {}",
+                reward_block, fee_entries, fee_entry_offset, available
+            ),
+            Self::RewardHashMismatch {
+                reward_block,
+                computed,
+                logged,
+            } => write!(
+                f,
+                "reward block #{}: fee summary hash {} does not match logged {}",
+                reward_block, computed, logged
+            ),
+            Self::MalformedProof {
+                reward_block,
+                error,
+            } => write!(
+                f,
+                "reward block #{}: malformed receipt proof: {}",
+                reward_block, error
+            ),
+            Self::ReceiptsRootMismatch {
+                header_index,
+                reward_block,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "header #{} (reward block #{}): receipts root from proof {} does not match {}",
+                header_index, reward_block, computed, expected
+            ),
+            Self::WrongTransactionTarget {
+                reward_block,
+                expected,
+                found,
+            } => write!(
+                f,
+                "reward block #{}: transaction calls {:?} instead of {}",
+                reward_block, found, expected
+            ),
+            Self::MalformedTransactionProof {
+                reward_block,
+                error,
+            } => write!(
+                f,
+                "reward block #{}: malformed transaction proof: {}",
+                reward_block, error
+            ),
+            Self::TransactionsRootMismatch {
+                header_index,
+                reward_block,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "header #{} (reward block #{}): transactions root from proof {} does not match {}",
+                header_index, reward_block, computed, expected
+            ),
+            Self::InvalidAccountProof { error } => {
+                write!(f, "invalid angstrom account proof: {}", error)
+            }
+            Self::MissingAngstromAccount => {
+                write!(f, "angstrom account doesn't exist at the last header")
+            }
+            Self::InvalidStorageProof { slot, error } => {
+                write!(
+                    f,
+                    "invalid proof of angstrom storage slot {}: {}",
+                    slot, error
+                )
+            }
+            Self::InconsistentBlockNumbers {
+                first,
+                last,
+                headers,
+            } => write!(
+                f,
+                "block numbers {}..={} inconsistent with {} headers",
+                first, last, headers
+            ),
+            Self::UnusedRewardBlocks { first_unused } => write!(
+                f,
+                "reward blocks from #{} onwards were not matched to any header",
+                first_unused
+            ),
+            Self::TooManyRewardBlocks { reward_blocks } => write!(
+                f,
+                "{} reward blocks exceed the maximum of {}",
+                reward_blocks,
+                u32::MAX
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+pub struct RewardAggregator<'p> {
+    sums: HashMap<Address, U256>,
+    fee_entry_offset: usize,
+    block_index: u32,
+    reward_blocks: std::iter::Peekable<std::iter::Enumerate<std::slice::Iter<'p, RewardBlock>>>,
+    payload: &'p Payload,
+    encoded_item_buf: Vec<u8>,
+    reward_transactions: Vec<B256>,
+}
+
+impl<'p> RewardAggregator<'p> {
+    pub fn new(payload: &'p Payload) -> Self {
+        Self {
+            sums: HashMap::with_capacity(32),
+            fee_entry_offset: 0,
+            block_index: 0,
+            reward_blocks: payload.reward_blocks.iter().enumerate().peekable(),
+            payload,
+            encoded_item_buf: Vec::with_capacity(512),
+            reward_transactions: Vec::with_capacity(payload.reward_blocks.len()),
+        }
+    }
+
+    pub fn validate_and_agg_next_block(
+        &mut self,
+        header: &impl ReceiptsRootInspector,
+        hash_out: &mut [u8; 32],
+        keccak: &mut Keccak256,
+    ) -> Result<(), ValidationError> {
+        let block_index = self.block_index;
+        self.block_index += 1;
+
+        // Position of the last reward log in the block as `(tx_index, log_index)`. The receipt
+        // proof binds `tx_index` to the receipt, requiring strictly increasing positions ensures no
+        // log is counted twice.
+        let mut last_position = None;
+
+        while let Some((reward_block, rb)) = self
+            .reward_blocks
+            .next_if(|(_, rb)| rb.block_index == block_index)
+        {
+            let position = (rb.tx_index, rb.log_index);
+            if last_position.is_some_and(|last| last >= position) {
+                return Err(ValidationError::UnorderedRewardLogs { reward_block });
+            }
+            last_position = Some(position);
+
+            self.validate_and_agg_reward_block(
+                header,
+                block_index,
+                reward_block,
+                rb,
+                hash_out,
+                keccak,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_and_agg_reward_block(
+        &mut self,
+        header: &impl ReceiptsRootInspector,
+        block_index: u32,
+        reward_block: usize,
+        rb: &RewardBlock,
+        hash_out: &mut [u8; 32],
+        keccak: &mut Keccak256,
+    ) -> Result<(), ValidationError> {
+        if !rb.receipt.is_success() {
+            return Err(ValidationError::FailedReceipt { reward_block });
+        }
+
+        let logs = rb.receipt.logs();
+        let log = logs
+            .get(rb.log_index as usize)
+            .ok_or(ValidationError::LogIndexOutOfBounds {
+                reward_block,
+                log_index: rb.log_index,
+                logs: logs.len(),
+            })?;
+        if log.address != self.payload.angstrom {
+            return Err(ValidationError::WrongLogAddress {
+                reward_block,
+                expected: self.payload.angstrom,
+                found: log.address,
+            });
+        }
+        let reward_event = &self.payload.reward_event;
+        let found_signature = log.topics().first().copied();
+        if found_signature != Some(reward_event.signature) {
+            return Err(ValidationError::WrongEventSignature {
+                reward_block,
+                expected: reward_event.signature,
+                found: found_signature,
+            });
+        }
+        let to = rb.transaction.to();
+        if to != Some(self.payload.angstrom) {
+            return Err(ValidationError::WrongTransactionTarget {
+                reward_block,
+                expected: self.payload.angstrom,
+                found: to,
+            });
+        }
+        let logged_hash = reward_event
+            .reward_hash(log)
+            .ok_or(ValidationError::InvalidLogData {
+                reward_block,
+                length: log.data.data.len(),
+            })?;
+
+        let fee_entry_offset = self.fee_entry_offset;
+        let out_of_bounds = ValidationError::FeeEntriesOutOfBounds {
+            reward_block,
+            fee_entry_offset,
+            fee_entries: rb.fee_entries,
+            available: self.payload.fee_entries.len() / FEE_ENTRY_SIZE,
+        };
+
+        // The counts are untrusted and `usize` is 32 bits wide in the guest.
+        let fee_entry_end = usize::try_from(rb.fee_entries)
+            .ok()
+            .and_then(|fee_entries| fee_entry_offset.checked_add(fee_entries))
+            .ok_or(out_of_bounds.clone())?;
+        let fee_summaries = fee_entry_offset
+            .checked_mul(FEE_ENTRY_SIZE)
+            .zip(fee_entry_end.checked_mul(FEE_ENTRY_SIZE))
+            .and_then(|(start, end)| self.payload.fee_entries.get(start..end))
+            .and_then(|entries| FeeSummaryInspector::try_from(entries).ok())
+            .ok_or(out_of_bounds)?;
+        self.fee_entry_offset = fee_entry_end;
+        {
+            let _span = span(Phase::FeeSummaryHashing);
+            keccak.update(fee_summaries);
+            keccak.finalize_and_reset(hash_out);
+        }
+        if hash_out != logged_hash {
+            return Err(ValidationError::RewardHashMismatch {
+                reward_block,
+                computed: B256::from(*hash_out),
+                logged: B256::from(logged_hash),
+            });
+        }
+
+        // Proving the receipt and the transaction at the same index binds them to each other.
+        {
+            let _span = span(Phase::ReceiptEncoding);
+            self.encoded_item_buf.clear();
+            rb.receipt.encode_2718(&mut self.encoded_item_buf);
+        }
+
+        let span_receipt_proof = span(Phase::ReceiptProof);
+        let computed_receipt_root =
+            trie_root_from_indexed_proof(keccak, &rb.proof, rb.tx_index, &self.encoded_item_buf)
+                .map_err(|error| ValidationError::MalformedProof {
+                    reward_block,
+                    error,
+                })?;
+        drop(span_receipt_proof);
+        if &computed_receipt_root != header.receipts_root() {
+            return Err(ValidationError::ReceiptsRootMismatch {
+                header_index: block_index,
+                reward_block,
+                computed: computed_receipt_root,
+                expected: *header.receipts_root(),
+            });
+        }
+
+        {
+            let _span = span(Phase::TransactionEncoding);
+            self.encoded_item_buf.clear();
+            rb.transaction.encode_2718(&mut self.encoded_item_buf);
+        }
+
+        let span_transaction_proof = span(Phase::TransactionProof);
+        let computed_transactions_root = trie_root_from_indexed_proof(
+            keccak,
+            &rb.transaction_proof,
+            rb.tx_index,
+            &self.encoded_item_buf,
+        )
+        .map_err(|error| ValidationError::MalformedTransactionProof {
+            reward_block,
+            error,
+        })?;
+        if *header.transactions_root() != computed_transactions_root {
+            return Err(ValidationError::TransactionsRootMismatch {
+                header_index: block_index,
+                reward_block,
+                computed: computed_transactions_root,
+                expected: *header.transactions_root(),
+            });
+        }
+        keccak.update(&self.encoded_item_buf);
+        keccak.finalize_and_reset(hash_out);
+        self.reward_transactions.push(B256::from(*hash_out));
+        drop(span_transaction_proof);
+
+        let _span = span(Phase::FeeAggregation);
+        for i in 0..fee_entry_end - fee_entry_offset {
+            let entry = fee_summaries[i];
+            let amount = entry.amount();
+            if amount > 0 {
+                *self.sums.entry(*entry.asset()).or_default() += U256::from(amount);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the fee sums and the hashes of the reward transactions.
+    pub fn finish(mut self) -> Result<(HashMap<Address, U256>, Vec<B256>), ValidationError> {
+        if let Some((first_unused, _)) = self.reward_blocks.next() {
+            return Err(ValidationError::UnusedRewardBlocks { first_unused });
+        }
+        Ok((self.sums, self.reward_transactions))
+    }
+}
+
+fn read_header<'b>(
+    headers: &mut Reader<'b>,
+    header_index: u32,
+) -> Result<EncodedHeaderLens<'b>, ValidationError> {
+    EncodedHeaderLens::read_from(headers).map_err(|error| ValidationError::MalformedHeader {
+        header_index,
+        error,
+    })
+}
+
+/// Verifies the proofs of Angstrom's account and storage slots against `state_root`, returning the
+/// values of the slots.
+fn verify_angstrom_state(
+    keccak: &mut Keccak256,
+    state_root: &B256,
+    angstrom: &Address,
+    state: &StateProof,
+) -> Result<Vec<(B256, U256)>, ValidationError> {
+    let account = verify_account_proof(keccak, state_root, angstrom, &state.account_proof)
+        .map_err(|error| ValidationError::InvalidAccountProof { error })?
+        .ok_or(ValidationError::MissingAngstromAccount)?;
+
+    state
+        .storage_proofs
+        .iter()
+        .map(|storage| {
+            verify_storage_proof(keccak, &account.storage_root, &storage.slot, &storage.proof)
+                .map(|value| (storage.slot, value))
+                .map_err(|error| ValidationError::InvalidStorageProof {
+                    slot: storage.slot,
+                    error,
+                })
+        })
+        .collect()
+}
+
+/// Validates the header chain and all reward blocks of `payload`, aggregating the fee entries of
+/// every reward block into per-asset sums.
+pub fn validate_payload(payload: &Payload) -> Result<SantaOutput, ValidationError> {
+    let mut keccak = Keccak256::default();
+
+    let mut headers = Reader::from(payload.headers.as_slice());
+    let mut reward_agg = RewardAggregator::new(payload);
+
+    if headers.is_empty() {
+        return Err(ValidationError::NoHeaders);
+    }
+
+    // Read first header, store parent as start of chain and compute hash.
+    let first_header = read_header(&mut headers, 0)?;
+    let chain_parent = *first_header.parent_hash();
+    let mut last_hash = {
+        let mut hash_out = [0u8; 32];
+
+        reward_agg.validate_and_agg_next_block(&first_header, &mut hash_out, &mut keccak)?;
+
+        let _span = span(Phase::HeaderHashing);
+        keccak.update(&first_header);
+        keccak.finalize_and_reset(&mut hash_out);
+        hash_out
+    };
+
+    let mut last_header = first_header.clone();
+    let mut header_index = 1;
+    while !headers.is_empty() {
+        let header = read_header(&mut headers, header_index)?;
+        if *header.parent_hash() != last_hash {
+            return Err(ValidationError::BrokenParentLink {
+                header_index,
+                expected: B256::from(last_hash),
+                found: *header.parent_hash(),
+            });
+        }
+
+        // Can use `last_hash` as hash out because it's value was already used and is going to be
+        // overwritten.
+        reward_agg.validate_and_agg_next_block(&header, &mut last_hash, &mut keccak)?;
+
+        let span_header_hashing = span(Phase::HeaderHashing);
+        keccak.update(&header);
+        keccak.finalize_and_reset(&mut last_hash);
+        drop(span_header_hashing);
+        last_header = header;
+        header_index += 1;
+    }
+
+    let malformed = |header_index| {
+        move |error| ValidationError::MalformedHeader {
+            header_index,
+            error,
+        }
+    };
+    // Only the first and last header need to be validated beyond the `receipts_root`.
+    let first_header = first_header
+        .validate_through::<Timestamp>()
+        .map_err(malformed(0))?;
+    let last_header = last_header
+        .validate_through::<Timestamp>()
+        .map_err(malformed(header_index - 1))?;
+    let first_block_number = first_header.number();
+    let first_block_timestamp = first_header.timestamp();
+    let last_block_number = last_header.number();
+    let last_block_timestamp = last_header.timestamp();
+    if first_block_number.checked_add(u64::from(header_index - 1)) != Some(last_block_number) {
+        return Err(ValidationError::InconsistentBlockNumbers {
+            first: first_block_number,
+            last: last_block_number,
+            headers: header_index,
+        });
+    }
+
+    let (sums, reward_transactions) = reward_agg.finish()?;
+    let span_state_proof = span(Phase::StateProof);
+    let angstrom_storage = match &payload.angstrom_state {
+        Some(state) => verify_angstrom_state(
+            &mut keccak,
+            last_header.state_root(),
+            &payload.angstrom,
+            state,
+        )?,
+        None => Vec::new(),
+    };
+    drop(span_state_proof);
+    Ok(SantaOutput {
+        chain_parent,
+        chain_last: B256::from(last_hash),
+        first_block_number,
+        last_block_number,
+        first_block_timestamp,
+        last_block_timestamp,
+        reward_block_count: payload.reward_blocks.len().try_into().map_err(|_| {
+            ValidationError::TooManyRewardBlocks {
+                reward_blocks: payload.reward_blocks.len(),
+            }
+        })?,
+        sums,
+        reward_transactions,
+        angstrom_storage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fee_summary::FeeEntry;
+    use crate::payload::{build_payload, PayloadError, RewardEvent};
+    use crate::state_proof::tests::secure_trie_proofs;
+    use crate::state_proof::StorageProof;
+    use crate::ReaderError;
+    use alloy_consensus::{
+        proofs::{calculate_receipt_root, calculate_transaction_root},
+        Header, Receipt, ReceiptEnvelope, SignableTransaction, TxEip1559, TxEnvelope,
+    };
+    use alloy_primitives::{address, keccak256, Log, PrimitiveSignature as Signature};
+    use alloy_trie::TrieAccount;
+    use std::collections::BTreeMap;
+
+    const ANGSTROM: Address = address!("0x3FcA107f4F20c8E240078BFAA5A3bEF952111e4e");
+    const ASSET_A: Address = address!("0x4a00E1790CD32D4B20b4231b556e04E4f5C3F4BF");
+    const ASSET_B: Address = address!("0xc62cAe6ed0b08e88863E4b3b3e5625C02Cbe5Af6");
+    const REWARD_EVENT: RewardEvent = RewardEvent {
+        signature: B256::repeat_byte(0xee),
+        hash_offset: 32,
+        data_len: 64,
+    };
+
+    fn receipt(cumulative_gas_used: u64, logs: Vec<Log>) -> ReceiptEnvelope {
+        ReceiptEnvelope::Eip1559(
+            Receipt {
+                status: true.into(),
+                cumulative_gas_used,
+                logs,
+            }
+            .with_bloom(),
+        )
+    }
+
+    fn transaction(nonce: u64, to: Address) -> TxEnvelope {
+        let tx = TxEip1559 {
+            nonce,
+            to: to.into(),
+            ..Default::default()
+        };
+        TxEnvelope::Eip1559(tx.into_signed(Signature::test_signature()))
+    }
+
+    /// Builds a valid payload of 4 chained headers where blocks 1 and 3 hold a reward log.
+    fn valid_payload() -> Payload {
+        let mut oracle = BTreeMap::new();
+        let mut blocks = Vec::new();
+        let mut parent_hash = B256::repeat_byte(0xaa);
+
+        for block_index in 0..4u64 {
+            let mut header = Header {
+                parent_hash,
+                number: 100 + block_index,
+                timestamp: 1_700_000_000 + 12 * block_index,
+                ..Default::default()
+            };
+            let receipts = (block_index % 2 == 1).then(|| {
+                let entries = vec![
+                    FeeEntry::new(ASSET_A, 1000 * block_index as u128),
+                    FeeEntry::new(ASSET_B, 7),
+                ];
+                let hash = keccak256(entries.concat());
+                oracle.insert(hash, entries);
+
+                let mut data = [0u8; 64];
+                data[32..].copy_from_slice(hash.as_slice());
+                // Other Angstrom event laid out like the reward event.
+                let decoy = Log::new(ANGSTROM, vec![B256::repeat_byte(0xdd)], data.into()).unwrap();
+                let reward_log =
+                    Log::new(ANGSTROM, vec![REWARD_EVENT.signature], data.into()).unwrap();
+
+                let transactions = vec![
+                    transaction(0, ASSET_A),
+                    transaction(1, ANGSTROM),
+                    transaction(2, ASSET_B),
+                ];
+                let receipts = vec![
+                    receipt(21_000, vec![]),
+                    receipt(90_000, vec![decoy, reward_log]),
+                    receipt(120_000, vec![]),
+                ];
+                header.transactions_root = calculate_transaction_root(&transactions);
+                header.receipts_root = calculate_receipt_root(&receipts);
+                (transactions, receipts)
+            });
+            parent_hash = header.hash_slow();
+            blocks.push((header, receipts));
+        }
+
+        build_payload(blocks, ANGSTROM, REWARD_EVENT, &oracle).unwrap()
+    }
+
+    /// Builds a payload of a single block with reward logs in two receipts, one of which holds two
+    /// of them.
+    fn multi_log_payload() -> Payload {
+        let mut oracle = BTreeMap::new();
+        let mut reward_log = |amount: u128| {
+            let entries = vec![FeeEntry::new(ASSET_A, amount)];
+            let hash = keccak256(entries.concat());
+            oracle.insert(hash, entries);
+
+            let mut data = [0u8; 64];
+            data[32..].copy_from_slice(hash.as_slice());
+            Log::new(ANGSTROM, vec![REWARD_EVENT.signature], data.into()).unwrap()
+        };
+        let decoy = Log::new(ANGSTROM, vec![B256::repeat_byte(0xdd)], Default::default()).unwrap();
+
+        let transactions = vec![
+            transaction(0, ANGSTROM),
+            transaction(1, ASSET_A),
+            transaction(2, ANGSTROM),
+        ];
+        let receipts = vec![
+            receipt(21_000, vec![reward_log(1)]),
+            receipt(60_000, vec![]),
+            receipt(90_000, vec![reward_log(20), decoy, reward_log(300)]),
+        ];
+        let header = Header {
+            transactions_root: calculate_transaction_root(&transactions),
+            receipts_root: calculate_receipt_root(&receipts),
+            ..Default::default()
+        };
+
+        build_payload(
+            vec![(header, Some((transactions, receipts)))],
+            ANGSTROM,
+            REWARD_EVENT,
+            &oracle,
+        )
+        .unwrap()
+    }
+
+    /// Builds a payload of a single block without reward logs, proving two storage slots of the
+    /// `angstrom` account against its state root. Only [`ANGSTROM`] and [`ASSET_A`] have accounts.
+    fn angstrom_state_payload(angstrom: Address) -> Payload {
+        let slots = [B256::with_last_byte(1), B256::with_last_byte(9)];
+        let (storage_root, storage_proofs) = secure_trie_proofs(
+            [
+                (slots[0], alloy_rlp::encode(U256::from(1234))),
+                (B256::with_last_byte(2), alloy_rlp::encode(U256::from(1))),
+            ],
+            &[slots[0].as_slice(), slots[1].as_slice()],
+        );
+        let account = TrieAccount {
+            storage_root,
+            ..Default::default()
+        };
+        let (state_root, account_proofs) = secure_trie_proofs(
+            [
+                (ANGSTROM, alloy_rlp::encode(account)),
+                (ASSET_A, alloy_rlp::encode(TrieAccount::default())),
+            ],
+            &[angstrom.as_slice()],
+        );
+
+        let header = Header {
+            state_root,
+            ..Default::default()
+        };
+        let mut payload = build_payload(
+            vec![(header, None)],
+            angstrom,
+            REWARD_EVENT,
+            &BTreeMap::<_, Vec<FeeEntry>>::new(),
+        )
+        .unwrap();
+        payload.angstrom_state = Some(StateProof {
+            account_proof: account_proofs[0].clone(),
+            storage_proofs: slots
+                .into_iter()
+                .zip(storage_proofs)
+                .map(|(slot, proof)| StorageProof { slot, proof })
+                .collect(),
+        });
+        payload
+    }
+
+    #[test]
+    fn aggregates_multiple_reward_logs_per_block() {
+        let payload = multi_log_payload();
+        let positions: Vec<_> = payload
+            .reward_blocks
+            .iter()
+            .map(|rb| (rb.block_index, rb.tx_index, rb.log_index))
+            .collect();
+        assert_eq!(positions, [(0, 0, 0), (0, 2, 0), (0, 2, 2)]);
+
+        let output = validate_payload(&payload).unwrap();
+        assert_eq!(output.reward_block_count, 3);
+        assert_eq!(output.sums[&ASSET_A], U256::from(321));
+    }
+
+    #[test]
+    fn detects_duplicate_reward_log() {
+        let mut payload = multi_log_payload();
+        let duplicate = payload.reward_blocks[2].clone();
+        payload.reward_blocks.push(duplicate);
+        payload
+            .fee_entries
+            .extend_from_within(FEE_ENTRY_SIZE * 2..FEE_ENTRY_SIZE * 3);
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::UnorderedRewardLogs { reward_block: 3 })
+        );
+    }
+
+    #[test]
+    fn detects_reward_logs_out_of_receipt_order() {
+        let mut payload = multi_log_payload();
+        payload.reward_blocks.swap(0, 1);
+        let (first, rest) = payload.fee_entries.split_at_mut(FEE_ENTRY_SIZE);
+        first.swap_with_slice(&mut rest[..FEE_ENTRY_SIZE]);
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::UnorderedRewardLogs { reward_block: 1 })
+        );
+    }
+
+    #[test]
+    fn valid_payload_aggregates_fees() {
+        let payload = valid_payload();
+        let output = validate_payload(&payload).unwrap();
+
+        assert_eq!(output.chain_parent, B256::repeat_byte(0xaa));
+        assert_eq!(output.first_block_number, 100);
+        assert_eq!(output.last_block_number, 103);
+        assert_eq!(output.first_block_timestamp, 1_700_000_000);
+        assert_eq!(output.last_block_timestamp, 1_700_000_036);
+        assert_eq!(output.reward_block_count, 2);
+        assert_eq!(output.sums.len(), 2);
+        assert_eq!(output.sums[&ASSET_A], U256::from(4000));
+        assert_eq!(output.sums[&ASSET_B], U256::from(14));
+        assert_eq!(
+            output.reward_transactions,
+            [
+                keccak256(transaction(1, ANGSTROM).encoded_2718()),
+                keccak256(transaction(1, ANGSTROM).encoded_2718())
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_other_events() {
+        let payload = valid_payload();
+        assert!(payload.reward_blocks.iter().all(|rb| rb.log_index == 1));
+
+        let mut payload = payload;
+        payload.reward_blocks[0].log_index = 0;
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::WrongEventSignature {
+                reward_block: 0,
+                expected: REWARD_EVENT.signature,
+                found: Some(B256::repeat_byte(0xdd))
+            })
+        );
+    }
+
+    #[test]
+    fn detects_data_layout_mismatch() {
+        let mut payload = valid_payload();
+        payload.reward_event.data_len = 32;
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::InvalidLogData {
+                reward_block: 0,
+                length: 64
+            })
+        );
+    }
+
+    #[test]
+    fn detects_failed_receipt() {
+        let mut payload = valid_payload();
+        if let ReceiptEnvelope::Eip1559(r) = &mut payload.reward_blocks[1].receipt {
+            r.receipt.status = false.into();
+        }
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::FailedReceipt { reward_block: 1 })
+        );
+    }
+
+    #[test]
+    fn detects_broken_parent_link() {
+        let mut payload = valid_payload();
+        // Corrupt the last byte of the second header's parent hash. The first header's encoding is
+        // the same length as all others since they only differ in fixed size fields.
+        let header_len = payload.headers.len() / 4;
+        payload.headers[header_len + 4 + 31] ^= 1;
+
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::BrokenParentLink {
+                header_index: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn detects_wrong_log_address() {
+        let mut payload = valid_payload();
+        payload.angstrom = ASSET_A;
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::WrongLogAddress {
+                reward_block: 0,
+                expected: ASSET_A,
+                found: ANGSTROM
+            })
+        );
+    }
+
+    #[test]
+    fn detects_reward_hash_mismatch() {
+        let mut payload = valid_payload();
+        let last = payload.fee_entries.len() - 1;
+        payload.fee_entries[last] ^= 1;
+
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::RewardHashMismatch {
+                reward_block: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn detects_receipts_root_mismatch() {
+        let mut payload = valid_payload();
+        let proof = &mut payload.reward_blocks[0].proof;
+        let last = proof.len() - 1;
+        proof[last] ^= 1;
+
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::ReceiptsRootMismatch {
+                header_index: 1,
+                reward_block: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn detects_truncated_proof() {
+        let mut payload = valid_payload();
+        payload.reward_blocks[1].proof.pop();
+
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::MalformedProof {
+                reward_block: 1,
+                error: ProofError::Truncated(ReaderError::UnexpectedEnd { .. })
+            })
+        ));
+    }
+
+    #[test]
+    fn detects_truncated_header() {
+        let mut payload = valid_payload();
+        payload.headers.pop();
+
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::MalformedHeader {
+                header_index: 3,
+                error: HeaderLensError::Truncated(_)
+            })
+        ));
+    }
+
+    #[test]
+    fn detects_missing_fee_entries() {
+        let mut payload = valid_payload();
+        payload.fee_entries.truncate(FEE_ENTRY_SIZE * 3);
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::FeeEntriesOutOfBounds {
+                reward_block: 1,
+                fee_entry_offset: 2,
+                fee_entries: 2,
+                available: 3
+            })
+        );
+    }
+
+    #[test]
+    fn detects_overflowing_fee_entry_counts() {
+        let mut payload = valid_payload();
+        payload.reward_blocks[1].fee_entries = u32::MAX;
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::FeeEntriesOutOfBounds {
+                reward_block: 1,
+                fee_entry_offset: 2,
+                fee_entries: u32::MAX,
+                available: 4
+            })
+        );
+    }
+
+    #[test]
+    fn detects_unused_reward_blocks() {
+        let mut payload = valid_payload();
+        payload.reward_blocks[1].block_index = 4;
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::UnusedRewardBlocks { first_unused: 1 })
+        );
+    }
+
+    #[test]
+    fn detects_wrong_transaction_target() {
+        let mut payload = valid_payload();
+        payload.reward_blocks[1].transaction = transaction(1, ASSET_A);
+
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::WrongTransactionTarget {
+                reward_block: 1,
+                expected: ANGSTROM,
+                found: Some(ASSET_A)
+            })
+        );
+    }
+
+    #[test]
+    fn detects_transactions_root_mismatch() {
+        let mut payload = valid_payload();
+        let proof = &mut payload.reward_blocks[0].transaction_proof;
+        let last = proof.len() - 1;
+        proof[last] ^= 1;
+
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::TransactionsRootMismatch {
+                header_index: 1,
+                reward_block: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn verifies_angstrom_state() {
+        let output = validate_payload(&angstrom_state_payload(ANGSTROM)).unwrap();
+        assert_eq!(
+            output.angstrom_storage,
+            [
+                (B256::with_last_byte(1), U256::from(1234)),
+                (B256::with_last_byte(9), U256::ZERO)
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_invalid_state_proofs() {
+        let mut payload = angstrom_state_payload(ANGSTROM);
+        let state = payload.angstrom_state.as_mut().unwrap();
+        let last = state.storage_proofs[0].proof.len() - 1;
+        state.storage_proofs[0].proof.truncate(last);
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::InvalidStorageProof {
+                slot: B256::with_last_byte(1),
+                error: StateProofError::Incomplete { nodes: last }
+            })
+        );
+
+        let payload = angstrom_state_payload(ASSET_B);
+        assert_eq!(
+            validate_payload(&payload),
+            Err(ValidationError::MissingAngstromAccount)
+        );
+
+        let mut payload = angstrom_state_payload(ANGSTROM);
+        payload.angstrom_state.as_mut().unwrap().account_proof[0] = Default::default();
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::InvalidAccountProof {
+                error: StateProofError::HashMismatch { node: 0, .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn detects_transaction_of_other_receipt() {
+        let mut payload = multi_log_payload();
+        // Valid proof of a call to Angstrom, but not of the transaction the receipt belongs to.
+        let other = payload.reward_blocks[1].clone();
+        payload.reward_blocks[0].transaction = other.transaction;
+        payload.reward_blocks[0].transaction_proof = other.transaction_proof;
+
+        assert!(matches!(
+            validate_payload(&payload),
+            Err(ValidationError::MalformedTransactionProof {
+                reward_block: 0,
+                error: ProofError::KeyMismatch { .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn payload_requires_one_receipt_per_transaction() {
+        let body = (vec![transaction(0, ANGSTROM)], vec![]);
+        assert_eq!(
+            build_payload(
+                vec![(Header::default(), None), (Header::default(), Some(body))],
+                ANGSTROM,
+                REWARD_EVENT,
+                &BTreeMap::<_, Vec<FeeEntry>>::new(),
+            )
+            .unwrap_err(),
+            PayloadError::ReceiptCountMismatch {
+                block_index: 1,
+                transactions: 1,
+                receipts: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn payload_skips_reward_logs_of_indirect_calls() {
+        let mut oracle = BTreeMap::new();
+        let mut reward_log = |amount: u128| {
+            let entries = vec![FeeEntry::new(ASSET_A, amount)];
+            let hash = keccak256(entries.concat());
+            oracle.insert(hash, entries);
+
+            let mut data = [0u8; 64];
+            data[32..].copy_from_slice(hash.as_slice());
+            Log::new(ANGSTROM, vec![REWARD_EVENT.signature], data.into()).unwrap()
+        };
+
+        // The first transaction reaches Angstrom through a router, only the second calls it.
+        let router = Address::repeat_byte(0x77);
+        let transactions = vec![transaction(0, router), transaction(1, ANGSTROM)];
+        let receipts = vec![
+            receipt(50_000, vec![reward_log(1)]),
+            receipt(90_000, vec![reward_log(20)]),
+        ];
+        let header = Header {
+            transactions_root: calculate_transaction_root(&transactions),
+            receipts_root: calculate_receipt_root(&receipts),
+            ..Default::default()
+        };
+        let payload = build_payload(
+            vec![(
+                header.clone(),
+                Some((transactions.clone(), receipts.clone())),
+            )],
+            ANGSTROM,
+            REWARD_EVENT,
+            &oracle,
+        )
+        .unwrap();
+        assert_eq!(payload.reward_blocks.len(), 1);
+        assert_eq!(payload.reward_blocks[0].tx_index, 1);
+
+        let output = validate_payload(&payload).unwrap();
+        assert_eq!(output.sums[&ASSET_A], U256::from(20));
+
+        // A block whose only reward log is of an indirect call can't be proven.
+        assert_eq!(
+            build_payload(
+                vec![(
+                    header,
+                    Some((transactions[..1].to_vec(), receipts[..1].to_vec()))
+                )],
+                ANGSTROM,
+                REWARD_EVENT,
+                &oracle,
+            )
+            .unwrap_err(),
+            PayloadError::NoRewardLogs { block_index: 0 }
+        );
+    }
+
+    #[test]
+    fn payload_requires_fee_summaries() {
+        let reward_hash = B256::repeat_byte(0x42);
+        let mut data = [0u8; 64];
+        data[32..].copy_from_slice(reward_hash.as_slice());
+        let log = Log::new(ANGSTROM, vec![REWARD_EVENT.signature], data.into()).unwrap();
+        let body = (
+            vec![transaction(0, ANGSTROM)],
+            vec![receipt(21_000, vec![log])],
+        );
+
+        assert_eq!(
+            build_payload(
+                vec![(Header::default(), Some(body))],
+                ANGSTROM,
+                REWARD_EVENT,
+                &BTreeMap::<_, Vec<FeeEntry>>::new(),
+            )
+            .unwrap_err(),
+            PayloadError::MissingFeeSummary {
+                block_index: 0,
+                reward_hash,
+            }
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Differential tests of receipt proofs against the receipts root computed by alloy.
+
+use alloy_consensus::{
+    proofs::calculate_receipt_root, Eip658Value, Receipt, ReceiptEnvelope, ReceiptWithBloom,
+};
+use alloy_eips::Encodable2718;
+use alloy_primitives::{Address, Log, B256};
+use alloy_trie::Nibbles;
+use proptest::{collection::vec, prelude::*, sample::select};
+use santa_lib::compact_proof::CompactProof;
+use santa_lib::receipt_trie::{
+    get_multiproof_for_receipts, get_proof_for_receipt, get_trie_multiproof_nodes,
+    receipt_trie_root_from_multiproof, receipt_trie_root_from_proof, sort_by_trie_key,
+};
+use santa_lib::Keccak256;
+
+/// Receipt counts around the first branches of the trie and the reordering of indices by
+/// `adjust_index_for_rlp`, whose RLP encoding grows from one to two bytes at 128.
+const INTERESTING_SIZES: [usize; 7] = [1, 2, 16, 17, 127, 128, 129];
+
+/// Logs are derived from a few seeds rather than generated byte by byte, which would make
+/// thousands of receipts prohibitively slow to generate.
+fn arb_log() -> impl Strategy<Value = Log> {
+    (
+        any::<u64>(),
+        vec(any::<u64>(), 0..=4),
+        0usize..200,
+        any::<u8>(),
+    )
+        .prop_map(|(address, topics, data_length, data_seed)| {
+            let word = |seed: u64| B256::left_padding_from(&seed.to_be_bytes());
+            Log::new_unchecked(
+                Address::from_word(word(address)),
+                topics.into_iter().map(word).collect(),
+                (0..data_length)
+                    .map(|i| data_seed.wrapping_add(i as u8))
+                    .collect(),
+            )
+        })
+}
+
+fn arb_receipt() -> impl Strategy<Value = ReceiptEnvelope> {
+    (
+        0u8..5,
+        any::<Option<u64>>(),
+        any::<bool>(),
+        any::<u64>(),
+        vec(arb_log(), 0..4),
+    )
+        .prop_map(|(ty, post_state, success, cumulative_gas_used, logs)| {
+            // Only legacy receipts may predate EIP-658 and commit to the post state instead.
+            let status = match post_state {
+                Some(state) if ty == 0 => Eip658Value::PostState(B256::with_last_byte(state as u8)),
+                _ => success.into(),
+            };
+            let receipt: ReceiptWithBloom<_> = Receipt {
+                status,
+                cumulative_gas_used,
+                logs,
+            }
+            .with_bloom();
+            match ty {
+                0 => ReceiptEnvelope::Legacy(receipt),
+                1 => ReceiptEnvelope::Eip2930(receipt),
+                2 => ReceiptEnvelope::Eip1559(receipt),
+                3 => ReceiptEnvelope::Eip4844(receipt),
+                _ => ReceiptEnvelope::Eip7702(receipt),
+            }
+        })
+}
+
+/// Proves every receipt from a single trie retaining all nodes, as proving receipts one by one
+/// rebuilds the trie every time. Proofs at the boundaries are compared to the ones built by
+/// [`get_proof_for_receipt`] and proven together in a multiproof.
+fn assert_every_proof_round_trips(receipts: &[ReceiptEnvelope]) -> Result<(), TestCaseError> {
+    let root = calculate_receipt_root(receipts);
+    let indices: Vec<u32> = (0..receipts.len() as u32).collect();
+    let nodes = get_trie_multiproof_nodes(receipts, &indices);
+    let last = indices.len() as u32 - 1;
+    let boundaries = [0, 1, 15, 16, 127, 128, 129, last];
+
+    let mut keccak = Keccak256::default();
+    for (&index, receipt) in indices.iter().zip(receipts) {
+        let key = Nibbles::unpack(alloy_rlp::encode(index));
+        let proof = CompactProof::from_proof_nodes(&nodes, &key)
+            .unwrap()
+            .encode();
+        prop_assert_eq!(
+            receipt_trie_root_from_proof(&mut keccak, &proof, receipt.encoded_2718()),
+            Ok(root),
+            "receipt #{} of {}",
+            index,
+            receipts.len()
+        );
+
+        if boundaries.contains(&index) {
+            prop_assert_eq!(get_proof_for_receipt(receipts, index), proof);
+        }
+    }
+
+    let mut proven: Vec<u32> = boundaries.into_iter().filter(|&i| i <= last).collect();
+    proven.sort_unstable();
+    proven.dedup();
+    sort_by_trie_key(&mut proven);
+    let multiproof = get_multiproof_for_receipts(receipts, &proven);
+    let encoded: Vec<_> = proven
+        .iter()
+        .map(|&i| receipts[i as usize].encoded_2718())
+        .collect();
+    prop_assert_eq!(
+        receipt_trie_root_from_multiproof(&mut keccak, &multiproof, &proven, &encoded),
+        Ok(root),
+        "receipts {:?} of {}",
+        proven,
+        receipts.len()
+    );
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn proofs_match_alloy_root(
+        receipts in prop_oneof![select(&INTERESTING_SIZES[..]), 1usize..300]
+            .prop_flat_map(|count| vec(arb_receipt(), count))
+    ) {
+        assert_every_proof_round_trips(&receipts)?;
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(4))]
+
+    #[test]
+    fn thousands_of_proofs_match_alloy_root(receipts in vec(arb_receipt(), 1000..3000)) {
+        assert_every_proof_round_trips(&receipts)?;
+    }
+}
@@ -1,5 +1,19 @@
-use sp1_build::build_program_with_args;
+use sp1_build::{build_program_with_args, BuildArgs};
 
 fn main() {
-    build_program_with_args("../program", Default::default())
+    // Per-phase cycle tracking changes the program and with it the verifying key, so it's only
+    // built in when asked for.
+    println!("cargo:rerun-if-env-changed=SANTA_CYCLE_TRACKER");
+    let features = match std::env::var_os("SANTA_CYCLE_TRACKER") {
+        Some(_) => vec!["cycle-tracker".to_string()],
+        None => vec![],
+    };
+    build_program_with_args(
+        "../program",
+        BuildArgs {
+            features,
+            ..Default::default()
+        },
+    );
+    build_program_with_args("../aggregator", Default::default());
 }
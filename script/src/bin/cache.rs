@@ -0,0 +1,47 @@
+use clap::{Parser, Subcommand};
+use santa_lib::{cache::StoreKind, Cache};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about = "Checks the block cache for inconsistent entries", long_about = None)]
+struct Args {
+    #[clap(long, default_value = ".cache/store")]
+    path: std::path::PathBuf,
+
+    #[clap(long, default_value = "file", help = "cache backend: file or kv")]
+    store: StoreKind,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Reports cached headers that don't link up and receipts or transactions not matching their
+    /// header.
+    Verify,
+    /// Reports and evicts bad entries so that they're fetched again on the next run.
+    Repair,
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut cache = Cache::new(args.store.open(&args.path));
+
+    let issues = cache.verify();
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    println!("found {} issues", issues.len());
+
+    if let Command::Repair = args.command {
+        for issue in &issues {
+            cache.evict(issue);
+        }
+        cache.save();
+        println!("evicted affected entries");
+    }
+
+    if !issues.is_empty() && matches!(args.command, Command::Verify) {
+        std::process::exit(1);
+    }
+}
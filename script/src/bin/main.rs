@@ -1,14 +1,34 @@
-use alloy_primitives::{address, Address};
+use alloy_primitives::{address, Address, B256};
 use alloy_provider::{Provider, ProviderBuilder};
 
+use alloy_sol_types::{sol, SolEvent, SolType};
 use clap::Parser;
-use santa_lib::{payload::build_payload, testing::random::LogInjector, Cache, SmolBlock};
-use sp1_sdk::{include_elf, ProverClient, SP1Stdin};
+use futures::StreamExt;
+use santa_lib::{
+    aggregation::aggregate_proven_segments,
+    anchor::AnchorStatus,
+    cache::StoreKind,
+    cycles::CycleReport,
+    fee_summary::FEE_ENTRY_SIZE,
+    payload::{build_payload, Payload, PayloadError, RewardEvent},
+    public_values::{SantaAggregatePublicValues, SantaPublicValues},
+    state_proof::{StateProof, StorageProof},
+    testing::random::LogInjector,
+    verifier::validate_payload,
+    Cache,
+};
+use santa_script::fetcher::{self, FetchConfig, Fetcher};
+use sp1_sdk::{include_elf, EnvProver, HashableKey, ProverClient, SP1Proof, SP1Stdin};
 use std::collections::HashMap;
-use tracing::info;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::pin::pin;
+use std::time::Duration;
+use tracing::{info, warn};
 
 /// The ELF (executable and linkable format) file for the Succinct RISC-V zkVM.
 const SANTA_ELF: &[u8] = include_elf!("santa-program");
+const AGGREGATOR_ELF: &[u8] = include_elf!("santa-aggregator");
 
 const ANGSTROM: Address = address!("0x3FcA107f4F20c8E240078BFAA5A3bEF952111e4e");
 const ASSETS: &[Address] = &[
@@ -18,6 +38,17 @@ const ASSETS: &[Address] = &[
     address!("0x569DBE15E6dB8B4FDA83170f797ee54901C8B41f"),
 ];
 
+sol! {
+    /// Event Angstrom emits to commit to the fee summary of a block.
+    event RewardsSummary(bytes32 summaryHash);
+}
+
+const REWARD_EVENT: RewardEvent = RewardEvent {
+    signature: RewardsSummary::SIGNATURE_HASH,
+    hash_offset: 0,
+    data_len: 32,
+};
+
 /// The arguments for the command.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -48,6 +79,73 @@ struct Args {
 
     #[clap(long, default_value_t = 0.85)]
     solo_prob: f32,
+
+    #[clap(
+        long,
+        help = "blocks per segment when proving, segment proofs are then aggregated into one"
+    )]
+    segment_size: Option<NonZeroUsize>,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Angstrom storage slots to prove at the last block"
+    )]
+    angstrom_slots: Vec<B256>,
+
+    #[clap(
+        long,
+        help = "write the per-phase cycle report of --execute as JSON to this path"
+    )]
+    cycle_report: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        default_value_t = 64,
+        help = "depth below the chain head at which cached blocks are no longer checked for reorgs"
+    )]
+    confirmations: u64,
+
+    #[clap(
+        long,
+        default_value = "file",
+        help = "cache backend: file (segment files), kv (embedded database) or memory"
+    )]
+    store: StoreKind,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Era1 archive files to import into the cache before fetching"
+    )]
+    era1: Vec<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "trusted accumulator roots of the Era1 files, in the same order"
+    )]
+    era1_accumulators: Vec<B256>,
+
+    #[clap(
+        long,
+        default_value_t = 16,
+        help = "maximum number of RPC requests in flight"
+    )]
+    concurrency: usize,
+
+    #[clap(long, help = "maximum number of RPC requests started per second")]
+    requests_per_second: Option<u32>,
+
+    #[clap(
+        long,
+        default_value_t = 30,
+        help = "seconds after which an RPC request is retried"
+    )]
+    request_timeout: u64,
+
+    #[clap(long, default_value_t = 5, help = "retries of a failed RPC request")]
+    max_retries: u32,
 }
 
 #[tokio::main]
@@ -55,11 +153,11 @@ async fn main() -> eyre::Result<()> {
     // Setup the logger.
     sp1_sdk::utils::setup_logger();
 
-    let mut cache = Cache::new(".cache/store.json");
-
     // Parse the command line arguments.
     let args = Args::parse();
 
+    let mut cache = Cache::new(args.store.open(Path::new(".cache/store")));
+
     // Setup the provider.
     let provider: Box<dyn Provider> =
         if args.rpc_url.starts_with("http://") || args.rpc_url.starts_with("https://") {
@@ -78,45 +176,96 @@ async fn main() -> eyre::Result<()> {
             Box::new(ipc_provider)
         };
 
+    let fetcher = Fetcher::new(
+        &*provider,
+        FetchConfig {
+            concurrency: args.concurrency,
+            requests_per_second: args.requests_per_second,
+            timeout: Duration::from_secs(args.request_timeout),
+            max_retries: args.max_retries,
+            ..FetchConfig::default()
+        },
+    );
+
     let (start, end) = (args.start, args.end);
 
+    eyre::ensure!(
+        args.era1.len() == args.era1_accumulators.len(),
+        "Expected an accumulator root for each of the {} Era1 files, got {}",
+        args.era1.len(),
+        args.era1_accumulators.len()
+    );
+    for (path, accumulator) in args.era1.iter().zip(&args.era1_accumulators) {
+        let bytes = std::fs::read(path)?;
+        let blocks = cache
+            .import_era1(&bytes, *accumulator)
+            .map_err(|err| eyre::eyre!("Invalid Era1 file {:?}: {}", path, err))?;
+        match blocks {
+            Some(blocks) => info!(
+                "Imported blocks #{}-#{} from {:?}",
+                blocks.start(),
+                blocks.end(),
+                path
+            ),
+            None => warn!("No blocks in Era1 file {:?}", path),
+        }
+        cache.save();
+    }
+
     info!("Fetching blocks");
 
-    // Reduce list to blocks that are not already in the cache.
-    let block_nums_to_fetch = (start..end)
-        .filter_map(|bn| match cache.get_block(bn.into()) {
-            Some(_) => None,
-            None => Some(bn),
-        })
-        .collect::<Vec<_>>();
+    // Reduce list to blocks that are not already confirmed in the cache.
+    let block_nums_to_fetch = fetcher::missing_blocks(&cache, start..end);
+
+    // Blocks this far below the head are cached as confirmed and not checked for reorgs again.
+    // Ranges cached in full don't need the RPC, such as ones imported from Era1 files.
+    let head = if block_nums_to_fetch.is_empty() {
+        0
+    } else {
+        fetcher.head().await?
+    };
+    let is_deep = |bn: u64| bn + args.confirmations <= head;
 
-    // Fetch missing blocks.
-    for (i, blocks) in block_nums_to_fetch.chunks(args.chunk_size).enumerate() {
+    // Fetch missing blocks, saving them every chunk so that a failed run resumes from there.
+    let total = block_nums_to_fetch.len();
+    let mut offset = 0;
+    let mut chunks = pin!(fetcher.blocks(block_nums_to_fetch).chunks(args.chunk_size));
+    while let Some(new_blocks) = chunks.next().await {
+        let new_blocks = new_blocks.into_iter().collect::<Result<Vec<_>, _>>()?;
         info!(
-            "Fetching blocks {}-{} / {}",
-            i * args.chunk_size,
-            i * args.chunk_size + blocks.len(),
-            block_nums_to_fetch.len()
+            "Fetched blocks {}-{} / {}",
+            offset,
+            offset + new_blocks.len(),
+            total
         );
+        offset += new_blocks.len();
+
+        for block in new_blocks {
+            let bn = block.number;
+            // Walk back to the common ancestor if the cached predecessor was orphaned.
+            let mut chain = vec![block];
+            while cache.has_orphaned_parent(&chain[0]) {
+                let parent = chain[0].number - 1;
+                eyre::ensure!(
+                    !cache.is_confirmed(parent),
+                    "Reorg orphans confirmed block #{}, increase --confirmations",
+                    parent
+                );
+                chain.insert(0, fetcher.block(parent).await?);
+            }
 
-        let new_blocks = futures::future::try_join_all(
-            blocks
-                .into_iter()
-                .map(|&block| provider.get_block_by_number(block.into(), false.into())),
-        )
-        .await?;
-
-        cache.append_blocks(new_blocks.into_iter().zip(blocks).map(|(block, bn)| {
-            let block = block.unwrap_or_else(|| panic!("Block #{} was empty", bn));
-            let txs = block
-                .transactions
-                .as_hashes()
-                .map_or_else(Vec::new, Vec::from);
-            SmolBlock {
-                header: block.header.into(),
-                txs,
+            let orphaned = cache.append_chain(chain)?;
+            if !orphaned.is_empty() {
+                warn!(
+                    "Reorg at block #{}, rolled back {} orphaned blocks",
+                    orphaned[0],
+                    orphaned.len()
+                );
             }
-        }));
+            if is_deep(bn) {
+                cache.confirm(bn);
+            }
+        }
 
         cache.save();
     }
@@ -133,74 +282,170 @@ async fn main() -> eyre::Result<()> {
         .filter(|_| !skip_rng.sample(&mut rng))
         .collect();
 
-    // Get list of transactions and their block number for which we don't have their receipts.
-    let tx_hashes = summary_blocks
-        .iter()
-        .map(|&bn| {
-            let txs = cache.get_block(bn).unwrap().txs.clone();
-            let already_fetched = cache.receipts.get(&bn).map_or(0, Vec::len);
-            txs.into_iter()
-                .skip(already_fetched)
-                .map(move |hash| (bn, hash))
-        })
-        .flatten()
-        .collect::<Vec<_>>();
+    // Get the blocks for which we don't have all receipts.
+    let receipt_blocks = fetcher::missing_receipts(&cache, &summary_blocks);
 
-    // Fetch and save receipts.
+    // Fetch and save receipts, which are checked against their block's receipts root.
+    let total = receipt_blocks.len();
     let mut offset = 0;
-    for tx_hash_batch in tx_hashes.chunks(args.chunk_size) {
+    let mut chunks = pin!(fetcher.receipts(receipt_blocks).chunks(args.chunk_size));
+    while let Some(receipts) = chunks.next().await {
+        let receipts = receipts.into_iter().collect::<Result<Vec<_>, _>>()?;
         info!(
-            "Fetching receipts {}-{} / {}",
+            "Fetched receipts of blocks {}-{} / {}",
             offset,
-            offset + tx_hash_batch.len(),
-            tx_hashes.len()
+            offset + receipts.len(),
+            total
         );
-        offset += tx_hash_batch.len();
+        offset += receipts.len();
 
-        let receipts = futures::future::try_join_all(
-            tx_hash_batch
-                .iter()
-                .map(|(_, hash)| provider.get_transaction_receipt(*hash)),
-        )
-        .await?;
+        for (bn, receipts) in receipts {
+            // Replace receipts cached in part by earlier runs.
+            if cache.get_receipts(bn).is_some() {
+                cache.evict_receipts(bn);
+            }
+            cache.append_receipts(bn, receipts);
+        }
+        cache.save();
+    }
 
-        tx_hash_batch
-            .iter()
-            .zip(receipts)
-            .for_each(|((bn, _), receipt)| {
-                let receipt = receipt.unwrap().into_primitives_receipt();
-                cache.append_receipt(*bn, receipt.into_inner());
-            });
+    info!("Fetching transactions");
+
+    // Reward logs are bound to the transactions that emitted them, get the blocks for which we
+    // don't have all transactions.
+    let transaction_blocks = fetcher::missing_transactions(&cache, &summary_blocks);
+
+    // Fetch and save transactions, which are checked against their block's transactions root.
+    let total = transaction_blocks.len();
+    let mut offset = 0;
+    let mut chunks = pin!(fetcher
+        .transactions(transaction_blocks)
+        .chunks(args.chunk_size));
+    while let Some(transactions) = chunks.next().await {
+        let transactions = transactions.into_iter().collect::<Result<Vec<_>, _>>()?;
+        info!(
+            "Fetched transactions of blocks {}-{} / {}",
+            offset,
+            offset + transactions.len(),
+            total
+        );
+        offset += transactions.len();
+
+        for (bn, transactions) in transactions {
+            // Replace transactions cached in part by earlier runs.
+            if cache.get_transactions(bn).is_some() {
+                cache.evict_transactions(bn);
+            }
+            cache.append_transactions(bn, transactions);
+        }
         cache.save();
     }
 
     cache.save();
 
     // From this point on `synthetic_blocks` no longer represents real or even valid headers.
-    // Get all block headers and if we're going to inject a summary also get the receipts.
+    // Get all block headers and if we're going to inject a summary also get the transactions and
+    // receipts.
     let mut synthetic_blocks: Vec<_> = (start..end)
         .map(|bn| {
             let header = cache.get_block(bn).unwrap().header.clone();
-            let receipts = summary_blocks
-                .binary_search(&bn)
-                .ok()
-                .map(|_| cache.receipts.get(&bn).unwrap().clone());
-            (header, receipts)
+            let body = summary_blocks.binary_search(&bn).ok().map(|_| {
+                (
+                    cache.get_transactions(bn).unwrap().to_vec(),
+                    cache.get_receipts(bn).unwrap().to_vec(),
+                )
+            });
+            (header, body)
         })
         .collect();
 
     // Inject fake reward summary logs and re-compute header hash chain.
-    let mut log_injector = LogInjector::new(ANGSTROM, ASSETS.into(), args.solo_prob.into());
+    let mut log_injector =
+        LogInjector::new(ANGSTROM, REWARD_EVENT, ASSETS.into(), args.solo_prob.into());
     let mut parent_hash = synthetic_blocks[0].0.parent_hash;
-    for (header, receipts) in synthetic_blocks.iter_mut() {
+    for (header, body) in synthetic_blocks.iter_mut() {
         header.parent_hash = parent_hash;
-        if let Some(receipts) = receipts {
-            log_injector.inject_random_summaries(header, receipts);
+        if let Some((transactions, receipts)) = body {
+            log_injector.inject_random_summaries(header, transactions, receipts);
         }
         parent_hash = header.hash_slow();
     }
 
-    let payload = build_payload(synthetic_blocks, ANGSTROM, &log_injector.into_oracle());
+    // Synthetic headers keep their state root, so Angstrom's state can be proven as is.
+    let angstrom_state = if args.angstrom_slots.is_empty() {
+        None
+    } else {
+        info!(
+            "Fetching proof of {} Angstrom storage slots",
+            args.angstrom_slots.len()
+        );
+        let proof = provider
+            .get_proof(ANGSTROM, args.angstrom_slots.clone())
+            .block_id((end - 1).into())
+            .await?;
+        Some(StateProof {
+            account_proof: proof.account_proof,
+            storage_proofs: proof
+                .storage_proof
+                .into_iter()
+                .map(|storage| StorageProof {
+                    slot: storage.key.as_b256(),
+                    proof: storage.proof,
+                })
+                .collect(),
+        })
+    };
+
+    let oracle = log_injector.into_oracle();
+    let segment_payloads = args
+        .segment_size
+        .map(|segment_size| {
+            let mut segment_payloads = synthetic_blocks
+                .chunks(segment_size.get())
+                .map(|blocks| build_payload(blocks.to_vec(), ANGSTROM, REWARD_EVENT, &oracle))
+                .collect::<Result<Vec<_>, _>>()?;
+            // Aggregation takes the storage values of the last segment.
+            segment_payloads.last_mut().unwrap().angstrom_state = angstrom_state.clone();
+            Ok::<_, PayloadError>(segment_payloads)
+        })
+        .transpose()?;
+    let mut payload = build_payload(synthetic_blocks, ANGSTROM, REWARD_EVENT, &oracle)?;
+    payload.angstrom_state = angstrom_state;
+
+    // Reject invalid payloads natively before spending any time in the zkVM.
+    let output = validate_payload(&payload)?;
+    let reward_transactions = output.reward_transactions.len();
+    let expected = SantaPublicValues::new(ANGSTROM, output);
+    info!(
+        "Payload valid, blocks #{}-#{} ({} -> {}) with fees in {} assets from {} transactions",
+        expected.first_block_number,
+        expected.last_block_number,
+        expected.chain_parent,
+        expected.chain_last,
+        expected.fees.len(),
+        reward_transactions
+    );
+
+    // The hashes can only be checked by a contract while they're in reach of `BLOCKHASH` or the
+    // EIP-2935 history contract.
+    match provider.get_block_number().await {
+        Ok(head) => {
+            for anchor in [
+                AnchorStatus::new(expected.first_block_number.saturating_sub(1), head),
+                AnchorStatus::new(expected.last_block_number, head),
+            ] {
+                if anchor.anchorable() {
+                    info!("Anchor {} (head #{})", anchor, head);
+                } else {
+                    warn!(
+                        "Anchor {} (head #{}), cannot be verified on-chain",
+                        anchor, head
+                    );
+                }
+            }
+        }
+        Err(err) => warn!("Skipping anchor check, failed to get chain head: {}", err),
+    }
 
     if args.execute {
         let client = ProverClient::from_env();
@@ -212,36 +457,164 @@ async fn main() -> eyre::Result<()> {
         //     "alloy_primitives::keccak256(&as_bytes): {:?}",
         //     alloy_primitives::keccak256(&as_bytes)
         // );
-        println!("{:?}", &payload.reward_blocks[0].receipt);
-        stdin.write(&payload.reward_blocks[0].receipt);
-        // stdin.write(&payload);
+        stdin.write(&payload);
 
         let (output, report) = client.execute(SANTA_ELF, &stdin).run().unwrap();
         println!("Program executed successfully.");
 
-        // println!("start: {}", hex::encode(&output.as_slice()[0..32]));
-        // println!("end: {}", hex::encode(&output.as_slice()[32..64]));
+        let public_values = SantaPublicValues::abi_decode(output.as_slice(), true)?;
+        eyre::ensure!(
+            public_values == expected,
+            "Program output {:?} differs from native validation {:?}",
+            public_values,
+            expected
+        );
+        println!(
+            "Proven fees for {} reward blocks: {:?}",
+            public_values.reward_block_count, public_values.fees
+        );
+        println!(
+            "Proven Angstrom storage: {:?}",
+            public_values.angstrom_storage
+        );
 
         // Record the number of cycles executed.
         println!("Number of cycles: {}", report.total_instruction_count());
+
+        if report.cycle_tracker.is_empty() {
+            warn!("No cycles per phase, build with SANTA_CYCLE_TRACKER=1 to track them");
+        }
+        let cycle_report = CycleReport::new(
+            report
+                .cycle_tracker
+                .iter()
+                .map(|(phase, &cycles)| (phase, cycles)),
+            report.total_instruction_count(),
+            expected.last_block_number - expected.first_block_number + 1,
+            payload.reward_blocks.len() as u64,
+            (payload.fee_entries.len() / FEE_ENTRY_SIZE) as u64,
+        );
+        println!("{}", cycle_report);
+        if let Some(path) = &args.cycle_report {
+            std::fs::write(path, serde_json::to_string_pretty(&cycle_report)?)?;
+            info!("Wrote cycle report to {}", path.display());
+        }
+    }
+    if args.prove {
+        let client = ProverClient::from_env();
+
+        match segment_payloads {
+            Some(segment_payloads) => {
+                prove_segments_and_aggregate(&client, &segment_payloads, &expected)?
+            }
+            None => {
+                let (pk, vk) = client.setup(SANTA_ELF);
+
+                let mut stdin = SP1Stdin::new();
+                stdin.write(&payload);
+
+                let proof = client
+                    .prove(&pk, &stdin)
+                    .run()
+                    .expect("failed to generate proof");
+                client.verify(&proof, &vk).expect("failed to verify proof");
+
+                let public_values =
+                    SantaPublicValues::abi_decode(proof.public_values.as_slice(), true)?;
+                eyre::ensure!(
+                    public_values == expected,
+                    "Proven output {:?} differs from native validation {:?}",
+                    public_values,
+                    expected
+                );
+                println!("Successfully generated and verified proof!");
+            }
+        }
     }
-    //else {
-    //     // Setup the program for proving.
-    //     let (pk, vk) = client.setup(FIBONACCI_ELF);
-
-    //     // Generate the proof
-    //     let proof = client
-    //         .prove(&pk, &stdin)
-    //         .run()
-    //         .expect("failed to generate proof");
-
-    //     println!("Successfully generated proof!");
-
-    //     // Verify the proof.
-    //     client.verify(&proof, &vk).expect("failed to verify proof");
-    //     println!("Successfully verified proof!");
-    // }
+
     info!("Done, shutting off");
 
     Ok(())
 }
+
+/// Proves every segment with the Santa program and then proves the aggregation of those proofs.
+fn prove_segments_and_aggregate(
+    client: &EnvProver,
+    segment_payloads: &[Payload],
+    expected: &SantaPublicValues,
+) -> eyre::Result<()> {
+    let (santa_pk, santa_vk) = client.setup(SANTA_ELF);
+    let (aggregator_pk, aggregator_vk) = client.setup(AGGREGATOR_ELF);
+
+    let mut segment_proofs = Vec::with_capacity(segment_payloads.len());
+    let mut segments = Vec::with_capacity(segment_payloads.len());
+    for (i, payload) in segment_payloads.iter().enumerate() {
+        info!("Proving segment {} / {}", i + 1, segment_payloads.len());
+
+        let mut stdin = SP1Stdin::new();
+        stdin.write(payload);
+
+        let proof = client
+            .prove(&santa_pk, &stdin)
+            .compressed()
+            .run()
+            .expect("failed to generate segment proof");
+
+        segments.push(SantaPublicValues::abi_decode(
+            proof.public_values.as_slice(),
+            true,
+        )?);
+        segment_proofs.push(proof);
+    }
+
+    // Check linkage natively before proving the aggregation.
+    let segment_vkey = santa_vk.hash_u32();
+    let expected_aggregate = aggregate_proven_segments(&segment_vkey, &segments)?;
+    eyre::ensure!(
+        &expected_aggregate.combined == expected,
+        "Aggregated segments {:?} differ from validation of entire range {:?}",
+        expected_aggregate.combined,
+        expected
+    );
+
+    info!("Proving aggregation of {} segments", segments.len());
+
+    let mut stdin = SP1Stdin::new();
+    stdin.write(&segment_vkey);
+    stdin.write(
+        &segment_proofs
+            .iter()
+            .map(|proof| proof.public_values.to_vec())
+            .collect::<Vec<_>>(),
+    );
+    for proof in segment_proofs {
+        let SP1Proof::Compressed(proof) = proof.proof else {
+            unreachable!("segments are proven as compressed proofs")
+        };
+        stdin.write_proof(*proof, santa_vk.vk.clone());
+    }
+
+    let proof = client
+        .prove(&aggregator_pk, &stdin)
+        .run()
+        .expect("failed to generate aggregation proof");
+    client
+        .verify(&proof, &aggregator_vk)
+        .expect("failed to verify aggregation proof");
+
+    let aggregate = SantaAggregatePublicValues::abi_decode(proof.public_values.as_slice(), true)?;
+    eyre::ensure!(
+        aggregate == expected_aggregate,
+        "Aggregation output {:?} differs from native aggregation {:?}",
+        aggregate,
+        expected_aggregate
+    );
+    println!(
+        "Successfully proved blocks #{}-#{} in {} segments!",
+        aggregate.combined.first_block_number,
+        aggregate.combined.last_block_number,
+        segment_payloads.len()
+    );
+
+    Ok(())
+}
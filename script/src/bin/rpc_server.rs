@@ -0,0 +1,64 @@
+use alloy_provider::ProviderBuilder;
+use clap::{Parser, Subcommand};
+use santa_lib::{cache::StoreKind, Cache};
+use santa_script::rpc_server::{Fixture, RpcServer};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about = "Serves JSON-RPC in place of a node", long_about = None)]
+struct Args {
+    #[clap(long, default_value = "127.0.0.1:8545")]
+    addr: SocketAddr,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Answers from the cached blocks, receipts and transactions.
+    Cache {
+        #[clap(long, default_value = ".cache/store")]
+        path: PathBuf,
+
+        #[clap(long, default_value = "file", help = "cache backend: file or kv")]
+        store: StoreKind,
+    },
+    /// Forwards requests to a node over HTTP and appends its responses to a fixture file.
+    Record {
+        #[clap(long)]
+        rpc_url: String,
+
+        #[clap(long)]
+        fixture: PathBuf,
+    },
+    /// Answers with the responses recorded in a fixture file.
+    Replay {
+        #[clap(long)]
+        fixture: PathBuf,
+    },
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    sp1_sdk::utils::setup_logger();
+    let args = Args::parse();
+
+    let server = match args.command {
+        Command::Cache { path, store } => RpcServer::from_cache(Cache::new(store.open(&path))),
+        Command::Record { rpc_url, fixture } => {
+            let upstream = Box::new(ProviderBuilder::new().on_http(rpc_url.parse()?));
+            info!("Recording responses of {} to {:?}", rpc_url, fixture);
+            RpcServer::record(upstream, fixture)?
+        }
+        Command::Replay { fixture } => RpcServer::replay(Fixture::load(&fixture)?),
+    };
+
+    let listener = TcpListener::bind(args.addr).await?;
+    info!("Serving JSON-RPC on http://{}", listener.local_addr()?);
+    server.serve(listener).await?;
+    Ok(())
+}
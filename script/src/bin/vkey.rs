@@ -1,10 +1,19 @@
+use santa_lib::aggregation::vkey_digest_to_bytes;
 use sp1_sdk::{include_elf, HashableKey, Prover, ProverClient};
 
 /// The ELF (executable and linkable format) file for the Succinct RISC-V zkVM.
-pub const FIBONACCI_ELF: &[u8] = include_elf!("santa-program");
+pub const SANTA_ELF: &[u8] = include_elf!("santa-program");
+pub const AGGREGATOR_ELF: &[u8] = include_elf!("santa-aggregator");
 
 fn main() {
     let prover = ProverClient::builder().cpu().build();
-    let (_, vk) = prover.setup(FIBONACCI_ELF);
+    let (_, vk) = prover.setup(SANTA_ELF);
     println!("{}", vk.bytes32());
+
+    let (_, aggregator_vk) = prover.setup(AGGREGATOR_ELF);
+    println!("aggregator: {}", aggregator_vk.bytes32());
+    println!(
+        "aggregator segment_vkey: {}",
+        vkey_digest_to_bytes(&vk.hash_u32())
+    );
 }
@@ -0,0 +1,485 @@
+//! Fetching of blocks, receipts and transactions over RPC.
+//!
+//! Requests run with bounded concurrency and an optional limit on the rate they're started at.
+//! Each request times out on its own and is retried with exponential backoff, so that a transient
+//! RPC error doesn't abort a long run. Results are yielded in request order to be cached as they
+//! arrive, a run that fails anyway resumes from what the cache holds by fetching only what's
+//! [`missing_blocks`], [`missing_receipts`] and [`missing_transactions`].
+//!
+//! Receipts are fetched per block with `eth_getBlockReceipts`, falling back to one request per
+//! transaction on nodes that don't support it, and are checked against the block's receipts root.
+//! Transactions are fetched per block with `eth_getBlockByNumber` and checked against the block's
+//! transactions root.
+
+use alloy_consensus::proofs::{calculate_receipt_root, calculate_transaction_root};
+use alloy_consensus::{ReceiptEnvelope, TxEnvelope};
+use alloy_primitives::{BlockNumber, B256};
+use alloy_provider::transport::TransportError;
+use alloy_provider::Provider;
+use futures::{Stream, StreamExt, TryStreamExt};
+use santa_lib::cache::BlockStore;
+use santa_lib::{Cache, SmolBlock};
+use std::future::Future;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::Instant;
+use tracing::warn;
+
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    /// Maximum number of requests in flight.
+    pub concurrency: usize,
+    /// Maximum number of requests started per second, unlimited if `None`.
+    pub requests_per_second: Option<u32>,
+    /// Time after which an attempt of a request is abandoned.
+    pub timeout: Duration,
+    /// Number of times a failed request is retried.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further one up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 16,
+            requests_per_second: None,
+            timeout: Duration::from_secs(30),
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Item requested from the RPC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    Head,
+    Block(BlockNumber),
+    BlockReceipts(BlockNumber),
+    BlockTransactions(BlockNumber),
+    Receipt(B256),
+    Transaction(B256),
+}
+
+impl std::fmt::Display for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Head => write!(f, "chain head"),
+            Self::Block(number) => write!(f, "block #{}", number),
+            Self::BlockReceipts(number) => write!(f, "receipts of block #{}", number),
+            Self::BlockTransactions(number) => write!(f, "transactions of block #{}", number),
+            Self::Receipt(hash) => write!(f, "receipt of {}", hash),
+            Self::Transaction(hash) => write!(f, "transaction {}", hash),
+        }
+    }
+}
+
+/// Failure of the last attempt of a request.
+#[derive(Debug)]
+pub enum FetchError {
+    Rpc {
+        request: Request,
+        attempts: u32,
+        error: TransportError,
+    },
+    Timeout {
+        request: Request,
+        attempts: u32,
+    },
+    /// The RPC doesn't know the requested item, such as a block past the chain head.
+    NotFound {
+        request: Request,
+        attempts: u32,
+    },
+    /// The node doesn't implement the method of the request, which isn't retried.
+    Unsupported {
+        request: Request,
+    },
+    /// The receipts of a block don't match its header.
+    ReceiptsRootMismatch {
+        number: BlockNumber,
+        computed: B256,
+        expected: B256,
+    },
+    /// The transactions of a block don't match its header.
+    TransactionsRootMismatch {
+        number: BlockNumber,
+        computed: B256,
+        expected: B256,
+    },
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rpc {
+                request,
+                attempts,
+                error,
+            } => write!(
+                f,
+                "fetching {} failed after {} attempts: {}",
+                request, attempts, error
+            ),
+            Self::Timeout { request, attempts } => {
+                write!(f, "fetching {} timed out {} times", request, attempts)
+            }
+            Self::NotFound { request, attempts } => {
+                write!(f, "{} not found after {} attempts", request, attempts)
+            }
+            Self::Unsupported { request } => {
+                write!(f, "fetching {} is not supported by the node", request)
+            }
+            Self::ReceiptsRootMismatch {
+                number,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "fetched receipts of block #{} have root {}, expected {}",
+                number, computed, expected
+            ),
+            Self::TransactionsRootMismatch {
+                number,
+                computed,
+                expected,
+            } => write!(
+                f,
+                "fetched transactions of block #{} have root {}, expected {}",
+                number, computed, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Rpc { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Spaces out the starts of requests to stay within a request rate.
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: Option<u32>) -> Self {
+        Self {
+            interval: requests_per_second.map(|rate| Duration::from_secs(1) / rate.max(1)),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the next free slot.
+    async fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let start = {
+            let mut next = self.next.lock().await;
+            let start = (*next).max(Instant::now());
+            *next = start + interval;
+            start
+        };
+        tokio::time::sleep_until(start).await;
+    }
+}
+
+/// Whether the node rejected a request because it doesn't implement the method.
+fn is_unsupported(error: &TransportError) -> bool {
+    // Method not found, or method not supported as defined by EIP-1474.
+    error
+        .as_error_resp()
+        .is_some_and(|payload| matches!(payload.code, -32601 | -32004))
+}
+
+pub struct Fetcher<'a> {
+    provider: &'a dyn Provider,
+    config: FetchConfig,
+    limiter: RateLimiter,
+    /// Bounds the requests in flight, also when streams issue several requests per item.
+    permits: Semaphore,
+    /// Cleared once the node turns out not to support `eth_getBlockReceipts`.
+    block_receipts_supported: AtomicBool,
+}
+
+impl<'a> Fetcher<'a> {
+    pub fn new(provider: &'a dyn Provider, config: FetchConfig) -> Self {
+        let limiter = RateLimiter::new(config.requests_per_second);
+        let permits = Semaphore::new(config.concurrency.max(1));
+        Self {
+            provider,
+            config,
+            limiter,
+            permits,
+            block_receipts_supported: AtomicBool::new(true),
+        }
+    }
+
+    /// Sends the request made by `send` until it succeeds or runs out of retries.
+    async fn request<T, F, Fut>(&self, request: Request, send: F) -> Result<T, FetchError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Option<T>, TransportError>>,
+    {
+        let mut backoff = self.config.initial_backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = {
+                let _permit = self
+                    .permits
+                    .acquire()
+                    .await
+                    .expect("semaphore is never closed");
+                self.limiter.wait().await;
+                tokio::time::timeout(self.config.timeout, send()).await
+            };
+            let error = match result {
+                Ok(Ok(Some(value))) => return Ok(value),
+                Ok(Ok(None)) => FetchError::NotFound { request, attempts },
+                Ok(Err(error)) if is_unsupported(&error) => {
+                    return Err(FetchError::Unsupported { request })
+                }
+                Ok(Err(error)) => FetchError::Rpc {
+                    request,
+                    attempts,
+                    error,
+                },
+                Err(_) => FetchError::Timeout { request, attempts },
+            };
+            if attempts > self.config.max_retries {
+                return Err(error);
+            }
+            warn!("{}, retrying in {:?}", error, backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.config.max_backoff);
+        }
+    }
+
+    pub async fn head(&self) -> Result<BlockNumber, FetchError> {
+        self.request(Request::Head, || async move {
+            self.provider.get_block_number().await.map(Some)
+        })
+        .await
+    }
+
+    pub async fn block(&self, bn: BlockNumber) -> Result<SmolBlock, FetchError> {
+        let block = self
+            .request(Request::Block(bn), || async move {
+                self.provider
+                    .get_block_by_number(bn.into(), false.into())
+                    .await
+            })
+            .await?;
+        let txs = block
+            .transactions
+            .as_hashes()
+            .map_or_else(Vec::new, Vec::from);
+        Ok(SmolBlock::new(block.header.into(), txs))
+    }
+
+    pub async fn receipt(&self, hash: B256) -> Result<ReceiptEnvelope, FetchError> {
+        let receipt = self
+            .request(Request::Receipt(hash), || async move {
+                self.provider.get_transaction_receipt(hash).await
+            })
+            .await?;
+        Ok(receipt.into_primitives_receipt().into_inner())
+    }
+
+    /// Fetches the receipts of `block` with a single `eth_getBlockReceipts` request, or with one
+    /// request per transaction if the node doesn't support it, and checks them against the block's
+    /// receipts root.
+    pub async fn block_receipts(
+        &self,
+        block: &SmolBlock,
+    ) -> Result<Vec<ReceiptEnvelope>, FetchError> {
+        let bn = block.number;
+        let mut receipts = None;
+        if !block.txs.is_empty() && self.block_receipts_supported.load(Ordering::Relaxed) {
+            let result = self
+                .request(Request::BlockReceipts(bn), || async move {
+                    self.provider.get_block_receipts(bn.into()).await
+                })
+                .await;
+            match result {
+                Ok(block_receipts) => {
+                    receipts = Some(
+                        block_receipts
+                            .into_iter()
+                            .map(|receipt| receipt.into_primitives_receipt().into_inner())
+                            .collect(),
+                    )
+                }
+                Err(FetchError::Unsupported { .. }) => {
+                    if self.block_receipts_supported.swap(false, Ordering::Relaxed) {
+                        warn!(
+                            "eth_getBlockReceipts not supported, fetching receipts per transaction"
+                        );
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let receipts = match receipts {
+            Some(receipts) => receipts,
+            None => {
+                futures::stream::iter(&block.txs)
+                    .map(|&hash| self.receipt(hash))
+                    .buffered(self.config.concurrency)
+                    .try_collect::<Vec<_>>()
+                    .await?
+            }
+        };
+
+        let computed = calculate_receipt_root(&receipts);
+        if computed != block.receipts_root {
+            return Err(FetchError::ReceiptsRootMismatch {
+                number: bn,
+                computed,
+                expected: block.receipts_root,
+            });
+        }
+        Ok(receipts)
+    }
+
+    /// Fetches the transactions of `block` with a single `eth_getBlockByNumber` request and checks
+    /// them against the block's transactions root.
+    pub async fn block_transactions(
+        &self,
+        block: &SmolBlock,
+    ) -> Result<Vec<TxEnvelope>, FetchError> {
+        let bn = block.number;
+        let mut transactions = Vec::new();
+        if !block.txs.is_empty() {
+            let full_block = self
+                .request(Request::BlockTransactions(bn), || async move {
+                    self.provider
+                        .get_block_by_number(bn.into(), true.into())
+                        .await
+                })
+                .await?;
+            transactions = full_block
+                .transactions
+                .into_transactions()
+                .map(|transaction| transaction.inner)
+                .collect();
+        }
+
+        let computed = calculate_transaction_root(&transactions);
+        if computed != block.transactions_root {
+            return Err(FetchError::TransactionsRootMismatch {
+                number: bn,
+                computed,
+                expected: block.transactions_root,
+            });
+        }
+        Ok(transactions)
+    }
+
+    pub async fn transaction(&self, hash: B256) -> Result<TxEnvelope, FetchError> {
+        let transaction = self
+            .request(Request::Transaction(hash), || async move {
+                self.provider.get_transaction_by_hash(hash).await
+            })
+            .await?;
+        Ok(transaction.inner)
+    }
+
+    /// Fetches the blocks `numbers`, yielding them in order.
+    pub fn blocks(
+        &self,
+        numbers: Vec<BlockNumber>,
+    ) -> impl Stream<Item = Result<SmolBlock, FetchError>> + '_ {
+        futures::stream::iter(numbers)
+            .map(move |bn| self.block(bn))
+            .buffered(self.config.concurrency)
+    }
+
+    /// Fetches the checked receipts of `blocks`, yielding them in order with the block number.
+    pub fn receipts(
+        &self,
+        blocks: Vec<SmolBlock>,
+    ) -> impl Stream<Item = Result<(BlockNumber, Vec<ReceiptEnvelope>), FetchError>> + '_ {
+        futures::stream::iter(blocks)
+            .map(move |block| async move {
+                let receipts = self.block_receipts(&block).await?;
+                Ok((block.number, receipts))
+            })
+            .buffered(self.config.concurrency)
+    }
+
+    /// Fetches the checked transactions of `blocks`, yielding them in order with the block number.
+    pub fn transactions(
+        &self,
+        blocks: Vec<SmolBlock>,
+    ) -> impl Stream<Item = Result<(BlockNumber, Vec<TxEnvelope>), FetchError>> + '_ {
+        futures::stream::iter(blocks)
+            .map(move |block| async move {
+                let transactions = self.block_transactions(&block).await?;
+                Ok((block.number, transactions))
+            })
+            .buffered(self.config.concurrency)
+    }
+}
+
+/// Blocks of `range` that aren't cached as confirmed. Unconfirmed blocks are fetched again to
+/// detect reorgs.
+pub fn missing_blocks<S: BlockStore>(
+    cache: &Cache<S>,
+    range: Range<BlockNumber>,
+) -> Vec<BlockNumber> {
+    range.filter(|&bn| !cache.is_confirmed(bn)).collect()
+}
+
+/// Cached `blocks` whose receipts aren't cached in full. Receipts are fetched for whole blocks, so
+/// ones cached in part are fetched again.
+pub fn missing_receipts<S: BlockStore>(cache: &Cache<S>, blocks: &[BlockNumber]) -> Vec<SmolBlock> {
+    blocks
+        .iter()
+        .map(|&bn| cached_block(cache, bn))
+        .filter(|block| {
+            cache
+                .get_receipts(block.number)
+                .map(|receipts| receipts.len())
+                != Some(block.txs.len())
+        })
+        .collect()
+}
+
+/// Cached `blocks` whose transactions aren't cached in full. Transactions are fetched for whole
+/// blocks, so ones cached in part are fetched again.
+pub fn missing_transactions<S: BlockStore>(
+    cache: &Cache<S>,
+    blocks: &[BlockNumber],
+) -> Vec<SmolBlock> {
+    blocks
+        .iter()
+        .map(|&bn| cached_block(cache, bn))
+        .filter(|block| {
+            cache
+                .get_transactions(block.number)
+                .map(|transactions| transactions.len())
+                != Some(block.txs.len())
+        })
+        .collect()
+}
+
+fn cached_block<S: BlockStore>(cache: &Cache<S>, bn: BlockNumber) -> SmolBlock {
+    cache
+        .get_block(bn)
+        .unwrap_or_else(|| panic!("Block #{} isn't cached", bn))
+        .into_owned()
+}
@@ -0,0 +1,4 @@
+//! Host-side code shared by the script binaries.
+
+pub mod fetcher;
+pub mod rpc_server;
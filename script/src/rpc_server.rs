@@ -0,0 +1,518 @@
+//! JSON-RPC server standing in for a node, to run the script end-to-end without one.
+//!
+//! It answers either from the block cache, or with responses recorded from a real node into a
+//! fixture file of one JSON exchange per line. Only the methods the script relies on are answered
+//! from the cache:
+//! `eth_blockNumber`, `eth_getBlockByNumber`, `eth_getBlockReceipts`, `eth_getTransactionByHash`,
+//! `eth_getTransactionReceipt` and `eth_getLogs`. The cache doesn't hold transaction senders, so
+//! `from` is always the zero address, and blocks only come with full transactions if all of them are
+//! cached.
+
+use alloy_consensus::Transaction as _;
+use alloy_eips::{BlockId, BlockNumberOrTag};
+use alloy_primitives::{Address, BlockNumber, B256, U64};
+use alloy_provider::Provider;
+use alloy_rpc_types_eth::{
+    Block, BlockTransactions, Filter, FilterBlockOption, FilteredParams, Header, Log, Transaction,
+    TransactionReceipt,
+};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use santa_lib::cache::BlockStore;
+use santa_lib::Cache;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+/// Standard JSON-RPC error codes.
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Generic server error, for data the server doesn't have.
+const SERVER_ERROR: i64 = -32000;
+
+fn error(code: i64, message: impl std::fmt::Display) -> Value {
+    json!({ "code": code, "message": message.to_string() })
+}
+
+/// Response of a recorded request, either a result or a JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Result(Value),
+    Error(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    pub params: Value,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl Exchange {
+    /// Writes the exchange as a line of JSON.
+    pub fn write_line(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        out.write_all(&line)
+    }
+}
+
+/// Requests recorded from a node together with its responses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fixture {
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Fixture {
+    /// Loads a fixture of one exchange per line, later exchanges replacing earlier ones of the
+    /// same request.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut fixture = Self::default();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                fixture.insert(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(fixture)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        for exchange in &self.exchanges {
+            exchange.write_line(&mut file)?;
+        }
+        Ok(())
+    }
+
+    /// Response recorded for `method` called with `params`.
+    pub fn find(&self, method: &str, params: &Value) -> Option<&Outcome> {
+        self.exchanges
+            .iter()
+            .find(|exchange| exchange.method == method && &exchange.params == params)
+            .map(|exchange| &exchange.outcome)
+    }
+
+    /// Records an exchange, replacing an earlier one of the same request.
+    pub fn insert(&mut self, exchange: Exchange) {
+        match self.exchanges.iter_mut().find(|recorded| {
+            recorded.method == exchange.method && recorded.params == exchange.params
+        }) {
+            Some(recorded) => *recorded = exchange,
+            None => self.exchanges.push(exchange),
+        }
+    }
+}
+
+/// Hashes of the cached blocks and transactions, indexed when the server starts.
+#[derive(Default)]
+struct CacheIndex {
+    head: BlockNumber,
+    blocks_by_hash: HashMap<B256, BlockNumber>,
+    /// Block number and index of every cached transaction hash.
+    txs: HashMap<B256, (BlockNumber, usize)>,
+}
+
+/// Cached data as seen by a single request.
+struct CacheView<'a> {
+    cache: &'a Cache<Box<dyn BlockStore + Send>>,
+    index: &'a CacheIndex,
+}
+
+enum Backend {
+    Cache {
+        cache: std::sync::Mutex<Cache<Box<dyn BlockStore + Send>>>,
+        index: CacheIndex,
+    },
+    Record {
+        upstream: Box<dyn Provider>,
+        fixture: Mutex<File>,
+    },
+    Replay(Fixture),
+}
+
+pub struct RpcServer {
+    backend: Backend,
+}
+
+#[derive(Deserialize)]
+struct Call {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+impl RpcServer {
+    /// Answers from the blocks, receipts and transactions of `cache`, reading them from its store
+    /// as they're requested. The highest cached block is reported as the chain head.
+    pub fn from_cache<S: BlockStore + Send + 'static>(cache: Cache<S>) -> Self {
+        let mut index = CacheIndex::default();
+        for bn in cache.store().block_numbers(0) {
+            let block = cache.get_block(bn).unwrap();
+            index.head = bn;
+            index.blocks_by_hash.insert(block.hash_slow(), bn);
+            for (i, &hash) in block.txs.iter().enumerate() {
+                index.txs.insert(hash, (bn, i));
+            }
+        }
+        let store: Box<dyn BlockStore + Send> = Box::new(cache.into_store());
+        Self {
+            backend: Backend::Cache {
+                cache: std::sync::Mutex::new(Cache::new(store)),
+                index,
+            },
+        }
+    }
+
+    /// Forwards requests to `upstream` and appends its responses to the fixture at `path`, which
+    /// is created if it doesn't exist. Requests failing without a response from the node aren't
+    /// recorded.
+    pub fn record(upstream: Box<dyn Provider>, path: PathBuf) -> std::io::Result<Self> {
+        let fixture = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            backend: Backend::Record {
+                upstream,
+                fixture: Mutex::new(fixture),
+            },
+        })
+    }
+
+    /// Answers with the responses recorded in `fixture`.
+    pub fn replay(fixture: Fixture) -> Self {
+        Self {
+            backend: Backend::Replay(fixture),
+        }
+    }
+
+    /// Serves JSON-RPC over HTTP on `listener` until the process exits.
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        let app = Router::new()
+            .route("/", post(handle_http))
+            .with_state(Arc::new(self));
+        axum::serve(listener, app).await
+    }
+
+    /// Answers a JSON-RPC request or batch of requests.
+    pub async fn handle(&self, request: Value) -> Value {
+        match request {
+            Value::Array(batch) => {
+                let responses =
+                    futures::future::join_all(batch.into_iter().map(|call| self.handle_call(call)));
+                Value::Array(responses.await)
+            }
+            call => self.handle_call(call).await,
+        }
+    }
+
+    async fn handle_call(&self, call: Value) -> Value {
+        let call = match serde_json::from_value::<Call>(call) {
+            Ok(call) => call,
+            Err(err) => return response(Value::Null, Err(error(INVALID_REQUEST, err))),
+        };
+        let outcome = match &self.backend {
+            Backend::Cache { cache, index } => {
+                let cache = cache.lock().unwrap();
+                let view = CacheView {
+                    cache: &cache,
+                    index,
+                };
+                view.answer(&call.method, call.params)
+            }
+            Backend::Record { upstream, fixture } => {
+                let outcome = match forward(&**upstream, &call).await {
+                    Ok(outcome) => outcome,
+                    Err(err) => return response(call.id, Err(err)),
+                };
+                let exchange = Exchange {
+                    method: call.method,
+                    params: call.params,
+                    outcome: outcome.clone(),
+                };
+                if let Err(err) = exchange.write_line(&mut *fixture.lock().await) {
+                    return response(
+                        call.id,
+                        Err(error(INTERNAL_ERROR, format!("writing fixture: {}", err))),
+                    );
+                }
+                match outcome {
+                    Outcome::Result(result) => Ok(result),
+                    Outcome::Error(error) => Err(error),
+                }
+            }
+            Backend::Replay(fixture) => match fixture.find(&call.method, &call.params) {
+                Some(Outcome::Result(result)) => Ok(result.clone()),
+                Some(Outcome::Error(error)) => Err(error.clone()),
+                None => Err(error(
+                    SERVER_ERROR,
+                    format!("no recorded response to {}", call.method),
+                )),
+            },
+        };
+        response(call.id, outcome)
+    }
+}
+
+async fn handle_http(
+    State(server): State<Arc<RpcServer>>,
+    Json(request): Json<Value>,
+) -> Json<Value> {
+    Json(server.handle(request).await)
+}
+
+fn response(id: Value, outcome: Result<Value, Value>) -> Value {
+    match outcome {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+/// Sends `call` to `upstream`, failing if there's no response from the node.
+async fn forward(upstream: &dyn Provider, call: &Call) -> Result<Outcome, Value> {
+    let params =
+        serde_json::value::to_raw_value(&call.params).map_err(|err| error(INVALID_PARAMS, err))?;
+    match upstream
+        .raw_request_dyn(call.method.clone().into(), &params)
+        .await
+    {
+        Ok(result) => serde_json::from_str(result.get())
+            .map(Outcome::Result)
+            .map_err(|err| error(INTERNAL_ERROR, err)),
+        Err(err) => match err.as_error_resp() {
+            Some(payload) => serde_json::to_value(payload)
+                .map(Outcome::Error)
+                .map_err(|err| error(INTERNAL_ERROR, err)),
+            None => Err(error(INTERNAL_ERROR, format!("upstream failed: {}", err))),
+        },
+    }
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, Value> {
+    serde_json::from_value(params).map_err(|err| error(INVALID_PARAMS, err))
+}
+
+fn to_result(value: impl Serialize) -> Result<Value, Value> {
+    serde_json::to_value(value).map_err(|err| error(INTERNAL_ERROR, err))
+}
+
+impl CacheView<'_> {
+    fn answer(&self, method: &str, params_value: Value) -> Result<Value, Value> {
+        match method {
+            "eth_blockNumber" => to_result(U64::from(self.index.head)),
+            "eth_getBlockByNumber" => {
+                let (number, full): (BlockNumberOrTag, bool) = params(params_value)?;
+                to_result(self.block(self.resolve(number), full)?)
+            }
+            "eth_getBlockReceipts" => {
+                let (block,): (BlockId,) = params(params_value)?;
+                let bn = match block {
+                    BlockId::Number(number) => Some(self.resolve(number)),
+                    BlockId::Hash(hash) => self.index.blocks_by_hash.get(&hash.block_hash).copied(),
+                };
+                let receipts = match bn {
+                    Some(bn) => self.block_receipts(bn)?,
+                    None => None,
+                };
+                to_result(receipts)
+            }
+            "eth_getTransactionByHash" => {
+                let (hash,): (B256,) = params(params_value)?;
+                to_result(self.transaction(hash))
+            }
+            "eth_getTransactionReceipt" => {
+                let (hash,): (B256,) = params(params_value)?;
+                to_result(self.receipt(hash)?)
+            }
+            "eth_getLogs" => {
+                let (filter,): (Filter,) = params(params_value)?;
+                to_result(self.logs(filter)?)
+            }
+            _ => Err(error(
+                METHOD_NOT_FOUND,
+                format!("method {} not supported", method),
+            )),
+        }
+    }
+
+    fn resolve(&self, number: BlockNumberOrTag) -> BlockNumber {
+        match number {
+            BlockNumberOrTag::Number(number) => number,
+            BlockNumberOrTag::Earliest => 0,
+            _ => self.index.head,
+        }
+    }
+
+    /// Block `bn` with its transaction hashes, or with its transactions if `full`. Fails if `full`
+    /// and its transactions aren't all cached.
+    fn block(&self, bn: BlockNumber, full: bool) -> Result<Option<Block>, Value> {
+        let Some(block) = self.cache.get_block(bn) else {
+            return Ok(None);
+        };
+        let transactions = if full {
+            let transactions = block
+                .txs
+                .iter()
+                .map(|&hash| self.transaction(hash))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    error(
+                        INTERNAL_ERROR,
+                        format!("transactions of block #{} aren't cached", bn),
+                    )
+                })?;
+            BlockTransactions::Full(transactions)
+        } else {
+            BlockTransactions::Hashes(block.txs.clone())
+        };
+        Ok(Some(Block {
+            header: Header::new(block.header.clone()),
+            uncles: Vec::new(),
+            transactions,
+            withdrawals: None,
+        }))
+    }
+
+    /// RPC receipts of the cached receipts of block `bn`, complete or not. Fails if the cumulative
+    /// gas used decreases between receipts, leaving the gas used by a transaction undefined.
+    fn rpc_receipts(&self, bn: BlockNumber) -> Result<Option<Vec<TransactionReceipt>>, Value> {
+        let (Some(block), Some(receipts)) = (self.cache.get_block(bn), self.cache.get_receipts(bn))
+        else {
+            return Ok(None);
+        };
+        let transactions = self.cache.get_transactions(bn);
+        let block_hash = block.hash_slow();
+
+        let mut log_index = 0;
+        let mut cumulative_gas_used = 0;
+        let mut rpc_receipts = Vec::with_capacity(receipts.len());
+        for (index, (receipt, &hash)) in receipts.iter().zip(&block.txs).enumerate() {
+            let tx = transactions.as_ref().and_then(|txs| txs.get(index));
+            let gas_used = receipt
+                .cumulative_gas_used()
+                .checked_sub(cumulative_gas_used)
+                .ok_or_else(|| {
+                    error(
+                        INTERNAL_ERROR,
+                        format!(
+                            "cached receipt #{} of block #{} has less cumulative gas used than \
+                             the receipt before it",
+                            index, bn
+                        ),
+                    )
+                })?;
+            cumulative_gas_used = receipt.cumulative_gas_used();
+            let inner = receipt.clone().map_logs(|inner| {
+                log_index += 1;
+                Log {
+                    inner,
+                    block_hash: Some(block_hash),
+                    block_number: Some(bn),
+                    block_timestamp: Some(block.timestamp),
+                    transaction_hash: Some(hash),
+                    transaction_index: Some(index as u64),
+                    log_index: Some(log_index - 1),
+                    removed: false,
+                }
+            });
+            rpc_receipts.push(TransactionReceipt {
+                inner,
+                transaction_hash: hash,
+                transaction_index: Some(index as u64),
+                block_hash: Some(block_hash),
+                block_number: Some(bn),
+                gas_used,
+                effective_gas_price: tx
+                    .map_or(0, |tx| tx.effective_gas_price(block.base_fee_per_gas)),
+                blob_gas_used: None,
+                blob_gas_price: None,
+                from: Address::ZERO,
+                to: tx.and_then(|tx| tx.to()),
+                contract_address: None,
+            });
+        }
+        Ok(Some(rpc_receipts))
+    }
+
+    /// Receipts of block `bn` if all of them are cached.
+    fn block_receipts(&self, bn: BlockNumber) -> Result<Option<Vec<TransactionReceipt>>, Value> {
+        let Some(block) = self.cache.get_block(bn) else {
+            return Ok(None);
+        };
+        Ok(self
+            .rpc_receipts(bn)?
+            .filter(|receipts| receipts.len() == block.txs.len()))
+    }
+
+    fn receipt(&self, hash: B256) -> Result<Option<TransactionReceipt>, Value> {
+        let Some(&(bn, index)) = self.index.txs.get(&hash) else {
+            return Ok(None);
+        };
+        Ok(self
+            .rpc_receipts(bn)?
+            .and_then(|receipts| receipts.into_iter().nth(index)))
+    }
+
+    fn transaction(&self, hash: B256) -> Option<Transaction> {
+        let &(bn, index) = self.index.txs.get(&hash)?;
+        let block = self.cache.get_block(bn)?;
+        let tx = self.cache.get_transactions(bn)?.get(index)?.clone();
+        Some(Transaction {
+            effective_gas_price: Some(tx.effective_gas_price(block.base_fee_per_gas)),
+            inner: tx,
+            block_hash: Some(block.hash_slow()),
+            block_number: Some(bn),
+            transaction_index: Some(index as u64),
+            from: Address::ZERO,
+        })
+    }
+
+    /// Logs matching `filter`, failing if the receipts of a block in its range aren't cached.
+    fn logs(&self, filter: Filter) -> Result<Vec<Log>, Value> {
+        let range = match filter.block_option {
+            FilterBlockOption::Range {
+                from_block,
+                to_block,
+            } => {
+                let from = self.resolve(from_block.unwrap_or_default());
+                let to = self.resolve(to_block.unwrap_or_default());
+                from..=to.min(self.index.head)
+            }
+            FilterBlockOption::AtBlockHash(hash) => match self.index.blocks_by_hash.get(&hash) {
+                Some(&bn) => bn..=bn,
+                None => return Err(error(SERVER_ERROR, format!("block {} not cached", hash))),
+            },
+        };
+
+        let filter = FilteredParams::new(Some(filter));
+        let mut logs = Vec::new();
+        for bn in range {
+            let receipts = self.block_receipts(bn)?.ok_or_else(|| {
+                error(
+                    SERVER_ERROR,
+                    format!("receipts of block #{} not cached", bn),
+                )
+            })?;
+            logs.extend(
+                receipts
+                    .into_iter()
+                    .flat_map(|receipt| receipt.inner.logs().to_vec())
+                    .filter(|log| {
+                        filter.filter_address(&log.address()) && filter.filter_topics(log.topics())
+                    }),
+            );
+        }
+        Ok(logs)
+    }
+}
@@ -0,0 +1,286 @@
+//! Runs the fetcher and the santa binary against the stand-in RPC server, answering from a cache
+//! or a recorded fixture.
+
+use alloy_consensus::{
+    proofs::{calculate_receipt_root, calculate_transaction_root},
+    Eip658Value, Header, Receipt, ReceiptEnvelope, ReceiptWithBloom, SignableTransaction,
+    TxEip1559, TxEnvelope,
+};
+use alloy_primitives::{Address, Bytes, Log, PrimitiveSignature as Signature, B256};
+use alloy_provider::ProviderBuilder;
+use futures::TryStreamExt;
+use santa_lib::cache::MemoryStore;
+use santa_lib::{Cache, SmolBlock};
+use santa_script::fetcher::{FetchConfig, FetchError, Fetcher};
+use santa_script::rpc_server::{Fixture, RpcServer};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+const EMITTER: Address = Address::repeat_byte(0xee);
+const TOPIC: B256 = B256::repeat_byte(0x77);
+
+fn receipt(cumulative_gas_used: u64) -> ReceiptEnvelope {
+    ReceiptEnvelope::Eip1559(ReceiptWithBloom::from(Receipt {
+        status: Eip658Value::Eip658(true),
+        cumulative_gas_used,
+        logs: vec![Log::new_unchecked(EMITTER, vec![TOPIC], Bytes::new())],
+    }))
+}
+
+fn transaction(nonce: u64) -> TxEnvelope {
+    let tx = TxEip1559 {
+        nonce,
+        to: EMITTER.into(),
+        ..Default::default()
+    };
+    TxEnvelope::Eip1559(tx.into_signed(Signature::test_signature()))
+}
+
+/// Chain of blocks 0-3 with as many transactions as their number, each emitting one log.
+fn cache() -> Cache<MemoryStore> {
+    let mut cache = Cache::new(MemoryStore::default());
+    let mut parent_hash = B256::ZERO;
+    for number in 0..4 {
+        let receipts = (1..=number)
+            .map(|i| receipt(21_000 * i))
+            .collect::<Vec<_>>();
+        let transactions = (0..number)
+            .map(|i| transaction(number * 16 + i))
+            .collect::<Vec<_>>();
+        let header = Header {
+            number,
+            parent_hash,
+            receipts_root: calculate_receipt_root(&receipts),
+            transactions_root: calculate_transaction_root(&transactions),
+            ..Default::default()
+        };
+        parent_hash = header.hash_slow();
+        let txs = transactions.iter().map(|tx| *tx.tx_hash()).collect();
+        cache.append_blocks([SmolBlock::new(header, txs)]);
+        cache.append_receipts(number, receipts);
+        cache.append_transactions(number, transactions);
+    }
+    cache
+}
+
+/// Serves `server` on a free local port, returning its URL.
+async fn spawn(server: RpcServer) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(server.serve(listener));
+    url
+}
+
+fn call(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params })
+}
+
+#[tokio::test]
+async fn serves_cached_blocks_and_receipts() {
+    let cache = cache();
+    let url = spawn(RpcServer::from_cache(self::cache())).await;
+    let provider = ProviderBuilder::new().on_http(url.parse().unwrap());
+    let fetcher = Fetcher::new(&provider, FetchConfig::default());
+
+    assert_eq!(fetcher.head().await.unwrap(), 3);
+
+    let blocks = fetcher
+        .blocks(vec![0, 1, 2, 3])
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    for block in &blocks {
+        let cached = cache.get_block(block.number).unwrap();
+        assert_eq!(block.header, cached.header);
+        assert_eq!(block.txs, cached.txs);
+    }
+
+    // Receipts are checked against the receipts root as they're fetched.
+    let receipts = fetcher
+        .receipts(blocks.clone())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    for (bn, receipts) in receipts {
+        assert_eq!(receipts, cache.get_receipts(bn).unwrap().to_vec());
+    }
+    assert_eq!(
+        fetcher.receipt(blocks[2].txs[1]).await.unwrap(),
+        cache.get_receipts(2).unwrap()[1]
+    );
+    // So are transactions against the transactions root, fetched with their full block.
+    let transactions = fetcher
+        .transactions(blocks.clone())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    for (bn, transactions) in transactions {
+        assert_eq!(transactions, cache.get_transactions(bn).unwrap().to_vec());
+    }
+    let mut forged = blocks[2].clone();
+    forged.header.transactions_root = B256::ZERO;
+    assert!(matches!(
+        fetcher.block_transactions(&forged).await,
+        Err(FetchError::TransactionsRootMismatch { number: 2, .. })
+    ));
+    assert_eq!(
+        fetcher.transaction(blocks[3].txs[2]).await.unwrap(),
+        cache.get_transactions(3).unwrap()[2]
+    );
+    assert!(fetcher.block(4).await.is_err());
+}
+
+#[tokio::test]
+async fn filters_cached_logs() {
+    let server = RpcServer::from_cache(cache());
+    let logs = |filter: Value| {
+        let server = &server;
+        async move { server.handle(call("eth_getLogs", json!([filter]))).await["result"].clone() }
+    };
+
+    let all = logs(json!({ "fromBlock": "0x0", "toBlock": "0x3", "address": EMITTER })).await;
+    assert_eq!(all.as_array().unwrap().len(), 6);
+    assert_eq!(all[5]["blockNumber"], "0x3");
+    assert_eq!(all[5]["logIndex"], "0x2");
+
+    let other_topic = logs(json!({ "fromBlock": "0x2", "topics": [B256::ZERO] })).await;
+    assert_eq!(other_topic, json!([]));
+
+    let unsupported = server.handle(call("eth_getProof", json!([]))).await;
+    assert_eq!(unsupported["error"]["code"], -32601);
+}
+
+#[tokio::test]
+async fn serves_full_blocks_of_cached_transactions() {
+    let mut cache = cache();
+    let server = RpcServer::from_cache(self::cache());
+    let response = server
+        .handle(call("eth_getBlockByNumber", json!(["0x3", true])))
+        .await;
+    let block: alloy_rpc_types_eth::Block =
+        serde_json::from_value(response["result"].clone()).unwrap();
+    let transactions = block
+        .transactions
+        .into_transactions()
+        .map(|transaction| transaction.inner)
+        .collect::<Vec<_>>();
+    assert_eq!(transactions, cache.get_transactions(3).unwrap().to_vec());
+
+    cache.evict_transactions(3);
+    let server = RpcServer::from_cache(cache);
+    let response = server
+        .handle(call("eth_getBlockByNumber", json!(["0x3", true])))
+        .await;
+    assert_eq!(response["error"]["code"], -32603);
+    let response = server
+        .handle(call("eth_getBlockByNumber", json!(["0x3", false])))
+        .await;
+    assert_eq!(
+        response["result"]["transactions"].as_array().unwrap().len(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn replays_recorded_responses() {
+    let url = spawn(RpcServer::from_cache(cache())).await;
+    let path = std::env::temp_dir().join(format!("santa-rpc-fixture-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let upstream = Box::new(ProviderBuilder::new().on_http(url.parse().unwrap()));
+    let recorder = RpcServer::record(upstream, path.clone()).unwrap();
+    let requests = [
+        call("eth_blockNumber", json!([])),
+        call("eth_getBlockByNumber", json!(["0x2", false])),
+        call("eth_getBlockReceipts", json!(["0x2"])),
+        call("eth_getProof", json!([EMITTER, [], "latest"])),
+    ];
+    let mut recorded = Vec::new();
+    for request in &requests {
+        recorded.push(recorder.handle(request.clone()).await);
+    }
+    assert_eq!(recorded[0]["result"], "0x3");
+    assert_eq!(recorded[3]["error"]["code"], -32601);
+
+    // Every response is appended as a line, recording a request again replaces it on load.
+    drop(recorder);
+    let upstream = Box::new(ProviderBuilder::new().on_http(url.parse().unwrap()));
+    let recorder = RpcServer::record(upstream, path.clone()).unwrap();
+    assert_eq!(recorder.handle(requests[0].clone()).await, recorded[0]);
+    let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+    assert_eq!(lines, requests.len() + 1);
+
+    let fixture = Fixture::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(fixture.exchanges.len(), requests.len());
+
+    let replayer = RpcServer::replay(fixture);
+    for (request, recorded) in requests.iter().zip(recorded) {
+        assert_eq!(replayer.handle(request.clone()).await, recorded);
+    }
+    let missing = replayer
+        .handle(call("eth_getBlockByNumber", json!(["0x1", false])))
+        .await;
+    assert_eq!(missing["error"]["code"], -32000);
+}
+
+#[tokio::test]
+async fn rejects_decreasing_cumulative_gas() {
+    let mut cache = cache();
+    let mut receipts = cache.get_receipts(3).unwrap().to_vec();
+    receipts.swap(0, 2);
+    cache.evict_receipts(3);
+    cache.append_receipts(3, receipts);
+    let server = RpcServer::from_cache(cache);
+
+    let response = server
+        .handle(call("eth_getBlockReceipts", json!(["0x3"])))
+        .await;
+    assert_eq!(response["error"]["code"], -32603);
+    let tx_hash = server
+        .handle(call("eth_getBlockByNumber", json!(["0x3", false])))
+        .await["result"]["transactions"][1]
+        .clone();
+    let response = server
+        .handle(call("eth_getTransactionReceipt", json!([tx_hash])))
+        .await;
+    assert_eq!(response["error"]["code"], -32603);
+}
+
+/// Runs the santa binary on blocks 1-3 with a reward log in every block. Its cache is kept in
+/// memory, so everything is fetched from the server.
+#[tokio::test]
+async fn santa_runs_against_server() {
+    let url = spawn(RpcServer::from_cache(cache())).await;
+    let output = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_santa"))
+            .args([
+                "--rpc-url",
+                &url,
+                "--store",
+                "memory",
+                "--start",
+                "1",
+                "--end",
+                "4",
+            ])
+            .args([
+                "--log-every",
+                "1",
+                "--skip-prob",
+                "0",
+                "--confirmations",
+                "0",
+            ])
+            .output()
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "santa failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}